@@ -9,30 +9,19 @@
 
 use sim::hle::mem::*;
 use sim::hle::riscv::*;
+use sim::lle::*;
+use sim::lle::pipereg::*;
 use std::ops::{BitAnd, BitOr, BitXor, Shl, Shr};
 
-pub struct ValidReg<T> {
-    data: Option<T>,
-}
-impl <T> ValidReg<T> {
-    pub fn new_valid(init: T) -> Self { 
-        Self { data: Some(init) }
-    }
-    pub fn new_invalid() -> Self { 
-        Self { data: None }
-    }
-    pub fn read(&self) -> &Option<T> { &self.data }
-    pub fn write(&mut self, val: T) { self.data = Some(val) }
-    pub fn invalidate(&mut self) { self.data = None }
-}
-
 /// Decode stage registers
+#[derive(Clone, Copy)]
 pub struct DecoderStage { 
     data: [u8; 4],
     pc: usize,
 }
 
 /// Execute stage registers
+#[derive(Clone, Copy)]
 pub struct ExecStage {
     inst: Instr,
     pc: usize,
@@ -44,13 +33,13 @@ pub struct RegisterFile {
 }
 impl RegisterFile { 
     pub fn write(&mut self, arn: ArchReg, val: u32) {
-        if arn == ArchReg(0) { return; }
-        let idx = arn.0 as usize;
+        if arn.is_zero() { return; }
+        let idx = arn.as_usize();
         self.data[idx] = val;
     }
     pub fn read(&self, arn: ArchReg) -> u32 { 
-        if arn == ArchReg(0) { return 0; }
-        let idx = arn.0 as usize;
+        if arn.is_zero() { return 0; }
+        let idx = arn.as_usize();
         self.data[idx]
     }
     pub fn new() -> Self {
@@ -76,20 +65,20 @@ fn main() {
     let entry   = read_prog(&mut ram, "programs/test.elf") as u32;
 
     let mut cycle = 0;
-    let mut r_pc   = ValidReg::<usize>::new_valid(entry as usize);
-    let mut r_dstage = ValidReg::<DecoderStage>::new_invalid();
-    let mut r_estage = ValidReg::<ExecStage>::new_invalid();
+    let mut r_pc   = PipeReg::<usize>::new_valid(entry as usize);
+    let mut r_dstage = PipeReg::<DecoderStage>::new();
+    let mut r_estage = PipeReg::<ExecStage>::new();
 
     let mut rf = RegisterFile::new();
 
     // Initialize the stack pointer; see '__stack_top' in rv.ld
-    rf.write(ArchReg(2), RAM_SIZE as u32 - 0x1000);
+    rf.write(ArchReg::new(2), RAM_SIZE as u32 - 0x1000);
 
     loop { 
 
         // Assume the next PC is always the next sequential instruction.
         // This value may change if a branch has been taken.
-        let mut npc = r_pc.read().unwrap().wrapping_add(4);
+        let mut npc = r_pc.sample().unwrap().wrapping_add(4);
         let mut taken_branch = false;
 
         println!("================= Cycle {} ==============", cycle);
@@ -98,7 +87,7 @@ fn main() {
         // -----------------------------------------------
         // Execute stage
 
-        if let Some(estage) = r_estage.read() {
+        if let Some(estage) = r_estage.sample_ref() {
             println!("Executing @ {:08x}: {:?}", estage.pc, estage.inst);
             match estage.inst {
                 Instr::AuiPc { rd, uimm } => {
@@ -228,14 +217,14 @@ fn main() {
                 },
                 Instr::Ecall { prv } => {
                     // The syscall number is in x17 (a7)
-                    let a0 = rf.read(ArchReg(10));
-                    let a1 = rf.read(ArchReg(11));
-                    let a2 = rf.read(ArchReg(12));
-                    let a3 = rf.read(ArchReg(13));
-                    let a4 = rf.read(ArchReg(14));
-                    let a5 = rf.read(ArchReg(15));
-                    let a6 = rf.read(ArchReg(16));
-                    let a7 = rf.read(ArchReg(17));
+                    let a0 = rf.read(ArchReg::new(10));
+                    let a1 = rf.read(ArchReg::new(11));
+                    let a2 = rf.read(ArchReg::new(12));
+                    let a3 = rf.read(ArchReg::new(13));
+                    let a4 = rf.read(ArchReg::new(14));
+                    let a5 = rf.read(ArchReg::new(15));
+                    let a6 = rf.read(ArchReg::new(16));
+                    let a7 = rf.read(ArchReg::new(17));
                     let sc = RvPkSyscall::from_u32(a7);
                     
                     println!("ECALL ({:?} a0={:08x} a1={:08x}", sc, a0, a1);
@@ -243,7 +232,6 @@ fn main() {
                 },
                 _ => unimplemented!("{:?}", estage.inst),
             }
-            r_estage.invalidate();
         } else { 
             println!("[*] No instruction to execute")
         }
@@ -252,18 +240,18 @@ fn main() {
         // fetching from the target address on the next cycle. 
         if taken_branch { 
             println!("[*] Taken branch invalidated decode and fetch");
-            r_dstage.invalidate();
-            r_pc.invalidate();
+            r_estage.flush();
+            r_dstage.flush();
         }
 
         // -----------------------------------------------------------
         // Decode stage
 
-        if let Some(dstage) = r_dstage.read() {
+        if let Some(dstage) = r_dstage.sample_ref() {
             let enc = u32::from_le_bytes(dstage.data);
             let tmp = Rv32::disas(enc);
             println!("[*] Decoding  @ {:08x}: {}", dstage.pc, tmp);
-            r_estage.write(ExecStage { inst: tmp, pc: dstage.pc });
+            r_estage.drive(ExecStage { inst: tmp, pc: dstage.pc });
         } else {
             println!("[*] No instruction to decode")
         }
@@ -271,18 +259,26 @@ fn main() {
         // -----------------------------------------------------------
         // Fetch stage
 
-        if let Some(pc) = r_pc.read() {
+        if let Some(pc) = r_pc.sample() {
             let mut tmp = [0u8; 4];
-            ram.read_bytes(*pc, &mut tmp);
+            ram.read_bytes(pc, &mut tmp);
             println!("Fetching  @ {:08x}: {:x?}", pc, tmp);
-            r_dstage.write(DecoderStage { data: tmp, pc: *pc });
+            r_dstage.drive(DecoderStage { data: tmp, pc });
         } else {
             println!("[*] No address to fetch")
         }
 
-        r_pc.write(npc);
+        r_pc.drive(npc);
+        r_pc.update();
+        r_dstage.update();
+        r_estage.update();
         cycle += 1;
 
     }
+
+    println!("[*] Halted after {} cycles", cycle);
+    println!("r_pc:     {}", r_pc.stats());
+    println!("r_dstage: {}", r_dstage.stats());
+    println!("r_estage: {}", r_estage.stats());
 }
 
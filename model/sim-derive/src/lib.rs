@@ -0,0 +1,311 @@
+//! Derive macros for the `sim` crate.
+//!
+//! `#[derive(Clocked)]` implements `Clocked` for a struct by updating each
+//! of its fields (in declaration order):
+//!
+//! ```ignore
+//! #[derive(Clocked)]
+//! struct Frontend {
+//!     pc: Reg<usize>,
+//!     ftq: Queue<usize>,
+//!     // Fields which aren't clocked must be skipped
+//!     #[clocked(skip)]
+//!     name: &'static str,
+//!     // Fields with a lower 'order' are updated first (the default is 0)
+//!     #[clocked(order = -1)]
+//!     cam: AsyncReadCam<usize, usize>,
+//! }
+//! ```
+//!
+//! The generated impl also reports that the struct is dirty when any of its
+//! (non-skipped) fields are dirty.
+//!
+//! By default, the generated impl is for `::sim::lle::Clocked`. Some other
+//! trait (with the same methods) can be used instead with
+//! `#[clocked(path = "some::other::Clocked")]` on the struct.
+//!
+//! `#[derive(Resettable)]` is the same, except that it resets each field
+//! and uses `#[reset(...)]` attributes (ie. `#[reset(skip)]`). The default
+//! trait is `::sim::lle::Resettable`.
+//!
+//! `#[derive(Cost)]` is also the same: it reports the cost of each field
+//! (with the path `{path}.{field}`), and uses `#[cost(...)]` attributes.
+//!
+//! `#[derive(HwBits)]` implements `::sim::lle::cost::HwBits`. A struct is
+//! the sum of its fields, and an enum is a tag (with enough bits for each
+//! variant) and the largest variant. The width of a field always comes from
+//! its type, so narrow values should use a type like `UInt<N>`:
+//!
+//! ```ignore
+//! #[derive(HwBits)]
+//! enum Exit {
+//!     Sequential,
+//!     // The index of one of 8 instructions
+//!     Jmp(UInt<3>),
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::*;
+use syn::spanned::Spanned;
+
+/// Describes one of the derivable traits.
+struct DeriveKind {
+    /// Name of the derive macro
+    name: &'static str,
+    /// Name of the helper attribute
+    attr: &'static str,
+    /// The default path to the trait
+    path: fn() -> Path,
+    /// The method called on each field
+    method: &'static str,
+    /// A predicate which is true if it's true for any field
+    any: Option<&'static str>,
+}
+
+const CLOCKED: DeriveKind = DeriveKind {
+    name: "Clocked",
+    attr: "clocked",
+    path: || parse_quote!(::sim::lle::Clocked),
+    method: "update",
+    any: Some("is_dirty"),
+};
+
+const RESETTABLE: DeriveKind = DeriveKind {
+    name: "Resettable",
+    attr: "reset",
+    path: || parse_quote!(::sim::lle::Resettable),
+    method: "reset",
+    any: None,
+};
+
+#[proc_macro_derive(Clocked, attributes(clocked))]
+pub fn derive_clocked(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&CLOCKED, &input) {
+        Ok(res) => res.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(Resettable, attributes(reset))]
+pub fn derive_resettable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&RESETTABLE, &input) {
+        Ok(res) => res.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+const COST: DeriveKind = DeriveKind {
+    name: "Cost",
+    attr: "cost",
+    path: || parse_quote!(::sim::lle::cost::Cost),
+    method: "cost",
+    any: None,
+};
+
+#[proc_macro_derive(Cost, attributes(cost))]
+pub fn derive_cost(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_cost(&COST, &input) {
+        Ok(res) => res.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[proc_macro_derive(HwBits)]
+pub fn derive_hw_bits(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_hw_bits(&input) {
+        Ok(res) => res.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Options from `#[clocked(...)]` (or `#[reset(...)]`) on a field.
+struct FieldOpts {
+    skip: bool,
+    order: i64,
+}
+
+fn helper_attrs<'a>(kind: &'a DeriveKind, attrs: &'a [Attribute]) 
+    -> impl Iterator<Item=&'a Attribute> 
+{
+    attrs.iter().filter(|a| a.path().is_ident(kind.attr))
+}
+
+/// Parse `#[clocked(path = "...")]` on the struct.
+fn trait_path(kind: &DeriveKind, attrs: &[Attribute]) -> Result<Path> {
+    let mut res = (kind.path)();
+    for attr in helper_attrs(kind, attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("path") {
+                let lit: LitStr = meta.value()?.parse()?;
+                res = lit.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `path = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(res)
+}
+
+/// Parse `#[clocked(skip)]` and `#[clocked(order = N)]` on a field.
+fn field_opts(kind: &DeriveKind, attrs: &[Attribute]) -> Result<FieldOpts> {
+    let mut res = FieldOpts { skip: false, order: 0 };
+    for attr in helper_attrs(kind, attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                res.skip = true;
+                Ok(())
+            } else if meta.path.is_ident("order") {
+                let lit: Expr = meta.value()?.parse()?;
+                res.order = parse_order(&lit)?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip` or `order = N`"))
+            }
+        })?;
+    }
+    Ok(res)
+}
+
+/// The order is an integer literal (which may be negative).
+fn parse_order(expr: &Expr) -> Result<i64> {
+    match expr {
+        Expr::Lit(ExprLit { lit: Lit::Int(lit), .. }) => lit.base10_parse(),
+        Expr::Unary(ExprUnary { op: UnOp::Neg(_), expr, .. }) => {
+            Ok(-parse_order(expr)?)
+        },
+        _ => Err(Error::new(expr.span(), "expected an integer")),
+    }
+}
+
+/// Returns the fields of a struct which aren't skipped (sorted by order).
+fn members(kind: &DeriveKind, input: &DeriveInput) -> Result<Vec<Member>> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new(input.span(), format!(
+            "#[derive({})] is only supported on structs", kind.name))),
+    };
+    let mut members = Vec::new();
+    for (idx, field) in fields.iter().enumerate() {
+        let opts = field_opts(kind, &field.attrs)?;
+        if opts.skip {
+            continue;
+        }
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(idx)),
+        };
+        members.push((opts.order, member));
+    }
+    // NOTE: This is a stable sort, so declaration order is preserved
+    // between fields with the same order.
+    members.sort_by_key(|(order, _)| *order);
+    Ok(members.into_iter().map(|(_, member)| member).collect())
+}
+
+fn expand(kind: &DeriveKind, input: &DeriveInput) -> Result<TokenStream2> {
+    let members = members(kind, input)?;
+    let path = trait_path(kind, &input.attrs)?;
+    let method = Ident::new(kind.method, proc_macro2::Span::call_site());
+
+    let calls = members.iter().map(|member| {
+        quote! { #path::#method(&mut self.#member); }
+    });
+    let any = kind.any.map(|any| {
+        let any = Ident::new(any, proc_macro2::Span::call_site());
+        let terms = members.iter().map(|member| {
+            quote! { || #path::#any(&self.#member) }
+        });
+        quote! {
+            fn #any(&self) -> bool {
+                false #(#terms)*
+            }
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #path for #name #ty_generics #where_clause {
+            fn #method(&mut self) {
+                #(#calls)*
+            }
+            #any
+        }
+    })
+}
+
+fn expand_cost(kind: &DeriveKind, input: &DeriveInput) -> Result<TokenStream2> {
+    let members = members(kind, input)?;
+    let path = trait_path(kind, &input.attrs)?;
+
+    let calls = members.iter().map(|member| {
+        let field = match member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(idx) => idx.index.to_string(),
+        };
+        quote! { 
+            #path::cost(&self.#member, &::std::format!("{}.{}", path, #field), r);
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #path for #name #ty_generics #where_clause {
+            fn cost(&self, path: &str, r: &mut ::sim::lle::cost::CostReport) {
+                #(#calls)*
+            }
+        }
+    })
+}
+
+/// The width of a field (from its type).
+fn field_bits(field: &Field) -> TokenStream2 {
+    let ty = &field.ty;
+    quote! { <#ty as ::sim::lle::cost::HwBits>::BITS }
+}
+
+/// The sum of the widths of some fields.
+fn sum_bits(fields: &Fields) -> TokenStream2 {
+    let terms = fields.iter().map(field_bits);
+    quote! { 0 #(+ #terms)* }
+}
+
+fn expand_hw_bits(input: &DeriveInput) -> Result<TokenStream2> {
+    let bits = match &input.data {
+        Data::Struct(data) => sum_bits(&data.fields),
+        Data::Enum(data) => {
+            // NOTE: This is the same as 'index_bits()' in the sim crate.
+            let num = data.variants.len();
+            let tag = num.next_power_of_two().trailing_zeros() as u64;
+            let variants = data.variants.iter().map(|v| sum_bits(&v.fields));
+            quote! {
+                #tag + {
+                    let mut max = 0;
+                    #( if #variants > max { max = #variants; } )*
+                    max
+                }
+            }
+        },
+        Data::Union(_) => return Err(Error::new(input.span(), 
+            "#[derive(HwBits)] isn't supported on unions")),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::sim::lle::cost::HwBits for #name #ty_generics 
+            #where_clause 
+        {
+            const BITS: u64 = #bits;
+        }
+    })
+}
@@ -4,6 +4,10 @@
 //! for certain devices. 
 
 pub mod mem;
+pub mod cache;
+pub mod bus;
+pub mod dram;
+pub mod axi;
 pub mod riscv;
 
 
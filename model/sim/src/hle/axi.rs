@@ -0,0 +1,801 @@
+//! Transaction-level model of an AXI4 interface.
+//!
+//! This mirrors the bundles in `src/main/scala/amba/axi.scala`. Each of the
+//! five channels is an [AxiChannel] made of [Wire]s: the source drives the
+//! payload (and VALID) and the sink drives READY on every cycle, and a
+//! transfer occurs when both are asserted.
+//!
+//! Components attached to an [AxiBus] are simulated in two steps:
+//!
+//! 1. `drive()` - Drive outputs onto the bus. Masters must be driven
+//!    before slaves (VALID must not depend on READY, but the opposite is
+//!    allowed).
+//! 2. `sample()` - Observe the transfers that occur at the clock edge.
+//!
+//! ..after which the bus (and any other state) is updated, ie.
+//!
+//! ```ignore
+//! master.drive(&mut bus);
+//! slave.drive(&mut bus);
+//! checker.check(&bus);
+//! master.sample(&bus);
+//! slave.sample(&bus);
+//! bus.update();
+//! slave.update();
+//! ```
+//!
+//! [AxiMaster] is a [MemDevice] which turns requests from a cache into
+//! bursts, and [AxiSlave] services bursts with some other [MemDevice]
+//! (ie. a [Ram]). [AxiChecker] checks the handshake and burst rules, and
+//! keeps a log of all transfers on the bus.
+
+use std::collections::*;
+
+use crate::lle::*;
+use crate::lle::wire::*;
+use crate::hle::mem::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AxiBurst {
+    Fixed,
+    Incr,
+    Wrap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AxiResp {
+    Okay,
+    ExOkay,
+    SlvErr,
+    DecErr,
+}
+
+/// Payload for the write address (AW) and read address (AR) channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxiAddr {
+    pub addr: usize,
+    /// The number of bytes in each beat (log2)
+    pub size: u8,
+    /// The number of beats in the burst (minus one)
+    pub len: u8,
+    pub burst: AxiBurst,
+    pub id: usize,
+    pub lock: bool,
+    pub cache: u8,
+    pub prot: u8,
+    pub qos: u8,
+}
+impl AxiAddr {
+    pub fn new(id: usize, addr: usize, size: u8, len: u8, burst: AxiBurst)
+        -> Self
+    {
+        Self { addr, size, len, burst, id, lock: false, cache: 0, prot: 0, qos: 0 }
+    }
+
+    /// Returns the number of beats in this burst.
+    pub fn beats(&self) -> usize { self.len as usize + 1 }
+
+    /// Returns the number of bytes in each beat.
+    pub fn beat_bytes(&self) -> usize { 1 << self.size }
+
+    /// Returns the address of some beat in this burst.
+    pub fn beat_addr(&self, beat: usize) -> usize {
+        let bytes = self.beat_bytes();
+        match self.burst {
+            AxiBurst::Fixed => self.addr,
+            AxiBurst::Incr => {
+                if beat == 0 {
+                    self.addr
+                } else {
+                    (self.addr & !(bytes - 1)) + (beat * bytes)
+                }
+            },
+            AxiBurst::Wrap => {
+                let total = bytes * self.beats();
+                let base  = self.addr & !(total - 1);
+                base + ((self.addr - base + beat * bytes) % total)
+            },
+        }
+    }
+}
+
+/// Payload for the write data (W) channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxiWData {
+    pub data: u64,
+    pub strb: u8,
+    pub last: bool,
+}
+
+/// Payload for the write response (B) channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxiWResp {
+    pub id: usize,
+    pub resp: AxiResp,
+}
+
+/// Payload for the read data (R) channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxiRData {
+    pub data: u64,
+    pub id: usize,
+    pub last: bool,
+    pub resp: AxiResp,
+}
+
+/// A ready/valid channel.
+///
+/// Both sides of the channel must be driven on every cycle.
+pub struct AxiChannel<T: Copy> {
+    valid: Wire<Option<T>>,
+    ready: Wire<bool>,
+}
+impl <T: Copy> AxiChannel<T> {
+    pub fn new() -> Self {
+        Self { valid: Wire::new(), ready: Wire::new() }
+    }
+    /// Drive VALID (and the payload) from the source.
+    pub fn drive_valid(&mut self, bits: Option<T>) { self.valid.drive(bits); }
+    /// Drive READY from the sink.
+    pub fn drive_ready(&mut self, ready: bool) { self.ready.drive(ready); }
+    pub fn sample_valid(&self) -> Option<T> { self.valid.sample() }
+    pub fn sample_ready(&self) -> bool { self.ready.sample() }
+
+    /// Returns the payload if a transfer occurs on this cycle.
+    pub fn fire(&self) -> Option<T> {
+        if self.sample_ready() { self.sample_valid() } else { None }
+    }
+}
+impl <T: Copy> Default for AxiChannel<T> {
+    fn default() -> Self { Self::new() }
+}
+impl <T: Copy> Clocked for AxiChannel<T> {
+    fn update(&mut self) {
+        self.valid.update();
+        self.ready.update();
+    }
+}
+
+/// The set of channels between an AXI master and slave.
+#[derive(Default)]
+pub struct AxiBus {
+    pub aw: AxiChannel<AxiAddr>,
+    pub w:  AxiChannel<AxiWData>,
+    pub b:  AxiChannel<AxiWResp>,
+    pub ar: AxiChannel<AxiAddr>,
+    pub r:  AxiChannel<AxiRData>,
+}
+impl AxiBus {
+    pub fn new() -> Self { Self::default() }
+}
+impl Clocked for AxiBus {
+    fn update(&mut self) {
+        self.aw.update();
+        self.w.update();
+        self.b.update();
+        self.ar.update();
+        self.r.update();
+    }
+}
+
+/// An outstanding burst from an [AxiMaster].
+struct MasterTxn {
+    req: MemReq,
+    data: Vec<u8>,
+}
+
+/// Adapter from [MemReq] to AXI bursts.
+///
+/// Each request becomes a single INCR burst with full-width beats, so the
+/// length of a request must be a multiple of the data width (and requests
+/// must not cross a 4KiB boundary). The AXI ID of a burst is the index of
+/// an internal slot, so the number of outstanding bursts is limited.
+/// Reads are not issued until older writes to the same address complete.
+pub struct AxiMaster {
+    /// Width of the data channels (in bytes)
+    data_bytes: usize,
+    slots: Vec<Option<MasterTxn>>,
+    aw: VecDeque<AxiAddr>,
+    w:  VecDeque<AxiWData>,
+    ar: VecDeque<AxiAddr>,
+    resp: VecDeque<MemResp>,
+}
+impl AxiMaster {
+    pub fn new(data_bytes: usize, max_outstanding: usize) -> Self {
+        assert!(data_bytes.is_power_of_two() && data_bytes <= 8);
+        Self {
+            data_bytes,
+            slots: (0..max_outstanding).map(|_| None).collect(),
+            aw: VecDeque::new(),
+            w:  VecDeque::new(),
+            ar: VecDeque::new(),
+            resp: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if `addr` overlaps a write that hasn't completed.
+    fn write_pending(&self, addr: &AxiAddr) -> bool {
+        let end = addr.addr + addr.beats() * addr.beat_bytes();
+        self.slots.iter().flatten().any(|txn| {
+            txn.req.op == MemOp::Write
+            && txn.req.addr < end && addr.addr < txn.req.addr + txn.req.len
+        })
+    }
+
+    pub fn drive(&mut self, bus: &mut AxiBus) {
+        // NOTE: There's no ordering between the read and write channels, so
+        // reads must wait for any older writes to the same address.
+        let ar = self.ar.front().filter(|ar| !self.write_pending(ar));
+        bus.aw.drive_valid(self.aw.front().copied());
+        bus.w.drive_valid(self.w.front().copied());
+        bus.ar.drive_valid(ar.copied());
+        bus.b.drive_ready(true);
+        bus.r.drive_ready(true);
+    }
+
+    pub fn sample(&mut self, bus: &AxiBus) {
+        if bus.aw.fire().is_some() { self.aw.pop_front(); }
+        if bus.w.fire().is_some()  { self.w.pop_front(); }
+        if bus.ar.fire().is_some() { self.ar.pop_front(); }
+
+        if let Some(b) = bus.b.fire() {
+            let txn = self.slots[b.id].take().unwrap();
+            assert!(txn.req.op == MemOp::Write);
+            self.resp.push_back(MemResp::from_req(&txn.req, Vec::new()));
+        }
+        if let Some(r) = bus.r.fire() {
+            let bytes = self.data_bytes;
+            let txn = self.slots[r.id].as_mut().unwrap();
+            assert!(txn.req.op == MemOp::Read);
+            txn.data.extend_from_slice(&r.data.to_le_bytes()[..bytes]);
+            if r.last {
+                let txn = self.slots[r.id].take().unwrap();
+                assert!(txn.data.len() == txn.req.len);
+                self.resp.push_back(MemResp::from_req(&txn.req, txn.data));
+            }
+        }
+    }
+}
+impl MemDevice for AxiMaster {
+    fn ready(&self, src: usize) -> bool {
+        self.slots.iter().any(|s| s.is_none())
+    }
+    fn drive_req(&mut self, req: MemReq) {
+        // Clean evictions don't need to be sent anywhere
+        if req.op == MemOp::Evict {
+            self.resp.push_back(MemResp::from_req(&req, Vec::new()));
+            return;
+        }
+        let bytes = self.data_bytes;
+        assert!(req.len.is_multiple_of(bytes) && req.addr.is_multiple_of(bytes));
+        assert!(req.addr >> 12 == (req.addr + req.len - 1) >> 12,
+            "AXI request crosses a 4KiB boundary");
+        let beats = req.len / bytes;
+        assert!(beats <= 256);
+
+        let id = self.slots.iter().position(|s| s.is_none()).unwrap();
+        let addr = AxiAddr::new(id, req.addr, bytes.trailing_zeros() as u8,
+            (beats - 1) as u8, AxiBurst::Incr);
+        match req.op {
+            MemOp::Read => self.ar.push_back(addr),
+            MemOp::Write => {
+                self.aw.push_back(addr);
+                for (beat, chunk) in req.data.chunks(bytes).enumerate() {
+                    let mut buf = [0u8; 8];
+                    buf[..bytes].copy_from_slice(chunk);
+                    self.w.push_back(AxiWData {
+                        data: u64::from_le_bytes(buf),
+                        strb: ((1u16 << bytes) - 1) as u8,
+                        last: beat == beats - 1,
+                    });
+                }
+            },
+            MemOp::Evict => unreachable!(),
+        }
+        self.slots[id] = Some(MasterTxn { req, data: Vec::new() });
+    }
+    fn take_resp(&mut self, src: usize) -> Option<MemResp> {
+        let idx = self.resp.iter().position(|r| r.src == src)?;
+        self.resp.remove(idx)
+    }
+}
+
+struct SlaveRead {
+    addr: AxiAddr,
+    /// Data for each beat (when it has been returned by the device)
+    data: Vec<Option<u64>>,
+    beat: usize,
+}
+
+struct SlaveWrite {
+    addr: AxiAddr,
+    beat: usize,
+    /// Set when the last beat has been accepted
+    done: bool,
+    /// Set when WLAST was not asserted on the last beat
+    err: bool,
+    /// Writes to the device which haven't been acknowledged
+    pending: usize,
+}
+
+/// Adapter from AXI bursts to some [MemDevice].
+///
+/// One read burst and one write burst can be in progress at a time.
+pub struct AxiSlave<D: MemDevice + Clocked> {
+    dev: D,
+    data_bytes: usize,
+    rd: Option<SlaveRead>,
+    wr: Option<SlaveWrite>,
+    dev_out: VecDeque<MemReq>,
+}
+impl <D: MemDevice + Clocked> AxiSlave<D> {
+    pub fn new(dev: D, data_bytes: usize) -> Self {
+        assert!(data_bytes.is_power_of_two() && data_bytes <= 8);
+        Self { dev, data_bytes, rd: None, wr: None, dev_out: VecDeque::new() }
+    }
+    pub fn device(&self) -> &D { &self.dev }
+    pub fn device_mut(&mut self) -> &mut D { &mut self.dev }
+
+    fn lane(&self, addr: usize) -> usize { addr & (self.data_bytes - 1) }
+
+    pub fn drive(&mut self, bus: &mut AxiBus) {
+        bus.ar.drive_ready(self.rd.is_none());
+        bus.aw.drive_ready(self.wr.is_none());
+        bus.w.drive_ready(matches!(&self.wr, Some(wr) if !wr.done));
+
+        let bresp = match &self.wr {
+            Some(wr) if wr.done && wr.pending == 0 => Some(AxiWResp {
+                id: wr.addr.id,
+                resp: if wr.err { AxiResp::SlvErr } else { AxiResp::Okay },
+            }),
+            _ => None,
+        };
+        bus.b.drive_valid(bresp);
+
+        let rdata = self.rd.as_ref().and_then(|rd| {
+            rd.data[rd.beat].map(|data| AxiRData {
+                data,
+                id: rd.addr.id,
+                last: rd.beat == rd.addr.len as usize,
+                resp: AxiResp::Okay,
+            })
+        });
+        bus.r.drive_valid(rdata);
+    }
+
+    pub fn sample(&mut self, bus: &AxiBus) {
+        if let Some(addr) = bus.ar.fire() {
+            assert!(addr.beat_bytes() <= self.data_bytes);
+            for beat in 0..addr.beats() {
+                let req = MemReq::read(beat, addr.beat_addr(beat), addr.beat_bytes());
+                self.dev_out.push_back(req);
+            }
+            self.rd = Some(SlaveRead { addr, data: vec![None; addr.beats()], beat: 0 });
+        }
+        if bus.r.fire().is_some() {
+            let rd = self.rd.as_mut().unwrap();
+            rd.beat += 1;
+            if rd.beat == rd.addr.beats() {
+                self.rd = None;
+            }
+        }
+
+        if let Some(addr) = bus.aw.fire() {
+            assert!(addr.beat_bytes() <= self.data_bytes);
+            self.wr = Some(SlaveWrite {
+                addr, beat: 0, done: false, err: false, pending: 0
+            });
+        }
+        if let Some(w) = bus.w.fire() {
+            let wr = self.wr.as_mut().unwrap();
+            let addr = wr.addr.beat_addr(wr.beat);
+            let lane = addr & (self.data_bytes - 1);
+            let bytes = w.data.to_le_bytes();
+
+            // Write each contiguous run of enabled byte lanes
+            let mut off = lane;
+            while off < lane + wr.addr.beat_bytes() {
+                if w.strb & (1 << off) == 0 {
+                    off += 1;
+                    continue;
+                }
+                let start = off;
+                while off < lane + wr.addr.beat_bytes() && w.strb & (1 << off) != 0 {
+                    off += 1;
+                }
+                let data = bytes[start..off].to_vec();
+                self.dev_out.push_back(MemReq::write(0, addr - lane + start, data));
+                wr.pending += 1;
+            }
+
+            let last_beat = wr.beat == wr.addr.len as usize;
+            wr.err |= w.last != last_beat;
+            wr.done = w.last || last_beat;
+            wr.beat += 1;
+        }
+        if bus.b.fire().is_some() {
+            self.wr = None;
+        }
+    }
+}
+impl <D: MemDevice + Clocked> Clocked for AxiSlave<D> {
+    fn update(&mut self) {
+        while !self.dev_out.is_empty() && self.dev.ready(0) {
+            let req = self.dev_out.pop_front().unwrap();
+            self.dev.drive_req(req);
+        }
+        self.dev.update();
+        while let Some(resp) = self.dev.take_resp(0) {
+            match resp.op {
+                MemOp::Read => {
+                    let lane = self.lane(resp.addr);
+                    let rd = self.rd.as_mut().unwrap();
+                    let mut buf = [0u8; 8];
+                    buf[lane..lane + resp.data.len()].copy_from_slice(&resp.data);
+                    rd.data[resp.id] = Some(u64::from_le_bytes(buf));
+                },
+                MemOp::Write => {
+                    self.wr.as_mut().unwrap().pending -= 1;
+                },
+                MemOp::Evict => unreachable!(),
+            }
+        }
+    }
+}
+
+/// A transfer observed by an [AxiChecker].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AxiBeat {
+    Aw(AxiAddr),
+    W(AxiWData),
+    B(AxiWResp),
+    Ar(AxiAddr),
+    R(AxiRData),
+}
+
+/// A protocol violation observed by an [AxiChecker].
+#[derive(Clone, Debug)]
+pub struct AxiViolation {
+    pub cycle: usize,
+    pub channel: &'static str,
+    pub msg: String,
+}
+
+/// Checks the handshake rules on a single channel: once VALID is asserted,
+/// it must remain asserted (with the same payload) until READY is asserted.
+struct HandshakeChecker<T: Copy + PartialEq> {
+    stalled: Option<T>,
+}
+impl <T: Copy + PartialEq + std::fmt::Debug> HandshakeChecker<T> {
+    fn check(&mut self, ch: &AxiChannel<T>) -> Option<String> {
+        let valid = ch.sample_valid();
+        let res = match (self.stalled, valid) {
+            (Some(prev), None) => {
+                Some(format!("VALID dropped before READY ({:x?})", prev))
+            },
+            (Some(prev), Some(cur)) if prev != cur => {
+                Some(format!("Payload changed before READY ({:x?} -> {:x?})",
+                    prev, cur))
+            },
+            _ => None,
+        };
+        self.stalled = if ch.sample_ready() { None } else { valid };
+        res
+    }
+}
+
+/// Passive monitor for an [AxiBus].
+pub struct AxiChecker {
+    data_bytes: usize,
+    cycle: usize,
+    aw: HandshakeChecker<AxiAddr>,
+    w:  HandshakeChecker<AxiWData>,
+    b:  HandshakeChecker<AxiWResp>,
+    ar: HandshakeChecker<AxiAddr>,
+    r:  HandshakeChecker<AxiRData>,
+
+    /// Expected number of beats for each write burst (in AW order)
+    aw_beats: VecDeque<(usize, usize)>,
+    /// Number of beats in each completed W burst (in order)
+    w_bursts: VecDeque<usize>,
+    /// Number of W beats seen for the current write burst
+    w_beats: usize,
+    /// IDs of write bursts waiting for a response
+    b_pending: Vec<usize>,
+    /// Remaining beats for each outstanding read burst (by ID, in order)
+    r_pending: BTreeMap<usize, VecDeque<usize>>,
+
+    log: Vec<(usize, AxiBeat)>,
+    violations: Vec<AxiViolation>,
+}
+impl AxiChecker {
+    pub fn new(data_bytes: usize) -> Self {
+        Self {
+            data_bytes,
+            cycle: 0,
+            aw: HandshakeChecker { stalled: None },
+            w:  HandshakeChecker { stalled: None },
+            b:  HandshakeChecker { stalled: None },
+            ar: HandshakeChecker { stalled: None },
+            r:  HandshakeChecker { stalled: None },
+            aw_beats: VecDeque::new(),
+            w_bursts: VecDeque::new(),
+            w_beats: 0,
+            b_pending: Vec::new(),
+            r_pending: BTreeMap::new(),
+            log: Vec::new(),
+            violations: Vec::new(),
+        }
+    }
+
+    /// Returns all transfers observed so far (with the cycle they occurred).
+    pub fn log(&self) -> &[(usize, AxiBeat)] { &self.log }
+
+    /// Returns all violations observed so far.
+    pub fn violations(&self) -> &[AxiViolation] { &self.violations }
+
+    fn report(&mut self, channel: &'static str, msg: String) {
+        self.violations.push(AxiViolation { cycle: self.cycle, channel, msg });
+    }
+
+    /// Check the rules for the address of a new burst.
+    fn check_burst(&mut self, channel: &'static str, a: &AxiAddr) {
+        let bytes = a.beat_bytes();
+        if bytes > self.data_bytes {
+            self.report(channel, format!("Size {} wider than the bus", bytes));
+        }
+        match a.burst {
+            AxiBurst::Incr => {
+                let last = a.beat_addr(a.len as usize) + bytes - 1;
+                if a.addr >> 12 != last >> 12 {
+                    self.report(channel, format!(
+                        "INCR burst at {:08x} crosses a 4KiB boundary", a.addr
+                    ));
+                }
+            },
+            AxiBurst::Wrap => {
+                if ![2, 4, 8, 16].contains(&a.beats()) {
+                    self.report(channel, format!(
+                        "WRAP burst with {} beats", a.beats()
+                    ));
+                }
+                if a.addr & (bytes - 1) != 0 {
+                    self.report(channel, format!(
+                        "Unaligned WRAP burst at {:08x}", a.addr
+                    ));
+                }
+            },
+            AxiBurst::Fixed => {
+                if a.beats() > 16 {
+                    self.report(channel, format!(
+                        "FIXED burst with {} beats", a.beats()
+                    ));
+                }
+            },
+        }
+    }
+
+    /// Match completed W bursts against the expected number of beats.
+    fn match_write_bursts(&mut self) {
+        while !self.aw_beats.is_empty() && !self.w_bursts.is_empty() {
+            let (id, expected) = self.aw_beats.pop_front().unwrap();
+            let actual = self.w_bursts.pop_front().unwrap();
+            if expected != actual {
+                self.report("W", format!(
+                    "WLAST on beat {} of a {}-beat burst (id {})",
+                    actual, expected, id
+                ));
+            }
+            self.b_pending.push(id);
+        }
+    }
+
+    /// Observe the state of the bus on this cycle.
+    pub fn check(&mut self, bus: &AxiBus) {
+        let hs = [
+            ("AW", self.aw.check(&bus.aw)),
+            ("W",  self.w.check(&bus.w)),
+            ("B",  self.b.check(&bus.b)),
+            ("AR", self.ar.check(&bus.ar)),
+            ("R",  self.r.check(&bus.r)),
+        ];
+        for (channel, res) in hs {
+            if let Some(msg) = res {
+                self.report(channel, msg);
+            }
+        }
+
+        if let Some(aw) = bus.aw.fire() {
+            self.log.push((self.cycle, AxiBeat::Aw(aw)));
+            self.check_burst("AW", &aw);
+            self.aw_beats.push_back((aw.id, aw.beats()));
+        }
+        if let Some(w) = bus.w.fire() {
+            self.log.push((self.cycle, AxiBeat::W(w)));
+            self.w_beats += 1;
+            // The length of the burst may already be known
+            let expected = self.aw_beats.get(self.w_bursts.len()).map(|x| x.1);
+            if w.last || expected == Some(self.w_beats) {
+                self.w_bursts.push_back(self.w_beats);
+                self.w_beats = 0;
+            }
+        }
+        self.match_write_bursts();
+
+        if let Some(b) = bus.b.fire() {
+            self.log.push((self.cycle, AxiBeat::B(b)));
+            if let Some(idx) = self.b_pending.iter().position(|id| *id == b.id) {
+                self.b_pending.remove(idx);
+            } else {
+                self.report("B", format!("Response for unknown write id {}", b.id));
+            }
+        }
+
+        if let Some(ar) = bus.ar.fire() {
+            self.log.push((self.cycle, AxiBeat::Ar(ar)));
+            self.check_burst("AR", &ar);
+            self.r_pending.entry(ar.id).or_default().push_back(ar.beats());
+        }
+        if let Some(r) = bus.r.fire() {
+            self.log.push((self.cycle, AxiBeat::R(r)));
+            let remaining = self.r_pending.get_mut(&r.id)
+                .and_then(|q| q.front_mut())
+                .map(|remaining| { *remaining -= 1; *remaining });
+            match remaining {
+                None => {
+                    self.report("R", format!("Data for unknown read id {}", r.id));
+                },
+                Some(remaining) => {
+                    if r.last != (remaining == 0) {
+                        self.report("R", format!(
+                            "RLAST={} with {} beats remaining (id {})",
+                            r.last, remaining, r.id
+                        ));
+                    }
+                    if remaining == 0 || r.last {
+                        self.r_pending.get_mut(&r.id).unwrap().pop_front();
+                    }
+                },
+            }
+        }
+        self.cycle += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hle::cache::*;
+    use crate::hle::cache::l1d::*;
+
+    fn step(master: &mut AxiMaster, slave: &mut AxiSlave<Ram>,
+        checker: &mut AxiChecker, bus: &mut AxiBus)
+    {
+        master.drive(bus);
+        slave.drive(bus);
+        checker.check(bus);
+        master.sample(bus);
+        slave.sample(bus);
+        bus.update();
+        slave.update();
+    }
+
+    #[test]
+    fn axi_beat_addr() {
+        let incr = AxiAddr::new(0, 0x1004, 2, 3, AxiBurst::Incr);
+        let wrap = AxiAddr::new(0, 0x1008, 2, 3, AxiBurst::Wrap);
+        let fixed = AxiAddr::new(0, 0x1008, 2, 3, AxiBurst::Fixed);
+        let beats = |a: AxiAddr| (0..4).map(|b| a.beat_addr(b)).collect::<Vec<_>>();
+        assert_eq!(beats(incr),  vec![0x1004, 0x1008, 0x100c, 0x1010]);
+        assert_eq!(beats(wrap),  vec![0x1008, 0x100c, 0x1000, 0x1004]);
+        assert_eq!(beats(fixed), vec![0x1008, 0x1008, 0x1008, 0x1008]);
+    }
+
+    #[test]
+    fn axi_cache_to_ram() {
+        let mut ram = Ram::new(0x10000);
+        for addr in (0..0x1000).step_by(4) {
+            ram.write_bytes(addr, &(addr as u32).to_le_bytes());
+        }
+        let cfg = CacheConfig {
+            sets: 1, ways: 1, line_size: 32, latency: 1,
+            mshrs: 2, mshr_targets: 2
+        };
+        let mut l1 = L1DCache::new(cfg);
+        let mut master = AxiMaster::new(8, 4);
+        let mut slave = AxiSlave::new(ram, 8);
+        let mut checker = AxiChecker::new(8);
+        let mut bus = AxiBus::new();
+
+        let reqs = [
+            L1Req::load(0, 0x100, 4),
+            L1Req::store(1, 0x104, 4, 0xdeadbeef),
+            // Evict the dirty line
+            L1Req::load(2, 0x200, 4),
+            L1Req::load(3, 0x104, 4),
+        ];
+        let mut resp = Vec::new();
+        for req in reqs {
+            while !l1.ready() {
+                l1.update();
+            }
+            l1.drive_req(req);
+            for _ in 0..64 {
+                link(0, &mut l1, &mut master);
+                step(&mut master, &mut slave, &mut checker, &mut bus);
+                l1.update();
+                resp.extend(l1.sample_resp().iter().copied());
+                if resp.iter().any(|r| r.id == req.id) {
+                    break;
+                }
+            }
+        }
+        assert_eq!(resp.len(), 4);
+        assert_eq!(resp[0].data, 0x100);
+        assert_eq!(resp[2].data, 0x200);
+        assert_eq!(resp[3].data, 0xdeadbeef);
+        assert!(checker.violations().is_empty(), "{:?}", checker.violations());
+
+        // 3 read bursts and 1 write burst with 4 beats each
+        let count = |f: fn(&AxiBeat) -> bool| {
+            checker.log().iter().filter(|(_, b)| f(b)).count()
+        };
+        assert_eq!(count(|b| matches!(b, AxiBeat::Ar(_))), 3);
+        assert_eq!(count(|b| matches!(b, AxiBeat::R(_))), 12);
+        assert_eq!(count(|b| matches!(b, AxiBeat::Aw(_))), 1);
+        assert_eq!(count(|b| matches!(b, AxiBeat::W(_))), 4);
+        assert_eq!(count(|b| matches!(b, AxiBeat::B(_))), 1);
+    }
+
+    #[test]
+    fn axi_checker_handshake() {
+        let mut checker = AxiChecker::new(8);
+        let mut bus = AxiBus::new();
+        let ar = AxiAddr::new(0, 0x1000, 3, 0, AxiBurst::Incr);
+
+        // VALID is dropped before READY
+        for valid in [Some(ar), None] {
+            bus.aw.drive_valid(None);
+            bus.aw.drive_ready(true);
+            bus.w.drive_valid(None);
+            bus.w.drive_ready(true);
+            bus.b.drive_valid(None);
+            bus.b.drive_ready(true);
+            bus.ar.drive_valid(valid);
+            bus.ar.drive_ready(false);
+            bus.r.drive_valid(None);
+            bus.r.drive_ready(true);
+            checker.check(&bus);
+            bus.update();
+        }
+        assert_eq!(checker.violations().len(), 1);
+        assert_eq!(checker.violations()[0].channel, "AR");
+        assert_eq!(checker.violations()[0].cycle, 1);
+    }
+
+    #[test]
+    fn axi_checker_bursts() {
+        let mut checker = AxiChecker::new(8);
+        let mut bus = AxiBus::new();
+        let aw = AxiAddr::new(0, 0x0ff8, 3, 1, AxiBurst::Incr);
+        let ar = AxiAddr::new(1, 0x1004, 2, 2, AxiBurst::Wrap);
+        let w0 = AxiWData { data: 0, strb: 0xff, last: true };
+
+        bus.aw.drive_valid(Some(aw));
+        bus.w.drive_valid(Some(w0));
+        bus.ar.drive_valid(Some(ar));
+        bus.aw.drive_ready(true);
+        bus.w.drive_ready(true);
+        bus.ar.drive_ready(true);
+        bus.b.drive_valid(None);
+        bus.b.drive_ready(true);
+        bus.r.drive_valid(None);
+        bus.r.drive_ready(true);
+        checker.check(&bus);
+
+        let msgs: Vec<&str> = checker.violations().iter()
+            .map(|v| v.channel).collect();
+        // AW crosses 4KiB, WLAST is early, AR is a 3-beat WRAP burst
+        assert_eq!(msgs, vec!["AW", "W", "AR"]);
+    }
+}
@@ -0,0 +1,92 @@
+//! Memory bus.
+//!
+//! A [Bus] routes requests from upstream ports to devices by address.
+//! Each device is mapped into a range of the physical address space, and
+//! sees addresses relative to the base of its range.
+
+use std::ops::Range;
+
+use crate::lle::*;
+use crate::hle::mem::*;
+
+/// A device that can be attached to a [Bus].
+pub trait BusDevice: MemDevice + Clocked {}
+impl <T: MemDevice + Clocked> BusDevice for T {}
+
+struct Mapping {
+    range: Range<usize>,
+    dev: Box<dyn BusDevice>,
+}
+
+/// Address-decoded interconnect between requesters and devices.
+///
+/// Routing is instantaneous; the latency of a request is the latency of
+/// the device that services it.
+pub struct Bus {
+    ports: usize,
+    map: Vec<Mapping>,
+}
+impl Bus {
+    pub fn new(ports: usize) -> Self {
+        Self { ports, map: Vec::new() }
+    }
+
+    /// Map a device into the range `base..base+size`.
+    pub fn attach(&mut self, base: usize, size: usize, dev: Box<dyn BusDevice>) {
+        let range = base..base + size;
+        for m in self.map.iter() {
+            assert!(range.end <= m.range.start || m.range.end <= range.start,
+                "Bus mapping {:x?} overlaps {:x?}", range, m.range);
+        }
+        self.map.push(Mapping { range, dev });
+    }
+
+    fn decode(&self, addr: usize) -> usize {
+        self.map.iter().position(|m| m.range.contains(&addr))
+            .unwrap_or_else(|| panic!("No device mapped at {:08x}", addr))
+    }
+
+    /// Get a reference to the device mapped at `addr`.
+    pub fn device(&self, addr: usize) -> &dyn BusDevice {
+        self.map[self.decode(addr)].dev.as_ref()
+    }
+    /// Get a mutable reference to the device mapped at `addr`.
+    pub fn device_mut(&mut self, addr: usize) -> &mut dyn BusDevice {
+        let idx = self.decode(addr);
+        self.map[idx].dev.as_mut()
+    }
+}
+
+impl MemDevice for Bus {
+    /// NOTE: The bus is only ready when *all* devices are ready, since the
+    /// address of the next request isn't known here.
+    fn ready(&self, src: usize) -> bool {
+        assert!(src < self.ports);
+        self.map.iter().all(|m| m.dev.ready(src))
+    }
+    fn drive_req(&mut self, mut req: MemReq) {
+        let idx = self.decode(req.addr);
+        let m = &mut self.map[idx];
+        assert!(req.addr + req.len <= m.range.end, "Request crosses devices");
+        req.addr -= m.range.start;
+        m.dev.drive_req(req);
+    }
+    fn take_resp(&mut self, src: usize) -> Option<MemResp> {
+        for m in self.map.iter_mut() {
+            if let Some(mut resp) = m.dev.take_resp(src) {
+                resp.addr += m.range.start;
+                return Some(resp);
+            }
+        }
+        None
+    }
+}
+
+impl Clocked for Bus {
+    fn update(&mut self) {
+        for m in self.map.iter_mut() {
+            m.dev.update();
+        }
+    }
+}
+
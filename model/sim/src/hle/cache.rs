@@ -1,37 +1,306 @@
+//! Behavioral cache models.
+//!
+//! The models in this module are transaction-level: data is moved between
+//! levels of the hierarchy with [MemReq]/[MemResp], and [link] is used to
+//! connect a requester to the next level.
 
-#[derive(Clone, Copy)]
-pub struct CacheWay<const SZ: usize> {
-    data: [u8; SZ],
+pub mod l1d;
+pub mod l2;
+
+use crate::hle::mem::*;
+use crate::lle::repl::*;
+
+/// Parameters describing the organization of a set-associative cache.
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+    /// Number of sets
+    pub sets: usize,
+    /// Number of ways in each set
+    pub ways: usize,
+    /// Size of a cache line (in bytes)
+    pub line_size: usize,
+    /// Number of cycles between accepting a request and responding (on hit)
+    pub latency: usize,
+    /// Number of outstanding misses
+    pub mshrs: usize,
+    /// Number of requests that can be merged into a single outstanding miss
+    pub mshr_targets: usize,
 }
-impl <const SZ: usize> CacheWay<SZ> {
-    pub fn new() -> Self {
-        Self { data: [0; SZ] }
+impl CacheConfig {
+    /// Returns the capacity of the cache (in bytes).
+    pub fn size(&self) -> usize {
+        self.sets * self.ways * self.line_size
+    }
+
+    fn validate(&self) {
+        assert!(self.sets.is_power_of_two());
+        assert!(self.line_size.is_power_of_two());
+        assert!(self.ways != 0);
+        assert!(self.latency != 0);
+        assert!(self.mshrs != 0);
+        assert!(self.mshr_targets != 0);
+    }
+
+    /// Returns the address of the line containing `addr`.
+    pub fn line_addr(&self, addr: usize) -> usize {
+        addr & !(self.line_size - 1)
+    }
+    /// Returns the offset of `addr` within a line.
+    pub fn line_offset(&self, addr: usize) -> usize {
+        addr & (self.line_size - 1)
+    }
+    /// Returns the index of the set containing `addr`.
+    pub fn set_index(&self, addr: usize) -> usize {
+        (addr / self.line_size) & (self.sets - 1)
+    }
+    /// Returns the tag for `addr`.
+    pub fn tag(&self, addr: usize) -> usize {
+        addr / self.line_size / self.sets
+    }
+    /// Returns the address of the line with the given tag and set index.
+    pub fn addr_from(&self, tag: usize, set: usize) -> usize {
+        ((tag * self.sets) + set) * self.line_size
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct CacheSet<const WAYS: usize, const SZ: usize> {
-    data: [CacheWay<SZ>; WAYS],
+/// A single cache line.
+#[derive(Clone, Debug)]
+pub struct CacheLine {
+    pub valid: bool,
+    pub dirty: bool,
+    pub tag: usize,
+    pub data: Vec<u8>,
 }
-impl <const WAYS: usize, const SZ: usize> CacheSet<WAYS, SZ> {
-    pub fn new() -> Self {
-        Self { data: [CacheWay::new(); WAYS] }
+impl CacheLine {
+    fn new(line_size: usize) -> Self {
+        Self { valid: false, dirty: false, tag: 0, data: vec![0; line_size] }
     }
 }
 
+/// A line removed from a [CacheArray].
+#[derive(Clone, Debug)]
+pub struct Eviction {
+    pub addr: usize,
+    pub dirty: bool,
+    pub data: Vec<u8>,
+}
 
-pub struct SetAssociativeCache
-<const SETS: usize, const WAYS: usize, const SZ: usize> 
-{
-    data: [CacheSet<SZ, WAYS>; SETS],
+/// Tag and data storage for a set-associative cache.
+///
+/// Victims are selected by a [ReplacementPolicy] (true LRU by default),
+/// with invalid ways preferred.
+pub struct CacheArray {
+    cfg: CacheConfig,
+    lines: Vec<CacheLine>,
+    repl: Box<dyn ReplacementPolicy>,
 }
-impl <const SETS: usize, const WAYS: usize, const SZ: usize> 
-SetAssociativeCache<SETS, WAYS, SZ> {
-    pub fn new() -> Self {
-        Self { data: [CacheSet::new(); SETS] }
+impl CacheArray {
+    pub fn new(cfg: CacheConfig) -> Self {
+        cfg.validate();
+        let num_lines = cfg.sets * cfg.ways;
+        Self {
+            cfg,
+            lines: vec![CacheLine::new(cfg.line_size); num_lines],
+            repl: Box::new(LruPolicy::new(cfg.sets, cfg.ways)),
+        }
+    }
+    /// Use a different replacement policy (the default is [LruPolicy]).
+    pub fn with_replacement(mut self, repl: impl ReplacementPolicy + 'static)
+        -> Self
+    {
+        assert!(repl.num_sets() == self.cfg.sets && repl.num_ways() == self.cfg.ways,
+            "replacement policy doesn't match the cache organization");
+        self.repl = Box::new(repl);
+        self
+    }
+    pub fn config(&self) -> &CacheConfig { &self.cfg }
+
+    fn idx(&self, set: usize, way: usize) -> usize {
+        set * self.cfg.ways + way
+    }
+
+    /// Returns the way containing `addr` (if it exists).
+    pub fn lookup(&self, addr: usize) -> Option<usize> {
+        let set = self.cfg.set_index(addr);
+        let tag = self.cfg.tag(addr);
+        (0..self.cfg.ways).find(|way| {
+            let line = &self.lines[self.idx(set, *way)];
+            line.valid && line.tag == tag
+        })
+    }
+
+    pub fn line(&self, set: usize, way: usize) -> &CacheLine {
+        &self.lines[self.idx(set, way)]
+    }
+    pub fn line_mut(&mut self, set: usize, way: usize) -> &mut CacheLine {
+        let idx = self.idx(set, way);
+        &mut self.lines[idx]
+    }
+
+    /// Mark a line as the most-recently used in its set.
+    pub fn touch(&mut self, set: usize, way: usize) {
+        self.repl.touch(set, way);
+    }
+
+    /// Select a way to be replaced in some set.
+    pub fn victim(&mut self, set: usize) -> usize {
+        self.repl.victim(set)
+    }
+
+    /// Fill the line containing `addr`, returning the evicted line (if a
+    /// valid line was replaced).
+    pub fn fill(&mut self, addr: usize, data: &[u8]) -> (usize, Option<Eviction>) {
+        assert!(data.len() == self.cfg.line_size);
+        assert!(self.lookup(addr).is_none());
+        let set = self.cfg.set_index(addr);
+        let tag = self.cfg.tag(addr);
+        let way = self.victim(set);
+        let evicted = self.evict(set, way);
+
+        let line = self.line_mut(set, way);
+        line.valid = true;
+        line.dirty = false;
+        line.tag   = tag;
+        line.data.copy_from_slice(data);
+        self.repl.insert(set, way);
+        (way, evicted)
+    }
+
+    /// Invalidate some way, returning the line if it was valid.
+    pub fn evict(&mut self, set: usize, way: usize) -> Option<Eviction> {
+        let addr = self.cfg.addr_from(self.line(set, way).tag, set);
+        let line = self.line_mut(set, way);
+        if !line.valid {
+            return None;
+        }
+        line.valid = false;
+        let res = Eviction { addr, dirty: line.dirty, data: line.data.clone() };
+        self.repl.invalidate(set, way);
+        Some(res)
+    }
+
+    /// Invalidate the line containing `addr` (if it exists).
+    pub fn invalidate(&mut self, addr: usize) -> Option<Eviction> {
+        let way = self.lookup(addr)?;
+        self.evict(self.cfg.set_index(addr), way)
+    }
+}
+
+/// A miss status holding register.
+#[derive(Clone, Debug)]
+pub struct Mshr<T> {
+    /// Address of the line being fetched
+    pub line_addr: usize,
+    /// Requests waiting for this line (in program order)
+    pub targets: Vec<T>,
+}
+
+/// A set of miss status holding registers.
+///
+/// The index of an entry is used as the tag for the associated request to
+/// the next level.
+pub struct MshrFile<T> {
+    entries: Vec<Option<Mshr<T>>>,
+    max_targets: usize,
+}
+impl <T> MshrFile<T> {
+    pub fn new(size: usize, max_targets: usize) -> Self {
+        Self {
+            entries: (0..size).map(|_| None).collect(),
+            max_targets,
+        }
+    }
+    pub fn num_used(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+    pub fn is_full(&self) -> bool {
+        self.entries.iter().all(|e| e.is_some())
+    }
+
+    /// Find the entry tracking the line at `line_addr` (if it exists).
+    pub fn find(&self, line_addr: usize) -> Option<usize> {
+        self.entries.iter().position(|e| {
+            matches!(e, Some(mshr) if mshr.line_addr == line_addr)
+        })
+    }
+
+    /// Allocate a new entry for a primary miss.
+    pub fn alloc(&mut self, line_addr: usize, target: T) -> Option<usize> {
+        assert!(self.find(line_addr).is_none());
+        let idx = self.entries.iter().position(|e| e.is_none())?;
+        self.entries[idx] = Some(Mshr { line_addr, targets: vec![target] });
+        Some(idx)
+    }
+
+    /// Merge a secondary miss into an existing entry.
+    /// Returns the target when the entry cannot accept another request.
+    pub fn merge(&mut self, idx: usize, target: T) -> Result<(), T> {
+        let mshr = self.entries[idx].as_mut().unwrap();
+        if mshr.targets.len() == self.max_targets {
+            return Err(target);
+        }
+        mshr.targets.push(target);
+        Ok(())
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Mshr<T>> {
+        self.entries[idx].as_ref()
+    }
+
+    /// Release an entry, returning the merged requests.
+    pub fn free(&mut self, idx: usize) -> Mshr<T> {
+        self.entries[idx].take().unwrap()
     }
 }
 
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CFG: CacheConfig = CacheConfig {
+        sets: 2, ways: 4, line_size: 16, latency: 1, mshrs: 1, mshr_targets: 1,
+    };
+
+    /// Fill set 0, then return the evicted addresses after
+    /// reusing the first line and scanning through `scan`.
+    fn evictions(arr: &mut CacheArray, scan: &[usize]) -> Vec<usize> {
+        let line = [0u8; 16];
+        for addr in [0x00, 0x20, 0x40, 0x60] {
+            arr.fill(addr, &line);
+        }
+        let way = arr.lookup(0x00).unwrap();
+        arr.touch(0, way);
+        scan.iter().filter_map(|addr| arr.fill(*addr, &line).1)
+            .map(|e| e.addr).collect()
+    }
+
+    #[test]
+    fn cache_array_replacement() {
+        let scan = [0x80, 0xa0, 0xc0];
 
+        // LRU evicts in order of use, skipping the reused line
+        let mut arr = CacheArray::new(CFG);
+        assert_eq!(evictions(&mut arr, &scan), vec![0x20, 0x40, 0x60]);
 
+        // Invalidated lines are replaced first
+        let mut arr = CacheArray::new(CFG);
+        for addr in [0x00, 0x20, 0x40, 0x60] {
+            arr.fill(addr, &[0u8; 16]);
+        }
+        let way = arr.lookup(0x40).unwrap();
+        arr.invalidate(0x40).unwrap();
+        assert_eq!(arr.victim(0), way);
+
+        let mut arr = CacheArray::new(CFG)
+            .with_replacement(RripPolicy::srrip(CFG.sets, CFG.ways));
+        assert_eq!(evictions(&mut arr, &scan), vec![0x20, 0x40, 0x60]);
+        assert!(arr.lookup(0x00).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't match")]
+    fn cache_array_replacement_mismatch() {
+        let _ = CacheArray::new(CFG).with_replacement(LruPolicy::new(1, 4));
+    }
+}
@@ -0,0 +1,415 @@
+//! Non-blocking L1 data cache.
+//!
+//! This is a write-back, write-allocate cache. Misses are tracked with
+//! miss status holding registers (MSHRs), and later misses to a line that
+//! is already being fetched are merged into the existing MSHR.
+//!
+//! A load/store unit drives one request per cycle with [L1DCache::drive_req]
+//! and samples completed requests with [L1DCache::sample_resp]. The next
+//! level of the hierarchy is attached with [link].
+
+use std::collections::*;
+
+use crate::lle::*;
+use crate::hle::mem::*;
+use crate::hle::cache::*;
+use crate::lle::repl::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum L1Op {
+    Load,
+    Store,
+}
+
+/// A request from a load/store unit.
+#[derive(Clone, Copy, Debug)]
+pub struct L1Req {
+    /// Tag chosen by the requester
+    pub id: usize,
+    pub op: L1Op,
+    pub addr: usize,
+    /// Access size in bytes (at most 8, and naturally aligned)
+    pub size: usize,
+    /// Store data
+    pub data: u64,
+}
+impl L1Req {
+    pub fn load(id: usize, addr: usize, size: usize) -> Self {
+        Self { id, op: L1Op::Load, addr, size, data: 0 }
+    }
+    pub fn store(id: usize, addr: usize, size: usize, data: u64) -> Self {
+        Self { id, op: L1Op::Store, addr, size, data }
+    }
+}
+
+/// A completed request. Stores are acknowledged with no data.
+#[derive(Clone, Copy, Debug)]
+pub struct L1Resp {
+    pub id: usize,
+    pub op: L1Op,
+    pub addr: usize,
+    /// Load data
+    pub data: u64,
+}
+
+/// Event counters for an [L1DCache].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct L1Stats {
+    pub hits: usize,
+    /// Misses which allocated an MSHR
+    pub misses: usize,
+    /// Misses which were merged into an existing MSHR
+    pub merged: usize,
+    /// Dirty lines written back to the next level
+    pub writebacks: usize,
+    /// Lines invalidated by the next level
+    pub invalidations: usize,
+    /// Cycles where a request could not be accepted
+    pub blocked: usize,
+}
+
+pub struct L1DCache {
+    cfg: CacheConfig,
+    array: CacheArray,
+    mshr: MshrFile<L1Req>,
+    cycle: usize,
+    /// Notify the next level when clean lines are evicted
+    evict_clean: bool,
+
+    /// Request driven by the load/store unit
+    req: Option<L1Req>,
+    /// Hit responses in flight (and the cycle they become visible)
+    hit_pipe: VecDeque<(usize, L1Resp)>,
+    /// Responses visible during the current cycle
+    resp: Vec<L1Resp>,
+
+    /// Responses from the next level
+    fills: VecDeque<MemResp>,
+    /// Invalidations from the next level
+    invals: VecDeque<(usize, usize)>,
+    /// Requests waiting to be sent to the next level
+    mem_out: VecDeque<MemReq>,
+
+    stats: L1Stats,
+}
+impl L1DCache {
+    pub fn new(cfg: CacheConfig) -> Self {
+        Self {
+            cfg,
+            array: CacheArray::new(cfg),
+            mshr: MshrFile::new(cfg.mshrs, cfg.mshr_targets),
+            cycle: 0,
+            evict_clean: false,
+            req: None,
+            hit_pipe: VecDeque::new(),
+            resp: Vec::new(),
+            fills: VecDeque::new(),
+            invals: VecDeque::new(),
+            mem_out: VecDeque::new(),
+            stats: L1Stats::default(),
+        }
+    }
+    /// Send [MemOp::Evict] to the next level when a clean line is evicted
+    /// (ie. when the next level is an exclusive cache).
+    pub fn with_clean_evictions(mut self) -> Self {
+        self.evict_clean = true;
+        self
+    }
+    /// Use a different replacement policy (see [CacheArray::with_replacement]).
+    pub fn with_replacement(mut self, repl: impl ReplacementPolicy + 'static)
+        -> Self
+    {
+        self.array = self.array.with_replacement(repl);
+        self
+    }
+    pub fn config(&self) -> &CacheConfig { &self.cfg }
+    pub fn stats(&self) -> &L1Stats { &self.stats }
+
+    /// Returns true if the line containing `addr` is present.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.array.lookup(addr).is_some()
+    }
+
+    /// Returns the number of outstanding misses.
+    pub fn num_outstanding(&self) -> usize { self.mshr.num_used() }
+
+    /// Returns true when a new request can be driven this cycle.
+    ///
+    /// A request which misses when no MSHR is available is held (and the
+    /// cache is not ready) until it can be accepted.
+    pub fn ready(&self) -> bool { self.req.is_none() }
+
+    /// Drive a request from the load/store unit.
+    pub fn drive_req(&mut self, req: L1Req) {
+        assert!(self.ready(), "L1D request driven while not ready");
+        assert!(req.size.is_power_of_two() && req.size <= 8);
+        assert!(req.addr & (req.size - 1) == 0, "Misaligned L1D request");
+        self.req = Some(req);
+    }
+
+    /// Sample the requests completed on the previous clock edge.
+    pub fn sample_resp(&self) -> &[L1Resp] { &self.resp }
+
+    /// Perform a request on a line that is present in the cache.
+    fn access(&mut self, set: usize, way: usize, req: &L1Req) -> L1Resp {
+        let off = self.cfg.line_offset(req.addr);
+        let line = self.array.line_mut(set, way);
+        let bytes = &mut line.data[off..off + req.size];
+        let data = match req.op {
+            L1Op::Load => {
+                let mut buf = [0u8; 8];
+                buf[..req.size].copy_from_slice(bytes);
+                u64::from_le_bytes(buf)
+            },
+            L1Op::Store => {
+                bytes.copy_from_slice(&req.data.to_le_bytes()[..req.size]);
+                line.dirty = true;
+                0
+            },
+        };
+        self.array.touch(set, way);
+        L1Resp { id: req.id, op: req.op, addr: req.addr, data }
+    }
+
+    /// Send an evicted line to the next level (if necessary).
+    fn handle_eviction(&mut self, evicted: Eviction) {
+        if evicted.dirty {
+            self.stats.writebacks += 1;
+            self.mem_out.push_back(
+                MemReq::write(0, evicted.addr, evicted.data)
+            );
+        } else if self.evict_clean {
+            self.mem_out.push_back(
+                MemReq::evict(0, evicted.addr, evicted.data)
+            );
+        }
+    }
+
+    /// Install a line returned from the next level and complete all of the
+    /// requests waiting for it.
+    fn handle_fill(&mut self, fill: MemResp) {
+        let line_addr = self.mshr.get(fill.id).unwrap().line_addr;
+        assert!(line_addr == fill.addr);
+        let (way, evicted) = self.array.fill(line_addr, &fill.data);
+        if let Some(evicted) = evicted {
+            self.handle_eviction(evicted);
+        }
+        let set = self.cfg.set_index(line_addr);
+        let mshr = self.mshr.free(fill.id);
+        for target in mshr.targets.iter() {
+            let resp = self.access(set, way, target);
+            self.resp.push(resp);
+        }
+    }
+
+    /// Try to accept the pending request.
+    /// Returns false if the request must be held until a later cycle.
+    fn handle_req(&mut self, req: L1Req) -> bool {
+        let line_addr = self.cfg.line_addr(req.addr);
+        assert!(self.cfg.line_addr(req.addr + req.size - 1) == line_addr);
+
+        if let Some(way) = self.array.lookup(req.addr) {
+            let set = self.cfg.set_index(req.addr);
+            let resp = self.access(set, way, &req);
+            let done = self.cycle + self.cfg.latency - 1;
+            self.hit_pipe.push_back((done, resp));
+            self.stats.hits += 1;
+            return true;
+        }
+
+        if let Some(idx) = self.mshr.find(line_addr) {
+            if self.mshr.merge(idx, req).is_ok() {
+                self.stats.merged += 1;
+                return true;
+            }
+            return false;
+        }
+
+        if let Some(idx) = self.mshr.alloc(line_addr, req) {
+            self.mem_out.push_back(
+                MemReq::read(idx, line_addr, self.cfg.line_size)
+            );
+            self.stats.misses += 1;
+            return true;
+        }
+        false
+    }
+}
+
+impl Clocked for L1DCache {
+    fn update(&mut self) {
+        self.cycle += 1;
+        self.resp.clear();
+
+        // Invalidated lines are written back if they were dirty. 
+        // Clean lines are dropped without notifying the next level. 
+        while let Some((addr, len)) = self.invals.pop_front() {
+            let start = self.cfg.line_addr(addr);
+            for line_addr in (start..addr + len).step_by(self.cfg.line_size) {
+                if let Some(evicted) = self.array.invalidate(line_addr) {
+                    self.stats.invalidations += 1;
+                    if evicted.dirty {
+                        self.stats.writebacks += 1;
+                        self.mem_out.push_back(
+                            MemReq::write(0, evicted.addr, evicted.data)
+                        );
+                    }
+                }
+            }
+        }
+
+        while let Some(fill) = self.fills.pop_front() {
+            self.handle_fill(fill);
+        }
+
+        if let Some(req) = self.req.take() {
+            if !self.handle_req(req) {
+                self.stats.blocked += 1;
+                self.req = Some(req);
+            }
+        }
+
+        while let Some((done, _)) = self.hit_pipe.front() {
+            if *done > self.cycle {
+                break;
+            }
+            let (_, resp) = self.hit_pipe.pop_front().unwrap();
+            self.resp.push(resp);
+        }
+    }
+}
+
+impl MemClient for L1DCache {
+    fn peek_req(&self) -> Option<&MemReq> { self.mem_out.front() }
+    fn take_req(&mut self) -> Option<MemReq> { self.mem_out.pop_front() }
+    fn drive_resp(&mut self, resp: MemResp) {
+        // Writebacks are acknowledged, but there's nothing to do
+        if resp.op == MemOp::Read {
+            self.fills.push_back(resp);
+        }
+    }
+    fn drive_inval(&mut self, addr: usize, len: usize) {
+        self.invals.push_back((addr, len));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CFG: CacheConfig = CacheConfig {
+        sets: 4, ways: 2, line_size: 32, latency: 2,
+        mshrs: 2, mshr_targets: 4,
+    };
+
+    /// Simulate a single cycle, returning the responses for that cycle.
+    fn step(l1: &mut L1DCache, ram: &mut Ram) -> Vec<L1Resp> {
+        link(0, l1, ram);
+        l1.update();
+        ram.update();
+        l1.sample_resp().to_vec()
+    }
+
+    /// Drive a request and wait for it to complete.
+    fn run(l1: &mut L1DCache, ram: &mut Ram, req: L1Req) -> (usize, L1Resp) {
+        l1.drive_req(req);
+        for cyc in 1..32 {
+            let resp = step(l1, ram);
+            if let Some(r) = resp.iter().find(|r| r.id == req.id) {
+                return (cyc, *r);
+            }
+        }
+        panic!("request {} never completed", req.id);
+    }
+
+    #[test]
+    fn l1d_miss_then_hit() {
+        let mut ram = Ram::new(0x1000);
+        ram.write_bytes(0x100, &0xdeadbeefu32.to_le_bytes());
+        let mut l1 = L1DCache::new(CFG);
+
+        let (miss_lat, r) = run(&mut l1, &mut ram, L1Req::load(0, 0x100, 4));
+        assert_eq!(r.data, 0xdeadbeef);
+        let (hit_lat, r) = run(&mut l1, &mut ram, L1Req::load(1, 0x102, 2));
+        assert_eq!(r.data, 0xdead);
+        assert_eq!(hit_lat, CFG.latency);
+        assert!(miss_lat > hit_lat);
+        assert_eq!(l1.stats().misses, 1);
+        assert_eq!(l1.stats().hits, 1);
+    }
+
+    #[test]
+    fn l1d_secondary_miss_merge() {
+        let mut ram = Ram::new(0x1000);
+        ram.write_bytes(0x200, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut l1 = L1DCache::new(CFG);
+
+        l1.drive_req(L1Req::load(0, 0x200, 1));
+        let mut resp = step(&mut l1, &mut ram);
+        l1.drive_req(L1Req::store(1, 0x204, 1, 0xff));
+        resp.extend(step(&mut l1, &mut ram));
+        l1.drive_req(L1Req::load(2, 0x204, 1));
+        for _ in 0..4 {
+            resp.extend(step(&mut l1, &mut ram));
+        }
+        // The store is merged, and the line is present for the last load
+        assert_eq!(l1.stats().misses, 1);
+        assert_eq!(l1.stats().merged, 1);
+        assert_eq!(l1.stats().hits, 1);
+
+        // Merged requests complete in order
+        let ids: Vec<usize> = resp.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(resp[0].data, 1);
+        assert_eq!(resp[2].data, 0xff);
+    }
+
+    #[test]
+    fn l1d_dirty_writeback() {
+        let mut ram = Ram::new(0x1000);
+        let mut l1 = L1DCache::new(CFG);
+        let stride = CFG.sets * CFG.line_size;
+
+        run(&mut l1, &mut ram, L1Req::store(0, 0x0, 4, 0xcafef00d));
+        // Evict the dirty line by filling the rest of the set
+        for way in 1..=CFG.ways {
+            run(&mut l1, &mut ram, L1Req::load(way, way * stride, 4));
+        }
+        step(&mut l1, &mut ram);
+        assert_eq!(l1.stats().writebacks, 1);
+
+        let mut buf = [0u8; 4];
+        ram.read_bytes(0x0, &mut buf);
+        assert_eq!(u32::from_le_bytes(buf), 0xcafef00d);
+
+        // The line is refetched with the written-back data
+        let (_, r) = run(&mut l1, &mut ram, L1Req::load(9, 0x0, 4));
+        assert_eq!(r.data, 0xcafef00d);
+    }
+
+    #[test]
+    fn l1d_mshr_limit() {
+        let mut ram = Ram::new(0x1000);
+        let mut l1 = L1DCache::new(CFG);
+
+        // Fill all MSHRs, then the next miss must be held
+        for id in 0..CFG.mshrs {
+            l1.drive_req(L1Req::load(id, id * CFG.line_size, 4));
+            l1.update();
+        }
+        assert_eq!(l1.num_outstanding(), CFG.mshrs);
+        l1.drive_req(L1Req::load(9, 0x400, 4));
+        l1.update();
+        assert!(!l1.ready());
+        assert_eq!(l1.stats().blocked, 1);
+
+        // The held request is accepted after the misses are serviced
+        let mut done = Vec::new();
+        for _ in 0..8 {
+            done.extend(step(&mut l1, &mut ram).iter().map(|r| r.id));
+        }
+        assert!(l1.ready());
+        assert_eq!(done, vec![0, 1, 9]);
+    }
+}
+
@@ -0,0 +1,470 @@
+//! Shared L2 cache.
+//!
+//! The L2 is a [MemDevice] with one port for each upstream cache, and a
+//! [MemClient] for the next level (ie. memory). Requests from all ports
+//! share a single queue, and one request is handled on each cycle.
+//!
+//! Upstream reads may be smaller than an L2 line (for instance, a 32-byte
+//! fetch block), but they must not cross an L2 line.
+//!
+//! The relationship between the contents of the L2 and the upstream caches
+//! is selected with [InclusionPolicy]:
+//!
+//! - [InclusionPolicy::Inclusive]: Lines are allocated when they are read.
+//!   When a line is evicted, the line is also invalidated in all upstream
+//!   caches (a "back-invalidation").
+//!
+//! - [InclusionPolicy::Exclusive]: Lines are only allocated when they are
+//!   evicted by an upstream cache (which must use
+//!   [L1DCache::with_clean_evictions]). A line that hits in the L2 is moved
+//!   to the upstream cache. Upstream line size must be the same as the L2.
+//!
+//! - [InclusionPolicy::Nine]: "Non-inclusive, non-exclusive." Lines are
+//!   allocated when they are read, but evictions are not propagated.
+//!
+//! Upstream writebacks that miss are allocated (when exclusive) or sent
+//! to the next level.
+
+use std::collections::*;
+
+use crate::lle::*;
+use crate::hle::mem::*;
+use crate::hle::cache::*;
+use crate::lle::repl::*;
+use crate::hle::cache::l1d::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InclusionPolicy {
+    Inclusive,
+    Exclusive,
+    Nine,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct L2Config {
+    pub cache: CacheConfig,
+    pub policy: InclusionPolicy,
+    /// Number of upstream ports
+    pub ports: usize,
+    /// Number of entries in the shared request queue
+    pub queue_depth: usize,
+}
+
+/// Event counters for an [L2Cache].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct L2Stats {
+    pub hits: usize,
+    /// Misses which allocated an MSHR
+    pub misses: usize,
+    /// Misses which were merged into an existing MSHR
+    pub merged: usize,
+    /// Dirty lines written back to the next level
+    pub writebacks: usize,
+    /// Invalidations sent to upstream caches
+    pub back_invals: usize,
+    /// Cycles where the request at the head of the queue was not handled
+    pub blocked: usize,
+}
+
+pub struct L2Cache {
+    cfg: L2Config,
+    array: CacheArray,
+    mshr: MshrFile<MemReq>,
+    cycle: usize,
+
+    /// Requests from all upstream ports
+    input: VecDeque<MemReq>,
+    /// Responses in flight (and the cycle they become visible)
+    pending: VecDeque<(usize, MemResp)>,
+    /// Responses for each upstream port
+    resp: Vec<VecDeque<MemResp>>,
+    /// Invalidations for each upstream port
+    inval: Vec<VecDeque<(usize, usize)>>,
+
+    /// Responses from the next level
+    fills: VecDeque<MemResp>,
+    /// Requests waiting to be sent to the next level
+    mem_out: VecDeque<MemReq>,
+
+    stats: L2Stats,
+}
+impl L2Cache {
+    pub fn new(cfg: L2Config) -> Self {
+        assert!(cfg.ports != 0 && cfg.queue_depth != 0);
+        Self {
+            cfg,
+            array: CacheArray::new(cfg.cache),
+            mshr: MshrFile::new(cfg.cache.mshrs, cfg.cache.mshr_targets),
+            cycle: 0,
+            input: VecDeque::new(),
+            pending: VecDeque::new(),
+            resp: (0..cfg.ports).map(|_| VecDeque::new()).collect(),
+            inval: (0..cfg.ports).map(|_| VecDeque::new()).collect(),
+            fills: VecDeque::new(),
+            mem_out: VecDeque::new(),
+            stats: L2Stats::default(),
+        }
+    }
+    /// Use a different replacement policy (see [CacheArray::with_replacement]).
+    pub fn with_replacement(mut self, repl: impl ReplacementPolicy + 'static)
+        -> Self
+    {
+        self.array = self.array.with_replacement(repl);
+        self
+    }
+    pub fn config(&self) -> &L2Config { &self.cfg }
+    pub fn stats(&self) -> &L2Stats { &self.stats }
+
+    /// Returns true if the line containing `addr` is present.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.array.lookup(addr).is_some()
+    }
+
+    /// Schedule a response to an upstream request.
+    fn respond(&mut self, req: &MemReq, data: Vec<u8>, latency: usize) {
+        let done = self.cycle + latency - 1;
+        self.pending.push_back((done, MemResp::from_req(req, data)));
+    }
+
+    fn writeback(&mut self, addr: usize, data: Vec<u8>) {
+        self.stats.writebacks += 1;
+        self.mem_out.push_back(MemReq::write(0, addr, data));
+    }
+
+    /// Handle a line evicted from the L2.
+    fn handle_eviction(&mut self, evicted: Eviction) {
+        if self.cfg.policy == InclusionPolicy::Inclusive {
+            for port in 0..self.cfg.ports {
+                self.stats.back_invals += 1;
+                self.inval[port].push_back(
+                    (evicted.addr, self.cfg.cache.line_size)
+                );
+            }
+        }
+        if evicted.dirty {
+            self.writeback(evicted.addr, evicted.data);
+        }
+    }
+
+    /// Allocate a line, handling any eviction.
+    fn install(&mut self, addr: usize, data: &[u8], dirty: bool) {
+        let (way, evicted) = self.array.fill(addr, data);
+        let set = self.cfg.cache.set_index(addr);
+        self.array.line_mut(set, way).dirty = dirty;
+        if let Some(evicted) = evicted {
+            self.handle_eviction(evicted);
+        }
+    }
+
+    /// Install a line returned from the next level and respond to all of
+    /// the requests waiting for it.
+    fn handle_fill(&mut self, fill: MemResp) {
+        let mshr = self.mshr.free(fill.id);
+        assert!(mshr.line_addr == fill.addr);
+        if self.cfg.policy != InclusionPolicy::Exclusive {
+            self.install(fill.addr, &fill.data, false);
+        }
+        for target in mshr.targets.iter() {
+            let off = self.cfg.cache.line_offset(target.addr);
+            let data = fill.data[off..off + target.len].to_vec();
+            self.respond(target, data, 1);
+        }
+    }
+
+    fn handle_read(&mut self, req: &MemReq) -> bool {
+        let line_addr = self.cfg.cache.line_addr(req.addr);
+        let set = self.cfg.cache.set_index(req.addr);
+        let off = self.cfg.cache.line_offset(req.addr);
+
+        if let Some(way) = self.array.lookup(req.addr) {
+            let data = self.array.line(set, way).data[off..off + req.len]
+                .to_vec();
+            self.array.touch(set, way);
+            self.respond(req, data, self.cfg.cache.latency);
+            self.stats.hits += 1;
+
+            // The line moves to the upstream cache.
+            if self.cfg.policy == InclusionPolicy::Exclusive {
+                let evicted = self.array.evict(set, way).unwrap();
+                if evicted.dirty {
+                    self.writeback(evicted.addr, evicted.data);
+                }
+            }
+            return true;
+        }
+
+        if let Some(idx) = self.mshr.find(line_addr) {
+            if self.mshr.merge(idx, req.clone()).is_ok() {
+                self.stats.merged += 1;
+                return true;
+            }
+            return false;
+        }
+
+        if let Some(idx) = self.mshr.alloc(line_addr, req.clone()) {
+            self.mem_out.push_back(
+                MemReq::read(idx, line_addr, self.cfg.cache.line_size)
+            );
+            self.stats.misses += 1;
+            return true;
+        }
+        false
+    }
+
+    fn handle_write(&mut self, req: &MemReq) -> bool {
+        let line_addr = self.cfg.cache.line_addr(req.addr);
+        let set = self.cfg.cache.set_index(req.addr);
+        let off = self.cfg.cache.line_offset(req.addr);
+
+        // Wait for an outstanding miss on this line to complete.
+        if self.mshr.find(line_addr).is_some() {
+            return false;
+        }
+
+        if let Some(way) = self.array.lookup(req.addr) {
+            let line = self.array.line_mut(set, way);
+            line.data[off..off + req.len].copy_from_slice(&req.data);
+            line.dirty = true;
+            self.array.touch(set, way);
+        }
+        else if self.cfg.policy == InclusionPolicy::Exclusive &&
+            req.len == self.cfg.cache.line_size
+        {
+            self.install(req.addr, &req.data, true);
+        }
+        else {
+            self.mem_out.push_back(
+                MemReq::write(0, req.addr, req.data.clone())
+            );
+        }
+        self.respond(req, Vec::new(), self.cfg.cache.latency);
+        true
+    }
+
+    fn handle_evict(&mut self, req: &MemReq) -> bool {
+        let line_addr = self.cfg.cache.line_addr(req.addr);
+        if self.mshr.find(line_addr).is_some() {
+            return false;
+        }
+        if self.cfg.policy == InclusionPolicy::Exclusive &&
+            self.array.lookup(req.addr).is_none()
+        {
+            assert!(req.len == self.cfg.cache.line_size);
+            self.install(req.addr, &req.data, false);
+        }
+        self.respond(req, Vec::new(), self.cfg.cache.latency);
+        true
+    }
+
+    fn handle_req(&mut self, req: &MemReq) -> bool {
+        let line_addr = self.cfg.cache.line_addr(req.addr);
+        assert!(self.cfg.cache.line_addr(req.addr + req.len - 1) == line_addr,
+            "L2 request crosses a line");
+        if self.cfg.policy == InclusionPolicy::Exclusive {
+            assert!(req.len == self.cfg.cache.line_size,
+                "Exclusive L2 requires upstream lines of the same size");
+        }
+        match req.op {
+            MemOp::Read  => self.handle_read(req),
+            MemOp::Write => self.handle_write(req),
+            MemOp::Evict => self.handle_evict(req),
+        }
+    }
+}
+
+impl Clocked for L2Cache {
+    fn update(&mut self) {
+        self.cycle += 1;
+
+        while let Some(fill) = self.fills.pop_front() {
+            self.handle_fill(fill);
+        }
+
+        if let Some(req) = self.input.pop_front() {
+            if !self.handle_req(&req) {
+                self.stats.blocked += 1;
+                self.input.push_front(req);
+            }
+        }
+
+        while let Some((done, _)) = self.pending.front() {
+            if *done > self.cycle {
+                break;
+            }
+            let (_, resp) = self.pending.pop_front().unwrap();
+            self.resp[resp.src].push_back(resp);
+        }
+    }
+}
+
+impl MemDevice for L2Cache {
+    fn ready(&self, src: usize) -> bool {
+        assert!(src < self.cfg.ports);
+        self.input.len() < self.cfg.queue_depth
+    }
+    fn drive_req(&mut self, req: MemReq) {
+        assert!(self.ready(req.src));
+        self.input.push_back(req);
+    }
+    fn take_resp(&mut self, src: usize) -> Option<MemResp> {
+        self.resp[src].pop_front()
+    }
+    fn take_inval(&mut self, src: usize) -> Option<(usize, usize)> {
+        self.inval[src].pop_front()
+    }
+}
+
+impl MemClient for L2Cache {
+    fn peek_req(&self) -> Option<&MemReq> { self.mem_out.front() }
+    fn take_req(&mut self) -> Option<MemReq> { self.mem_out.pop_front() }
+    fn drive_resp(&mut self, resp: MemResp) {
+        if resp.op == MemOp::Read {
+            self.fills.push_back(resp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const L1_CFG: CacheConfig = CacheConfig {
+        sets: 8, ways: 4, line_size: 32, latency: 1,
+        mshrs: 4, mshr_targets: 4,
+    };
+
+    fn l2_cfg(policy: InclusionPolicy, line_size: usize) -> L2Config {
+        L2Config {
+            cache: CacheConfig {
+                sets: 1, ways: 2, line_size, latency: 4,
+                mshrs: 4, mshr_targets: 4,
+            },
+            policy,
+            ports: 1,
+            queue_depth: 4,
+        }
+    }
+
+    struct Hierarchy {
+        l1: L1DCache,
+        l2: L2Cache,
+        ram: Ram,
+    }
+    impl Hierarchy {
+        fn new(l1: L1DCache, l2: L2Config) -> Self {
+            let mut ram = Ram::new(0x10000);
+            for addr in (0..0x1000).step_by(4) {
+                ram.write_bytes(addr, &(addr as u32).to_le_bytes());
+            }
+            Self { l1, l2: L2Cache::new(l2), ram }
+        }
+        fn step(&mut self) -> Vec<L1Resp> {
+            link(0, &mut self.l1, &mut self.l2);
+            link(0, &mut self.l2, &mut self.ram);
+            self.l1.update();
+            self.l2.update();
+            self.ram.update();
+            self.l1.sample_resp().to_vec()
+        }
+        fn load(&mut self, id: usize, addr: usize) -> u64 {
+            self.l1.drive_req(L1Req::load(id, addr, 4));
+            for _ in 0..64 {
+                if let Some(r) = self.step().iter().find(|r| r.id == id) {
+                    return r.data;
+                }
+            }
+            panic!("load {} never completed", id);
+        }
+        fn settle(&mut self) {
+            for _ in 0..16 {
+                self.step();
+            }
+        }
+    }
+
+    #[test]
+    fn l2_inclusive_back_invalidation() {
+        let l1 = L1DCache::new(L1_CFG);
+        let mut h = Hierarchy::new(l1, l2_cfg(InclusionPolicy::Inclusive, 32));
+
+        // Three lines in the same L2 set: the first is evicted from the L2
+        for (id, addr) in [0x000, 0x100, 0x200].iter().enumerate() {
+            assert_eq!(h.load(id, *addr), *addr as u64);
+        }
+        h.settle();
+        assert!(!h.l2.contains(0x000));
+        assert!(!h.l1.contains(0x000));
+        assert!(h.l1.contains(0x100) && h.l1.contains(0x200));
+        assert_eq!(h.l1.stats().invalidations, 1);
+    }
+
+    #[test]
+    fn l2_inclusive_dirty_back_invalidation() {
+        let l1 = L1DCache::new(L1_CFG);
+        let mut h = Hierarchy::new(l1, l2_cfg(InclusionPolicy::Inclusive, 32));
+
+        h.l1.drive_req(L1Req::store(0, 0x000, 4, 0x1234_5678));
+        h.settle();
+        h.load(1, 0x100);
+        h.load(2, 0x200);
+        h.settle();
+
+        // The dirty L1 line was written back after being invalidated
+        let mut buf = [0u8; 4];
+        h.ram.read_bytes(0x000, &mut buf);
+        assert_eq!(u32::from_le_bytes(buf), 0x1234_5678);
+        assert_eq!(h.load(3, 0x000), 0x1234_5678);
+    }
+
+    #[test]
+    fn l2_nine_no_back_invalidation() {
+        let l1 = L1DCache::new(L1_CFG);
+        let mut h = Hierarchy::new(l1, l2_cfg(InclusionPolicy::Nine, 32));
+
+        for (id, addr) in [0x000, 0x100, 0x200].iter().enumerate() {
+            h.load(id, *addr);
+        }
+        h.settle();
+        assert!(!h.l2.contains(0x000));
+        assert!(h.l1.contains(0x000));
+        assert_eq!(h.l2.stats().back_invals, 0);
+    }
+
+    #[test]
+    fn l2_exclusive() {
+        // A direct-mapped L1, so that lines are evicted into the L2
+        let l1 = L1DCache::new(CacheConfig { sets: 1, ways: 1, ..L1_CFG })
+            .with_clean_evictions();
+        let mut h = Hierarchy::new(l1, l2_cfg(InclusionPolicy::Exclusive, 32));
+
+        h.load(0, 0x000);
+        h.settle();
+        assert!(h.l1.contains(0x000) && !h.l2.contains(0x000));
+
+        h.load(1, 0x100);
+        h.settle();
+        assert!(h.l1.contains(0x100) && !h.l2.contains(0x100));
+        assert!(h.l2.contains(0x000));
+
+        // Hit in the L2, and the line moves back to the L1
+        assert_eq!(h.load(2, 0x000), 0);
+        h.settle();
+        assert_eq!(h.l2.stats().hits, 1);
+        assert!(h.l1.contains(0x000) && !h.l2.contains(0x000));
+        assert!(h.l2.contains(0x100));
+    }
+
+    #[test]
+    fn l2_larger_lines() {
+        let l1 = L1DCache::new(L1_CFG);
+        let mut h = Hierarchy::new(l1, l2_cfg(InclusionPolicy::Inclusive, 128));
+
+        // Four L1 lines are covered by a single L2 line
+        for (id, addr) in [0x00, 0x20, 0x40, 0x60].iter().enumerate() {
+            assert_eq!(h.load(id, *addr), *addr as u64);
+        }
+        assert_eq!(h.l2.stats().misses, 1);
+        assert_eq!(h.l2.stats().hits, 3);
+    }
+}
+
@@ -0,0 +1,340 @@
+//! Main memory timing model.
+//!
+//! [Dram] is a [MemDevice] which stores data in a [Ram] and approximates
+//! the latency of a DRAM device with some number of banks, each with a
+//! single row buffer. Rows are left open after an access ("open-page").
+//!
+//! The latency of a request depends on the state of the row buffer in the
+//! target bank:
+//!
+//! - Row hit:      `t_cas`
+//! - Row closed:   `t_rcd + t_cas`
+//! - Row conflict: `t_rp + t_rcd + t_cas`
+//!
+//! Each access is followed by a burst of `t_burst` cycles on a data bus
+//! that is shared by all banks.
+//!
+//! Pending requests are scheduled with FR-FCFS ("first-ready, first-come
+//! first-serve"): the oldest request that hits in an open row is issued
+//! before any older requests, otherwise the oldest request is issued.
+//! At most one request is issued each cycle.
+
+use std::collections::*;
+
+use crate::lle::*;
+use crate::hle::mem::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DramConfig {
+    /// Number of banks
+    pub banks: usize,
+    /// Size of a row (in bytes)
+    pub row_size: usize,
+    /// Column access latency
+    pub t_cas: usize,
+    /// Row activation latency
+    pub t_rcd: usize,
+    /// Precharge latency
+    pub t_rp: usize,
+    /// Number of cycles occupying the data bus for each access
+    pub t_burst: usize,
+    /// Number of entries in the request queue
+    pub queue_depth: usize,
+}
+impl DramConfig {
+    /// Returns the bank and row containing `addr`.
+    ///
+    /// Consecutive rows are interleaved across banks.
+    pub fn decode(&self, addr: usize) -> (usize, usize) {
+        let row = addr / self.row_size;
+        (row % self.banks, row / self.banks)
+    }
+}
+
+/// Event counters for a [Dram].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DramStats {
+    pub row_hits: usize,
+    pub row_closed: usize,
+    pub row_conflicts: usize,
+    /// Completed requests
+    pub requests: usize,
+    /// Sum of the latency of all completed requests (including queueing)
+    pub total_latency: usize,
+}
+impl DramStats {
+    /// Returns the average latency of a completed request.
+    pub fn avg_latency(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.total_latency as f64 / self.requests as f64
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Bank {
+    /// The row held in the row buffer
+    open_row: Option<usize>,
+    /// The first cycle where a new access can be issued
+    ready_at: usize,
+}
+
+struct Pending {
+    req: MemReq,
+    /// The cycle this request was accepted
+    arrival: usize,
+}
+
+pub struct Dram {
+    cfg: DramConfig,
+    storage: Ram,
+    banks: Vec<Bank>,
+    cycle: usize,
+    /// The first cycle where the data bus is unused
+    bus_free_at: usize,
+
+    queue: Vec<Pending>,
+    /// Issued requests (and the cycle they complete)
+    in_flight: Vec<(usize, usize, MemResp)>,
+    resp: VecDeque<MemResp>,
+
+    stats: DramStats,
+}
+impl Dram {
+    pub fn new(size: usize, cfg: DramConfig) -> Self {
+        assert!(cfg.banks != 0 && cfg.queue_depth != 0);
+        assert!(cfg.row_size.is_power_of_two());
+        Self {
+            cfg,
+            storage: Ram::new(size),
+            banks: vec![Bank::default(); cfg.banks],
+            cycle: 0,
+            bus_free_at: 0,
+            queue: Vec::new(),
+            in_flight: Vec::new(),
+            resp: VecDeque::new(),
+            stats: DramStats::default(),
+        }
+    }
+    pub fn config(&self) -> &DramConfig { &self.cfg }
+    pub fn stats(&self) -> &DramStats { &self.stats }
+
+    /// Direct access to the underlying storage (ie. for loading programs).
+    pub fn storage(&self) -> &Ram { &self.storage }
+    pub fn storage_mut(&mut self) -> &mut Ram { &mut self.storage }
+
+    /// Select the next request to issue.
+    fn schedule(&self) -> Option<usize> {
+        let mut oldest = None;
+        for (idx, p) in self.queue.iter().enumerate() {
+            let (bank, row) = self.cfg.decode(p.req.addr);
+            let b = &self.banks[bank];
+            if b.ready_at > self.cycle {
+                continue;
+            }
+            if b.open_row == Some(row) {
+                return Some(idx);
+            }
+            if oldest.is_none() {
+                oldest = Some(idx);
+            }
+        }
+        oldest
+    }
+
+    fn issue(&mut self, idx: usize) {
+        let p = self.queue.remove(idx);
+        let (bank, row) = self.cfg.decode(p.req.addr);
+        let b = &mut self.banks[bank];
+
+        let latency = match b.open_row {
+            Some(open) if open == row => {
+                self.stats.row_hits += 1;
+                self.cfg.t_cas
+            },
+            Some(_) => {
+                self.stats.row_conflicts += 1;
+                self.cfg.t_rp + self.cfg.t_rcd + self.cfg.t_cas
+            },
+            None => {
+                self.stats.row_closed += 1;
+                self.cfg.t_rcd + self.cfg.t_cas
+            },
+        };
+        b.open_row = Some(row);
+
+        let start = usize::max(self.cycle + latency, self.bus_free_at);
+        let done = start + self.cfg.t_burst;
+        self.bus_free_at = done;
+        b.ready_at = start;
+
+        // The data is moved when the request is issued.
+        let data = match p.req.op {
+            MemOp::Read => {
+                let mut data = vec![0u8; p.req.len];
+                self.storage.read_bytes(p.req.addr, &mut data);
+                data
+            },
+            MemOp::Write => {
+                self.storage.write_bytes(p.req.addr, &p.req.data);
+                Vec::new()
+            },
+            MemOp::Evict => Vec::new(),
+        };
+        self.in_flight.push((done, p.arrival, MemResp::from_req(&p.req, data)));
+    }
+}
+
+impl Clocked for Dram {
+    fn update(&mut self) {
+        self.cycle += 1;
+
+        let cycle = self.cycle;
+        let mut idx = 0;
+        while idx < self.in_flight.len() {
+            if self.in_flight[idx].0 <= cycle {
+                let (_, arrival, resp) = self.in_flight.remove(idx);
+                self.stats.requests += 1;
+                self.stats.total_latency += cycle - arrival;
+                self.resp.push_back(resp);
+            } else {
+                idx += 1;
+            }
+        }
+
+        if let Some(idx) = self.schedule() {
+            self.issue(idx);
+        }
+    }
+}
+
+impl MemDevice for Dram {
+    fn ready(&self, src: usize) -> bool {
+        self.queue.len() < self.cfg.queue_depth
+    }
+    fn drive_req(&mut self, req: MemReq) {
+        assert!(self.ready(req.src));
+        // Evictions of clean lines are acknowledged without an access
+        if req.op == MemOp::Evict {
+            self.resp.push_back(MemResp::from_req(&req, Vec::new()));
+            return;
+        }
+        self.queue.push(Pending { req, arrival: self.cycle });
+    }
+    fn take_resp(&mut self, src: usize) -> Option<MemResp> {
+        let idx = self.resp.iter().position(|r| r.src == src)?;
+        self.resp.remove(idx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hle::bus::*;
+
+    const CFG: DramConfig = DramConfig {
+        banks: 4, row_size: 0x400,
+        t_cas: 10, t_rcd: 10, t_rp: 10, t_burst: 4,
+        queue_depth: 8,
+    };
+
+    /// Run until all requests have completed, returning the ids in the
+    /// order they completed (and the cycle they completed).
+    fn drain(dram: &mut Dram, num: usize) -> Vec<(usize, usize)> {
+        let mut res = Vec::new();
+        for cyc in 1..1000 {
+            dram.update();
+            while let Some(resp) = dram.take_resp(0) {
+                res.push((resp.id, cyc));
+            }
+            if res.len() == num {
+                return res;
+            }
+        }
+        panic!("requests never completed");
+    }
+
+    #[test]
+    fn dram_row_states() {
+        let mut dram = Dram::new(0x10000, CFG);
+        let (row_a, row_b) = (0x0000, CFG.banks * CFG.row_size);
+
+        dram.drive_req(MemReq::read(0, row_a, 32));
+        let closed = drain(&mut dram, 1)[0].1;
+        dram.drive_req(MemReq::read(1, row_a + 32, 32));
+        let hit = drain(&mut dram, 1)[0].1;
+        dram.drive_req(MemReq::read(2, row_b, 32));
+        let conflict = drain(&mut dram, 1)[0].1;
+
+        // (Requests are issued on the first clock edge after they arrive)
+        assert_eq!(hit, 1 + CFG.t_cas + CFG.t_burst);
+        assert_eq!(closed, 1 + CFG.t_rcd + CFG.t_cas + CFG.t_burst);
+        assert_eq!(conflict, 1 + CFG.t_rp + CFG.t_rcd + CFG.t_cas + CFG.t_burst);
+        assert_eq!(dram.stats().row_hits, 1);
+        assert_eq!(dram.stats().row_closed, 1);
+        assert_eq!(dram.stats().row_conflicts, 1);
+    }
+
+    #[test]
+    fn dram_frfcfs() {
+        let mut dram = Dram::new(0x10000, CFG);
+        let row_b = CFG.banks * CFG.row_size;
+
+        // Open a row in bank 0
+        dram.drive_req(MemReq::read(0, 0x0000, 32));
+        drain(&mut dram, 1);
+
+        // The older conflicting request is issued after the row hit
+        dram.drive_req(MemReq::read(1, row_b, 32));
+        dram.drive_req(MemReq::read(2, 0x0040, 32));
+        let order: Vec<usize> = drain(&mut dram, 2).iter()
+            .map(|(id, _)| *id).collect();
+        assert_eq!(order, vec![2, 1]);
+    }
+
+    #[test]
+    fn dram_readwrite() {
+        let mut dram = Dram::new(0x10000, CFG);
+        dram.drive_req(MemReq::write(0, 0x1000, vec![0xaa; 8]));
+        dram.drive_req(MemReq::read(1, 0x1000, 8));
+        drain(&mut dram, 2);
+
+        dram.drive_req(MemReq::read(2, 0x1000, 8));
+        dram.update();
+        let mut resp = None;
+        for _ in 0..100 {
+            dram.update();
+            if let Some(r) = dram.take_resp(0) {
+                resp = Some(r);
+                break;
+            }
+        }
+        assert_eq!(resp.unwrap().data, vec![0xaa; 8]);
+    }
+
+    #[test]
+    fn dram_on_bus() {
+        let mut bus = Bus::new(1);
+        bus.attach(0x8000_0000, 0x10000, Box::new(Dram::new(0x10000, CFG)));
+        bus.attach(0x0000_0000, 0x1000, Box::new(Ram::new(0x1000)));
+
+        bus.drive_req(MemReq::write(0, 0x8000_0100, vec![1, 2, 3, 4]));
+        bus.drive_req(MemReq::read(1, 0x8000_0100, 4));
+        bus.drive_req(MemReq::read(2, 0x0000_0100, 4));
+
+        let mut resp = Vec::new();
+        for _ in 0..100 {
+            bus.update();
+            while let Some(r) = bus.take_resp(0) {
+                resp.push(r);
+            }
+        }
+        assert_eq!(resp.len(), 3);
+        let r = resp.iter().find(|r| r.id == 1).unwrap();
+        assert_eq!(r.addr, 0x8000_0100);
+        assert_eq!(r.data, vec![1, 2, 3, 4]);
+    }
+}
+
@@ -1,17 +1,131 @@
 
 extern crate goblin;
 use goblin::*;
+use std::collections::*;
+
+use crate::lle::*;
+
+/// The kind of operation carried by a [MemReq].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemOp {
+    /// Read a block of memory.
+    Read,
+    /// Write a block of memory.
+    Write,
+    /// Notify the device that a clean block was evicted by the requester.
+    Evict,
+}
+
+/// A memory transaction sent to a [MemDevice].
+#[derive(Clone, Debug)]
+pub struct MemReq {
+    /// Index of the device port that carried this request (see [link]).
+    pub src: usize,
+    /// Transaction tag chosen by the requester.
+    pub id: usize,
+    pub op: MemOp,
+    pub addr: usize,
+    /// Number of bytes being accessed.
+    pub len: usize,
+    /// Write data (empty for reads).
+    pub data: Vec<u8>,
+}
+impl MemReq {
+    pub fn read(id: usize, addr: usize, len: usize) -> Self {
+        Self { src: 0, id, op: MemOp::Read, addr, len, data: Vec::new() }
+    }
+    pub fn write(id: usize, addr: usize, data: Vec<u8>) -> Self {
+        Self { src: 0, id, op: MemOp::Write, addr, len: data.len(), data }
+    }
+    pub fn evict(id: usize, addr: usize, data: Vec<u8>) -> Self {
+        Self { src: 0, id, op: MemOp::Evict, addr, len: data.len(), data }
+    }
+}
+
+/// A response to a [MemReq]. 
+///
+/// Writes are acknowledged with an empty response.
+#[derive(Clone, Debug)]
+pub struct MemResp {
+    pub src: usize,
+    pub id: usize,
+    pub op: MemOp,
+    pub addr: usize,
+    /// Read data (empty for writes).
+    pub data: Vec<u8>,
+}
+impl MemResp {
+    pub fn from_req(req: &MemReq, data: Vec<u8>) -> Self {
+        Self { src: req.src, id: req.id, op: req.op, addr: req.addr, data }
+    }
+}
+
+/// Interface to a device servicing [MemReq] on behalf of one or more 
+/// requesters. Each requester is connected to a numbered port. 
+pub trait MemDevice {
+    /// Returns true when the device can accept a request from port `src`
+    /// during this cycle.
+    fn ready(&self, src: usize) -> bool;
+
+    /// Drive a request onto port `req.src`.
+    fn drive_req(&mut self, req: MemReq);
+
+    /// Take the oldest completed response for port `src` (if it exists).
+    fn take_resp(&mut self, src: usize) -> Option<MemResp>;
+
+    /// Take the oldest pending invalidation for port `src`, given as an 
+    /// address and a length in bytes. 
+    fn take_inval(&mut self, src: usize) -> Option<(usize, usize)> { None }
+}
+
+/// Interface to the memory-side port of a requester (ie. a cache). 
+pub trait MemClient {
+    /// Get a reference to the oldest outgoing request (if it exists).
+    fn peek_req(&self) -> Option<&MemReq>;
+
+    /// Remove the oldest outgoing request.
+    fn take_req(&mut self) -> Option<MemReq>;
+
+    /// Drive a response from the device.
+    fn drive_resp(&mut self, resp: MemResp);
+
+    /// Drive an invalidation from the device. 
+    fn drive_inval(&mut self, addr: usize, len: usize) {}
+}
+
+/// Move transactions between a requester and port `src` on some device. 
+///
+/// This is expected to be called once per cycle for each connection, 
+/// before the requester and device are updated. 
+pub fn link(src: usize, up: &mut impl MemClient, down: &mut impl MemDevice) {
+    while let Some(resp) = down.take_resp(src) {
+        up.drive_resp(resp);
+    }
+    while let Some((addr, len)) = down.take_inval(src) {
+        up.drive_inval(addr, len);
+    }
+    while up.peek_req().is_some() && down.ready(src) {
+        let mut req = up.take_req().unwrap();
+        req.src = src;
+        down.drive_req(req);
+    }
+}
+
 
 /// Simple random-access memory device. 
+///
+/// When used as a [MemDevice], all requests complete immediately. 
 pub struct Ram {
     data: Vec<u8>,
     size: usize,
+    resp: VecDeque<MemResp>,
 }
 impl Ram {
     pub fn new(size: usize) -> Self {
         Self { 
             data: vec![0u8; size],
             size,
+            resp: VecDeque::new(),
         }
     }
     pub fn read_bytes(&self, off: usize, dst: &mut [u8]) {
@@ -23,6 +137,31 @@ impl Ram {
         self.data[off..(off + src.len())].copy_from_slice(src)
     }
 }
+impl MemDevice for Ram {
+    fn ready(&self, src: usize) -> bool { true }
+    fn drive_req(&mut self, req: MemReq) {
+        let data = match req.op {
+            MemOp::Read => {
+                let mut data = vec![0u8; req.len];
+                self.read_bytes(req.addr, &mut data);
+                data
+            },
+            MemOp::Write => {
+                self.write_bytes(req.addr, &req.data);
+                Vec::new()
+            },
+            MemOp::Evict => Vec::new(),
+        };
+        self.resp.push_back(MemResp::from_req(&req, data));
+    }
+    fn take_resp(&mut self, src: usize) -> Option<MemResp> {
+        let idx = self.resp.iter().position(|r| r.src == src)?;
+        self.resp.remove(idx)
+    }
+}
+impl Clocked for Ram {
+    fn update(&mut self) {}
+}
 
 
 pub fn read_prog(ram: &mut Ram, filename: &'static str) -> usize { 
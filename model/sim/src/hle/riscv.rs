@@ -1,3 +1,4 @@
+use crate::lle::bits::*;
 
 /// RV32I instruction formats.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -216,16 +217,23 @@ impl std::fmt::Display for RvBranchOp {
 /// An architectural register index. 
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct ArchReg(pub u32);
+pub struct ArchReg(pub UInt<5>);
 impl ArchReg {
+    /// The zero register (`x0`)
+    pub const ZERO: Self = Self(UInt::lit(0));
+    /// The return address (`x1`)
+    pub const RA: Self = Self(UInt::lit(1));
+    /// The alternate link register (`x5`)
+    pub const T0: Self = Self(UInt::lit(5));
+
+    #[track_caller]
     pub fn new(idx: u32) -> Self {
-        assert!(idx < 32);
-        Self(idx)
+        Self(UInt::new(idx as u64))
     }
     pub fn is_zero(&self) -> bool {
-        self.0 == 0
+        *self == Self::ZERO
     }
-    pub fn as_usize(&self) -> usize { self.0 as usize }
+    pub fn as_usize(&self) -> usize { self.0.as_usize() }
 }
 impl std::fmt::Display for ArchReg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -282,9 +290,9 @@ impl Instr {
     pub fn is_nop(&self) -> bool {
         match self { 
             Self::Op { rd, rs1, rs2, alu_op } => {
-                *rd == ArchReg(0) && 
-                *rs1 == ArchReg(0) && 
-                *rs2 == ArchReg(0) && 
+                *rd == ArchReg::ZERO && 
+                *rs1 == ArchReg::ZERO && 
+                *rs2 == ArchReg::ZERO && 
                 *alu_op == RvALUOp::Add
             },
             _ => false,
@@ -523,9 +531,9 @@ impl Rv32 {
             Opcode::SYSTEM   => {
                 let f12 = (enc & Rv32::MASK_I_IMM12_20_31) >> 20;
                 match (f12, rs1, rd) { 
-                    (0b0000_0000_0000, ArchReg(0), ArchReg(0)) => 
+                    (0b0000_0000_0000, ArchReg::ZERO, ArchReg::ZERO) => 
                         Instr::Ecall { prv: f3 },
-                    (0b0000_0000_0001, ArchReg(0), ArchReg(0)) => 
+                    (0b0000_0000_0001, ArchReg::ZERO, ArchReg::ZERO) => 
                         Instr::Ebreak { prv: f3 },
                     (_, _, _) => Instr::Illegal(op),
                 }
@@ -587,17 +595,12 @@ impl Default for ImmFormat {
 }
 
 /// RV32I encoded immediate data bits.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct ImmData {
     /// The sign bit
     pub sign: bool,
     /// 19-bit immediate data
-    pub imm19: u32,
-}
-impl Default for ImmData {
-    fn default() -> Self {
-        Self { sign: false, imm19: 0 }
-    }
+    pub imm19: UInt<19>,
 }
 
 impl ImmData {
@@ -606,11 +609,11 @@ impl ImmData {
     fn gen(&self, fmt: ImmFormat) -> u32 {
         match fmt {
             ImmFormat::None => 0,
-            ImmFormat::I => ((self.sign as u32) << 11) | self.imm19,
-            ImmFormat::S => ((self.sign as u32) << 11) | self.imm19,
-            ImmFormat::B => (((self.sign as u32) << 11) | self.imm19) << 1,
-            ImmFormat::U => (((self.sign as u32) << 19) | self.imm19) << 12,
-            ImmFormat::J => (((self.sign as u32) << 19) | self.imm19) << 1,
+            ImmFormat::I => ((self.sign as u32) << 11) | self.imm19.get() as u32,
+            ImmFormat::S => ((self.sign as u32) << 11) | self.imm19.get() as u32,
+            ImmFormat::B => (((self.sign as u32) << 11) | self.imm19.get() as u32) << 1,
+            ImmFormat::U => (((self.sign as u32) << 19) | self.imm19.get() as u32) << 12,
+            ImmFormat::J => (((self.sign as u32) << 19) | self.imm19.get() as u32) << 1,
         }
     }
 
@@ -705,7 +708,7 @@ impl Rv32 {
                  | (((menc & Self::MASK_J_IMM1_31_31) >> 31) << 19))
             },
         };
-        let data = ImmData { sign: sign_bit, imm19: imm };
+        let data = ImmData { sign: sign_bit, imm19: UInt::new(imm as u64) };
         Rv32Imm::new(fmt, data)
     }
 }
@@ -786,17 +789,17 @@ impl Instr {
     pub fn branch_kind(&self) -> BranchKind {
         match self {
             Self::Jalr { rd, rs1, simm } => {
-                match (rd, rs1) {
-                    (ArchReg(0), ArchReg(1)) => BranchKind::Return(*rs1),
-                    (ArchReg(0), ArchReg(5)) => BranchKind::Return(*rs1),
-                    (ArchReg(1), _) => BranchKind::CallIndirect(*rs1),
-                    (ArchReg(5), _) => BranchKind::CallIndirect(*rs1),
+                match (*rd, *rs1) {
+                    (ArchReg::ZERO, ArchReg::RA) => BranchKind::Return(*rs1),
+                    (ArchReg::ZERO, ArchReg::T0) => BranchKind::Return(*rs1),
+                    (ArchReg::RA, _) => BranchKind::CallIndirect(*rs1),
+                    (ArchReg::T0, _) => BranchKind::CallIndirect(*rs1),
                     (_, _) => BranchKind::JmpIndirect(*rs1),
                 }
             },
             Self::Jal { rd, .. } => {
-                match rd {
-                    ArchReg(1) | ArchReg(5) => BranchKind::CallRelative,
+                match *rd {
+                    ArchReg::RA | ArchReg::T0 => BranchKind::CallRelative,
                     _ => BranchKind::JmpRelative,
                 }
             },
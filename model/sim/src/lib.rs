@@ -6,6 +6,9 @@
 #![allow(unreachable_patterns)]
 #![allow(non_upper_case_globals)]
 
+// Allows '#[derive(Clocked)]' to be used within this crate
+extern crate self as sim;
+
 pub mod hle;
 pub mod lle;
 
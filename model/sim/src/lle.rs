@@ -18,68 +18,588 @@
 //! internal state of an object should change at clock edges.
 //!
 //! [ClockedState] is an example of a container used to synchronize updates to 
-//! multiple simulated clocked components.
+//! multiple simulated clocked components. Components that weren't driven 
+//! on a cycle can report this with [Clocked::is_dirty], and their updates 
+//! are skipped.
+//!
+//! Alternatively, a group of components can be declared as a struct with
+//! `#[derive(Clocked)]`, which updates each field on each clock edge (in 
+//! declaration order, unless a field is skipped or given an explicit 
+//! order with `#[clocked(...)]`, see the `sim-derive` crate).
+//!
+//! An [arena::Arena] is an alternative to [ClockedState] which owns its 
+//! components (instead of sharing them with [StateRef]), so that a whole 
+//! simulation can be moved to another thread.
+//!
+//! Combinational logic can be split into blocks which declare the signals 
+//! they read and write, and an [eval::Evaluator] runs them in dependency 
+//! order before each clock edge. A [ClockedState] has its own evaluator 
+//! (see [ClockedState::add_eval]).
+//!
+//! Components with a reset state implement [Resettable], and components
+//! tracked by a [ClockedState] with [ClockedState::track_resettable] can be
+//! reset with [ClockedState::assert_reset].
+//!
+//! Designs with more than one clock can use a [clock::Scheduler] to advance
+//! a [ClockedState] for each clock domain.
+//!
+//! Storage primitives implement [cost::Cost], so that the number of bits
+//! and ports in a design can be collected into a [cost::CostReport].
 //!
 
+pub mod drc;
+pub mod trace;
+pub mod cost;
+pub mod vcd;
+pub mod arena;
+pub mod eval;
+pub mod bits;
 pub mod wire;
 pub mod register;
+pub mod pipereg;
 pub mod mem;
 pub mod syncmem;
 pub mod cam;
+pub mod arbiter;
+pub mod ring;
+pub mod alloc;
+pub mod repl;
 pub mod queue;
+pub mod decoupled;
+pub mod clock;
+pub mod cdc;
 
 use std::fmt::Debug;
 use std::cell::*;
+use std::io::Write;
 use std::rc::*;
 
+use crate::lle::trace::*;
+
+pub use sim_derive::{Clocked, Resettable};
+
 /// Interface to a clocked component. 
 pub trait Clocked { 
     /// Simulate a clock edge, mutating some internal state. 
     fn update(&mut self);
+
+    /// Returns false if [Clocked::update] would do nothing on this cycle
+    /// (ie. nothing was driven since the last clock edge), in which case
+    /// the update may be skipped.
+    ///
+    /// NOTE: Components which change state on every cycle (or aren't sure)
+    /// must return true, which is the default.
+    fn is_dirty(&self) -> bool { true }
 }
 
+impl <T: Clocked, const N: usize> Clocked for [T; N] {
+    fn update(&mut self) {
+        for x in self.iter_mut() {
+            x.update();
+        }
+    }
+    fn is_dirty(&self) -> bool {
+        self.iter().any(|x| x.is_dirty())
+    }
+}
+impl <T: Clocked> Clocked for Vec<T> {
+    fn update(&mut self) {
+        for x in self.iter_mut() {
+            x.update();
+        }
+    }
+    fn is_dirty(&self) -> bool {
+        self.iter().any(|x| x.is_dirty())
+    }
+}
+impl <T: Clocked + ?Sized> Clocked for Box<T> {
+    fn update(&mut self) {
+        self.as_mut().update();
+    }
+    fn is_dirty(&self) -> bool {
+        self.as_ref().is_dirty()
+    }
+}
+
+/// Interface to a component with a reset state.
+pub trait Resettable {
+    /// Return to the reset state immediately (an asynchronous reset).
+    /// Anything driven on the current cycle is discarded.
+    fn reset(&mut self);
+}
+
+impl <T: Resettable, const N: usize> Resettable for [T; N] {
+    fn reset(&mut self) {
+        for x in self.iter_mut() {
+            x.reset();
+        }
+    }
+}
+impl <T: Resettable> Resettable for Vec<T> {
+    fn reset(&mut self) {
+        for x in self.iter_mut() {
+            x.reset();
+        }
+    }
+}
+impl <T: Resettable + ?Sized> Resettable for Box<T> {
+    fn reset(&mut self) {
+        self.as_mut().reset();
+    }
+}
+
+/// When a reset takes effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetKind {
+    /// Immediately
+    Async,
+    /// At the next clock edge (instead of the usual update)
+    Sync,
+}
+
+/// A clocked component with a reset state.
+pub trait Component: Clocked + Resettable {}
+impl <T: Clocked + Resettable + ?Sized> Component for T {}
+
 /// A shared mutable reference to some clocked component.
 pub type StateRef<T> = Rc<RefCell<T>>;
 
+/// Counts of component updates performed by a [ClockedState].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ActivityStats {
+    /// Number of calls to [Clocked::update]
+    pub updates: usize,
+    /// Number of updates skipped because a component wasn't dirty
+    pub skipped: usize,
+}
+
 /// A container for components sharing the same clock signal. 
 ///
-/// NOTE: Are there situations where the *order* of updates should matter? 
-/// I guess we're leaving that to the user.
+/// On each clock edge, only components which are dirty (see
+/// [Clocked::is_dirty]) are updated. 
+///
+/// Before each clock edge, blocks of combinational logic added with
+/// [ClockedState::add_eval] are run in dependency order (the *eval* phase).
+///
+/// NOTE: Components are updated in the order they were tracked. Anything 
+/// that depends on ordering within a cycle belongs in the eval phase (see
+/// [ClockedState::add_eval] and [eval::Evaluator]).
+///
+/// NOTE: Components are shared with [StateRef] (which isn't [Send]), so 
+/// they're always updated on the current thread. See [arena::Arena] for 
+/// parallel updates.
 ///
 pub struct ClockedState {
     cycle: usize,
+    /// Update every component on each clock edge (even if it isn't dirty).
+    update_all: bool,
+    activity: ActivityStats,
     /// The set of clocked components being tracked.
     components: Vec<StateRef<dyn Clocked>>,
+    /// The subset of components which are reset with this container.
+    resettable: Vec<StateRef<dyn Resettable>>,
+    /// Set when a synchronous reset is asserted for the next clock edge.
+    reset_pending: bool,
+    /// Named components (which can be traced).
+    traced: Vec<(String, StateRef<dyn Trace>)>,
+    /// Combinational logic run before each clock edge.
+    eval: eval::Evaluator<()>,
+    /// Waveform output (and the name of the top-level scope).
+    vcd: Option<(String, VcdWriter<Box<dyn Write>>)>,
 }
 impl ClockedState { 
     /// Create a new clock domain. 
     pub fn new() -> Self { 
         Self { 
             cycle: 0,
+            update_all: false,
+            activity: ActivityStats::default(),
             components: Vec::new(),
+            resettable: Vec::new(),
+            reset_pending: false,
+            traced: Vec::new(),
+            eval: eval::Evaluator::new(),
+            vcd: None,
         }
     }
 
+    /// Sample all traced values with `vcd` on each update, where `path` is 
+    /// the name of the top-level scope.
+    pub fn trace_vcd(mut self, path: &str, vcd: VcdWriter<Box<dyn Write>>) 
+        -> Self 
+    {
+        self.vcd = Some((path.to_string(), vcd));
+        self
+    }
+
+    /// Update every component on each clock edge, ignoring
+    /// [Clocked::is_dirty] (for debugging components which don't report
+    /// their activity correctly).
+    pub fn with_update_all(mut self) -> Self {
+        self.update_all = true;
+        self
+    }
+
     /// Clone a [Clocked] object, tracking it in this container. 
+    ///
+    /// NOTE: The object is *not* reset with this container (see 
+    /// [ClockedState::track_resettable]).
     pub fn track<T>(&mut self, obj: &StateRef<T>)
         where T: Clocked + 'static
     {
         self.components.push(obj.clone());
     }
 
+    /// Like [ClockedState::track], but the object is also reset with this
+    /// container (see [ClockedState::assert_reset]).
+    pub fn track_resettable<T>(&mut self, obj: &StateRef<T>)
+        where T: Clocked + Resettable + 'static
+    {
+        self.components.push(obj.clone());
+        self.resettable.push(obj.clone());
+    }
+
+    /// Like [ClockedState::track], but also give the object a name in the
+    /// hierarchy so that it can be traced. 
+    ///
+    /// Other instances of [ClockedState] can be tracked this way, in which 
+    /// case the names of their components are relative to `name`. 
+    pub fn track_named<T>(&mut self, name: &str, obj: &StateRef<T>)
+        where T: Clocked + Trace + 'static
+    {
+        self.components.push(obj.clone());
+        self.traced.push((name.to_string(), obj.clone()));
+    }
+
+    /// Like [ClockedState::track_named], but the object is also reset with
+    /// this container.
+    pub fn track_named_resettable<T>(&mut self, name: &str, obj: &StateRef<T>)
+        where T: Clocked + Resettable + Trace + 'static
+    {
+        self.track_named(name, obj);
+        self.resettable.push(obj.clone());
+    }
+
+    /// Add a block of combinational logic named `name`, which samples the
+    /// signals in `reads` and drives the signals in `writes` (see
+    /// [eval::Evaluator::add]). Blocks are run before each clock edge,
+    /// after any blocks they depend on. 
+    ///
+    /// NOTE: The clock edge panics if the blocks have a combinational loop
+    /// (see [eval::Evaluator::eval]).
+    pub fn add_eval(&mut self, name: &'static str,
+        reads: &[&'static str], writes: &[&'static str],
+        mut f: impl FnMut() + 'static)
+    {
+        self.eval.add(name, reads, writes, move |_| f());
+    }
+
     pub fn cycle(&self) -> usize {
         self.cycle
     }
+
+    /// Returns the number of component updates performed (and skipped) so
+    /// far. This does not include nested containers.
+    pub fn activity(&self) -> ActivityStats {
+        self.activity
+    }
+
+    /// Reset all components tracked with [ClockedState::track_resettable]
+    /// (or [ClockedState::track_named_resettable]).
+    ///
+    /// NOTE: The cycle count is *not* reset. 
+    pub fn assert_reset(&mut self, kind: ResetKind) {
+        match kind {
+            ResetKind::Async => self.reset(),
+            ResetKind::Sync => self.reset_pending = true,
+        }
+    }
 }
 
 impl Clocked for ClockedState {
-    // Update all tracked components.
+    // Run the combinational logic, then update all tracked components 
+    // (which are dirty).
     fn update(&mut self) {
-        for entry in self.components.iter() {
-            entry.borrow_mut().update()
+        let _drc = drc::CycleScope::enter(self.cycle);
+        self.eval.eval(&mut ());
+        // Values are sampled before the clock edge
+        if let Some((path, mut vcd)) = self.vcd.take() {
+            vcd.sample(self.cycle, &path, self).expect("VCD write failed");
+            self.vcd = Some((path, vcd));
+        }
+        if self.reset_pending {
+            self.reset();
+        } else {
+            for entry in self.components.iter() {
+                let mut c = entry.borrow_mut();
+                if self.update_all || c.is_dirty() {
+                    c.update();
+                    self.activity.updates += 1;
+                } else {
+                    self.activity.skipped += 1;
+                }
+            }
         }
         self.cycle += 1;
     }
 }
 
+impl Resettable for ClockedState {
+    fn reset(&mut self) {
+        for entry in self.resettable.iter() {
+            entry.borrow_mut().reset()
+        }
+        self.reset_pending = false;
+    }
+}
 
+impl Trace for ClockedState {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        for (name, obj) in self.traced.iter() {
+            obj.borrow().trace(&format!("{}.{}", path, name), t);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::register::*;
+    use crate::lle::wire::*;
+    use crate::lle::queue::*;
+    use crate::lle::mem::*;
+
+    #[derive(Clocked, Resettable)]
+    struct Pipeline<const N: usize> {
+        stages: [Reg<usize>; N],
+        out: Queue<usize>,
+        #[clocked(skip)]
+        #[reset(skip)]
+        name: &'static str,
+    }
+
+    /// Records the order in which it was updated.
+    struct Probe(&'static str, StateRef<Vec<&'static str>>);
+    impl Clocked for Probe {
+        fn update(&mut self) { self.1.borrow_mut().push(self.0); }
+    }
+
+    #[derive(Clocked)]
+    struct Ordered(
+        Probe,
+        #[clocked(order = 1)] Probe,
+        #[clocked(order = -1)] Probe,
+        Probe,
+    );
+
+    #[test]
+    fn derive_clocked_fields() {
+        let mut p = Pipeline::<3> {
+            stages: std::array::from_fn(|_| Reg::new(0)),
+            out: Queue::new(),
+            name: "pipeline",
+        };
+        for cyc in 1..=5 {
+            p.out.enq(p.stages[2].sample());
+            p.stages[2].drive(p.stages[1].sample());
+            p.stages[1].drive(p.stages[0].sample());
+            p.stages[0].drive(cyc);
+            p.update();
+        }
+        let out: Vec<usize> = p.out.data.iter().copied().collect();
+        assert_eq!(out, vec![0, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn derive_clocked_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut o = Ordered(
+            Probe("a", log.clone()), Probe("b", log.clone()),
+            Probe("c", log.clone()), Probe("d", log.clone()),
+        );
+        o.update();
+        assert_eq!(*log.borrow(), vec!["c", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn derive_resettable() {
+        let mut p = Pipeline::<2> {
+            stages: std::array::from_fn(|_| Reg::new(7)),
+            out: Queue::new(),
+            name: "pipeline",
+        };
+        p.stages[0].drive(1);
+        p.out.enq(1);
+        p.update();
+        p.stages[1].drive(2);
+        p.reset();
+        p.update();
+        assert_eq!(p.stages[0].sample(), 7);
+        assert_eq!(p.stages[1].sample(), 7);
+        assert!(p.out.data.is_empty());
+        assert_eq!(p.name, "pipeline");
+    }
+
+    /// Count up to 'n', then enqueue the count.
+    fn count(state: &mut ClockedState, r: &StateRef<Reg<u32>>, 
+        q: &StateRef<Queue<u32>>, n: u32) 
+    {
+        for _ in 0..n {
+            let x = r.borrow().sample();
+            r.borrow_mut().drive(x + 1);
+            q.borrow_mut().enq(x);
+            state.update();
+        }
+    }
+
+    #[test]
+    fn reset_async() {
+        let mut state = ClockedState::new();
+        let r = Rc::new(RefCell::new(Reg::new(10u32)));
+        let q = Rc::new(RefCell::new(Queue::new()));
+        state.track_resettable(&r);
+        state.track_resettable(&q);
+        count(&mut state, &r, &q, 3);
+        assert_eq!(r.borrow().sample(), 13);
+
+        // Values driven before the reset are discarded
+        r.borrow_mut().drive(100);
+        q.borrow_mut().enq(100);
+        state.assert_reset(ResetKind::Async);
+        assert_eq!(r.borrow().sample(), 10);
+        assert!(q.borrow().data.is_empty());
+        state.update();
+        assert_eq!(r.borrow().sample(), 10);
+        assert!(q.borrow().data.is_empty());
+
+        count(&mut state, &r, &q, 2);
+        assert_eq!(r.borrow().sample(), 12);
+        assert_eq!(q.borrow().data, [10, 11]);
+        assert_eq!(state.cycle(), 6);
+    }
+
+    #[test]
+    fn reset_sync() {
+        let mut state = ClockedState::new();
+        let r = Rc::new(RefCell::new(Reg::new(10u32)));
+        let q = Rc::new(RefCell::new(Queue::new()));
+        state.track_resettable(&r);
+        state.track_resettable(&q);
+        count(&mut state, &r, &q, 3);
+
+        // Nothing changes until the clock edge
+        state.assert_reset(ResetKind::Sync);
+        assert_eq!(r.borrow().sample(), 13);
+        count(&mut state, &r, &q, 1);
+        assert_eq!(r.borrow().sample(), 10);
+        assert!(q.borrow().data.is_empty());
+
+        // The reset is only asserted for a single cycle
+        count(&mut state, &r, &q, 1);
+        assert_eq!(r.borrow().sample(), 11);
+        assert_eq!(q.borrow().data, [10]);
+    }
+
+    #[test]
+    fn reset_untracked() {
+        // Components without a reset state can still be tracked
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let probe = Rc::new(RefCell::new(Probe("p", log.clone())));
+        let r = Rc::new(RefCell::new(Reg::new(10u32)));
+        let mut state = ClockedState::new();
+        state.track(&probe);
+        state.track(&r);
+        r.borrow_mut().drive(1);
+        state.update();
+        state.assert_reset(ResetKind::Async);
+        assert_eq!(r.borrow().sample(), 1);
+        state.update();
+        assert_eq!(*log.borrow(), vec!["p", "p"]);
+    }
+
+    #[test]
+    fn eval_phase() {
+        let pc = Rc::new(RefCell::new(Reg::new(0u32)));
+        let fetch = Rc::new(RefCell::new(Wire::new().named("fetch")));
+        let out = Rc::new(RefCell::new(Reg::new(0u32)));
+        let mut state = ClockedState::new();
+        state.track(&pc);
+        state.track(&fetch);
+        state.track(&out);
+
+        // Added from the back of the pipeline to the front
+        let (f, o) = (fetch.clone(), out.clone());
+        state.add_eval("writeback", &["fetch"], &[], move || {
+            o.borrow_mut().drive(f.borrow().sample() * 2);
+        });
+        let (p, f) = (pc.clone(), fetch.clone());
+        state.add_eval("fetch", &[], &["fetch"], move || {
+            let x = p.borrow().sample();
+            f.borrow_mut().drive(x);
+            p.borrow_mut().drive(x + 1);
+        });
+        for _ in 0..4 {
+            state.update();
+        }
+        assert_eq!(pc.borrow().sample(), 4);
+        assert_eq!(out.borrow().sample(), 6);
+    }
+
+    #[test]
+    fn activity_skip_idle() {
+        let mut state = ClockedState::new();
+        let r = Rc::new(RefCell::new(Reg::new(0u32)));
+        let p = Rc::new(RefCell::new(Pipeline::<2> {
+            stages: std::array::from_fn(|_| Reg::new(0)),
+            out: Queue::new(),
+            name: "pipeline",
+        }));
+        state.track(&r);
+        state.track(&p);
+
+        state.update();
+        assert_eq!(state.activity(), ActivityStats { updates: 0, skipped: 2 });
+
+        // Driving any field makes the whole struct dirty
+        p.borrow_mut().stages[1].drive(5);
+        r.borrow_mut().drive(1);
+        state.update();
+        assert_eq!(state.activity(), ActivityStats { updates: 2, skipped: 2 });
+        assert_eq!(p.borrow().stages[1].sample(), 5);
+        assert!(!p.borrow().is_dirty());
+
+        let mut state = ClockedState::new().with_update_all();
+        state.track(&r);
+        state.update();
+        assert_eq!(state.activity().updates, 1);
+    }
+
+    /// Compare the time spent on a mostly-idle design, with and without
+    /// skipping idle components. 
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn activity_speedup() {
+        let run = |state: ClockedState| {
+            let mut state = state;
+            let mems: Vec<StateRef<Mem<u64, 64>>> = (0..256)
+                .map(|_| Rc::new(RefCell::new(Mem::new_init_val(0)))).collect();
+            for m in mems.iter() {
+                state.track(m);
+            }
+            let start = std::time::Instant::now();
+            for cyc in 0..10_000 {
+                // Only one component is driven on each cycle
+                mems[cyc % mems.len()].borrow_mut().drive(0, cyc as u64);
+                state.update();
+            }
+            start.elapsed()
+        };
+        let all = run(ClockedState::new().with_update_all());
+        let dirty = run(ClockedState::new());
+        println!("update all: {:?}, dirty only: {:?}", all, dirty);
+        assert!(dirty < all);
+    }
+}
@@ -0,0 +1,268 @@
+//! Allocators for a fixed set of entries.
+//!
+//! A [BitAllocator] keeps one bit per entry (set when the entry is free).
+//! On each cycle, a chain of `K` priority encoders selects the `K` free
+//! entries with the lowest indices, and up to `J` entries can be freed.
+//! This is the usual way to manage physical registers, ROB entries, or
+//! immediate storage in hardware.
+//!
+//! [BitAllocator::snapshot] and [BitAllocator::drive_restore] are used to
+//! recover from a misprediction by returning all entries allocated after
+//! the snapshot (ie. on the wrong path).
+
+use crate::lle::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
+
+/// A copy of the free entries in a [BitAllocator].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllocSnapshot {
+    free: Vec<u64>,
+}
+
+/// An allocator for `SZ` entries, with `K` allocations and `J` frees per
+/// cycle.
+#[derive(Clone)]
+pub struct BitAllocator<const SZ: usize, const K: usize, const J: usize> {
+    /// One bit for each entry (set when free)
+    free: Vec<u64>,
+    /// The reset state
+    init: Vec<u64>,
+
+    /// Number of grants (from [BitAllocator::sample_alcs]) taken on this
+    /// cycle
+    alc: usize,
+    /// Entries freed on this cycle
+    dealc: [Option<usize>; J],
+    /// Snapshot restored on this cycle
+    restore: Option<AllocSnapshot>,
+}
+impl <const SZ: usize, const K: usize, const J: usize> BitAllocator<SZ, K, J> {
+    /// Create an allocator where all entries are free.
+    pub fn new() -> Self {
+        assert!(SZ != 0 && K != 0 && K <= SZ);
+        let mut free = vec![u64::MAX; SZ.div_ceil(64)];
+        if !SZ.is_multiple_of(64) {
+            *free.last_mut().unwrap() = (1 << (SZ % 64)) - 1;
+        }
+        Self {
+            init: free.clone(),
+            free,
+            alc: 0,
+            dealc: [None; J],
+            restore: None,
+        }
+    }
+
+    /// Mark entry `idx` as allocated (including after a reset), ie. for a
+    /// physical register which is hardwired to zero.
+    pub fn with_allocated(mut self, idx: usize) -> Self {
+        assert!(idx < SZ, "BitAllocator entry {} out of bounds", idx);
+        self.free[idx / 64] &= !(1 << (idx % 64));
+        self.init = self.free.clone();
+        self
+    }
+
+    pub fn capacity(&self) -> usize { SZ }
+
+    /// Returns the number of free entries.
+    pub fn num_free(&self) -> usize {
+        self.free.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Returns true if entry `idx` is free.
+    pub fn is_free(&self, idx: usize) -> bool {
+        assert!(idx < SZ, "BitAllocator entry {} out of bounds", idx);
+        self.free[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    /// Sample the free entries that can be allocated on this cycle (the
+    /// output of each priority encoder, lowest index first).
+    ///
+    /// NOTE: Entries freed on this cycle can't be allocated until the
+    /// next cycle.
+    pub fn sample_alcs(&self) -> [Option<usize>; K] {
+        let mut res = [None; K];
+        let mut out = res.iter_mut();
+        for (widx, word) in self.free.iter().enumerate() {
+            let mut w = *word;
+            while w != 0 {
+                let Some(slot) = out.next() else { return res };
+                *slot = Some(widx * 64 + w.trailing_zeros() as usize);
+                // Clear the lowest set bit for the next encoder
+                w &= w - 1;
+            }
+        }
+        res
+    }
+
+    /// Allocate the first `n` entries from [BitAllocator::sample_alcs] at
+    /// the next clock edge.
+    #[track_caller]
+    pub fn drive_alc(&mut self, n: usize) {
+        assert!(n <= K, "BitAllocator can only allocate {} entries per cycle", K);
+        assert!(n <= self.num_free(), "BitAllocator has {} free entries, need {}",
+            self.num_free(), n);
+        self.alc = n;
+    }
+
+    /// Free entry `idx` at the next clock edge.
+    #[track_caller]
+    pub fn drive_free(&mut self, idx: usize) {
+        assert!(!self.is_free(idx), "BitAllocator entry {} is already free", idx);
+        assert!(!self.dealc.contains(&Some(idx)),
+            "BitAllocator entry {} freed twice", idx);
+        let Some(slot) = self.dealc.iter_mut().find(|x| x.is_none()) else {
+            panic!("BitAllocator can only free {} entries per cycle", J);
+        };
+        *slot = Some(idx);
+    }
+
+    /// Take a snapshot of the free entries (ie. when renaming a branch).
+    pub fn snapshot(&self) -> AllocSnapshot {
+        AllocSnapshot { free: self.free.clone() }
+    }
+
+    /// Free all entries allocated since `snap` was taken at the next clock
+    /// edge. Entries which were freed since then stay free.
+    ///
+    /// Allocations driven on this cycle are discarded (they're on the
+    /// wrong path), but frees still take effect.
+    pub fn drive_restore(&mut self, snap: AllocSnapshot) {
+        assert_eq!(snap.free.len(), self.free.len());
+        self.restore = Some(snap);
+    }
+}
+impl <const SZ: usize, const K: usize, const J: usize> Default
+    for BitAllocator<SZ, K, J>
+{
+    fn default() -> Self { Self::new() }
+}
+impl <const SZ: usize, const K: usize, const J: usize> Clocked
+    for BitAllocator<SZ, K, J>
+{
+    fn update(&mut self) {
+        let alcs = self.sample_alcs();
+        let alc = std::mem::take(&mut self.alc);
+        if let Some(snap) = self.restore.take() {
+            for (w, s) in self.free.iter_mut().zip(snap.free.iter()) {
+                *w |= s;
+            }
+        } else {
+            for idx in alcs.iter().take(alc).flatten() {
+                self.free[idx / 64] &= !(1 << (idx % 64));
+            }
+        }
+        for idx in std::mem::replace(&mut self.dealc, [None; J]).iter().flatten() {
+            self.free[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+    fn is_dirty(&self) -> bool {
+        self.alc != 0 || self.dealc.iter().any(|x| x.is_some())
+            || self.restore.is_some()
+    }
+}
+impl <const SZ: usize, const K: usize, const J: usize> Resettable
+    for BitAllocator<SZ, K, J>
+{
+    fn reset(&mut self) {
+        self.free = self.init.clone();
+        self.alc = 0;
+        self.dealc = [None; J];
+        self.restore = None;
+    }
+}
+/// NOTE: Each priority encoder is counted as a read port, and each
+/// allocation or free as a write port.
+impl <const SZ: usize, const K: usize, const J: usize> Cost
+    for BitAllocator<SZ, K, J>
+{
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("BitAllocator", SZ, 1).with_ports(K, K + J));
+    }
+}
+/// NOTE: Only the number of free entries is traced.
+impl <const SZ: usize, const K: usize, const J: usize> Trace
+    for BitAllocator<SZ, K, J>
+{
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(&format!("{}.num_free", path), 32, Some(self.num_free() as u64));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alloc_lowest_first() {
+        let mut a = BitAllocator::<70, 4, 2>::new().with_allocated(0);
+        assert_eq!(a.num_free(), 69);
+        assert_eq!(a.sample_alcs(), [Some(1), Some(2), Some(3), Some(4)]);
+        a.drive_alc(3);
+        a.update();
+        assert_eq!(a.sample_alcs(), [Some(4), Some(5), Some(6), Some(7)]);
+
+        // Freed entries are available on the next cycle
+        a.drive_free(2);
+        a.drive_alc(4);
+        assert_eq!(a.sample_alcs()[0], Some(4));
+        a.update();
+        assert_eq!(a.sample_alcs()[0], Some(2));
+        assert_eq!(a.num_free(), 69 - 6);
+
+        a.reset();
+        assert_eq!(a.num_free(), 69);
+        assert!(!a.is_free(0));
+    }
+
+    /// Allocation across word boundaries, until the allocator is empty.
+    #[test]
+    fn alloc_exhaust() {
+        let mut a = BitAllocator::<130, 8, 1>::new();
+        let mut seen: Vec<usize> = Vec::new();
+        while a.num_free() != 0 {
+            let n = a.num_free().min(8);
+            seen.extend(a.sample_alcs().iter().take(n).flatten());
+            a.drive_alc(n);
+            a.update();
+        }
+        assert_eq!(seen, (0..130).collect::<Vec<_>>());
+        assert_eq!(a.sample_alcs(), [None; 8]);
+    }
+
+    #[test]
+    fn alloc_restore() {
+        let mut a = BitAllocator::<16, 2, 2>::new();
+        a.drive_alc(2); // 0, 1
+        a.update();
+        let snap = a.snapshot();
+        a.drive_alc(2); // 2, 3 (wrong path)
+        a.update();
+        a.drive_free(0); // retired
+        a.drive_alc(2); // 4, 5 (discarded)
+        a.drive_restore(snap);
+        a.update();
+        assert!(a.is_free(0));
+        assert!(!a.is_free(1));
+        assert_eq!(a.num_free(), 15);
+        assert_eq!(a.sample_alcs(), [Some(0), Some(2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "only free 1 entries per cycle")]
+    fn alloc_too_many_frees() {
+        let mut a = BitAllocator::<4, 2, 1>::new();
+        a.drive_alc(2);
+        a.update();
+        a.drive_free(0);
+        a.drive_free(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already free")]
+    fn alloc_double_free() {
+        let mut a = BitAllocator::<4, 1, 1>::new();
+        a.drive_free(3);
+    }
+}
@@ -0,0 +1,453 @@
+//! Arbiters.
+//!
+//! An arbiter picks up to `G` winners ("grants") among `M` requesters on
+//! each cycle. The requesters drive their request lines with
+//! [Arbiter::drive_req], and [Arbiter::grants] is the combinational result
+//! (which can be sampled any number of times after all requests are
+//! driven). Arbiters with some priority state (ie. [RoundRobinArbiter])
+//! update it at the clock edge, according to the grants on that cycle.
+//!
+//! The number of grants per cycle is 1 by default, and can be changed with
+//! `with_grants()` on each arbiter (ie. when a scheduler issues to more
+//! than one functional unit, or a memory has more than one port).
+
+use crate::lle::*;
+
+/// Interface to an arbiter with `M` requesters.
+pub trait Arbiter<const M: usize>: Clocked + Resettable {
+    /// Assert request line `idx` on this cycle.
+    fn drive_req(&mut self, idx: usize);
+
+    /// Returns the requesters granted on this cycle (in priority order).
+    fn grants(&self) -> Vec<usize>;
+
+    /// Assert all request lines in `req` on this cycle.
+    fn drive_reqs(&mut self, req: &[bool; M]) {
+        for (idx, _) in req.iter().enumerate().filter(|(_, r)| **r) {
+            self.drive_req(idx);
+        }
+    }
+
+    /// Returns true if `idx` is granted on this cycle.
+    fn is_granted(&self, idx: usize) -> bool {
+        self.grants().contains(&idx)
+    }
+}
+
+/// Request lines and the maximum number of grants (shared by all arbiters).
+#[derive(Clone, Copy)]
+struct Requests<const M: usize> {
+    req: [bool; M],
+    max_grants: usize,
+}
+impl <const M: usize> Requests<M> {
+    fn new() -> Self {
+        assert!(M != 0, "Arbiter must have at least one requester");
+        Self { req: [false; M], max_grants: 1 }
+    }
+    fn with_grants(mut self, n: usize) -> Self {
+        assert!(n != 0 && n <= M, "Invalid number of grants {} for {} requesters",
+            n, M);
+        self.max_grants = n;
+        self
+    }
+    #[track_caller]
+    fn drive(&mut self, idx: usize) {
+        assert!(idx < M, "Arbiter request {} out of bounds", idx);
+        self.req[idx] = true;
+    }
+    fn clear(&mut self) {
+        self.req = [false; M];
+    }
+    fn any(&self) -> bool {
+        self.req.iter().any(|r| *r)
+    }
+}
+
+/// Grants the requesters with the lowest indices.
+///
+/// NOTE: This is not fair: high indices can starve.
+#[derive(Clone, Copy)]
+pub struct FixedPriorityArbiter<const M: usize> {
+    req: Requests<M>,
+}
+impl <const M: usize> FixedPriorityArbiter<M> {
+    pub fn new() -> Self {
+        Self { req: Requests::new() }
+    }
+    pub fn with_grants(mut self, n: usize) -> Self {
+        self.req = self.req.with_grants(n);
+        self
+    }
+}
+impl <const M: usize> Default for FixedPriorityArbiter<M> {
+    fn default() -> Self { Self::new() }
+}
+impl <const M: usize> Arbiter<M> for FixedPriorityArbiter<M> {
+    #[track_caller]
+    fn drive_req(&mut self, idx: usize) { self.req.drive(idx); }
+    fn grants(&self) -> Vec<usize> {
+        (0..M).filter(|idx| self.req.req[*idx])
+            .take(self.req.max_grants).collect()
+    }
+}
+impl <const M: usize> Clocked for FixedPriorityArbiter<M> {
+    fn update(&mut self) { self.req.clear(); }
+    fn is_dirty(&self) -> bool { self.req.any() }
+}
+impl <const M: usize> Resettable for FixedPriorityArbiter<M> {
+    fn reset(&mut self) { self.req.clear(); }
+}
+
+/// Grants requesters in circular order, starting after the last requester
+/// granted on a previous cycle.
+#[derive(Clone, Copy)]
+pub struct RoundRobinArbiter<const M: usize> {
+    req: Requests<M>,
+    /// The requester with the highest priority
+    ptr: usize,
+}
+impl <const M: usize> RoundRobinArbiter<M> {
+    pub fn new() -> Self {
+        Self { req: Requests::new(), ptr: 0 }
+    }
+    pub fn with_grants(mut self, n: usize) -> Self {
+        self.req = self.req.with_grants(n);
+        self
+    }
+    /// Returns the requester which currently has the highest priority.
+    pub fn ptr(&self) -> usize { self.ptr }
+}
+impl <const M: usize> Default for RoundRobinArbiter<M> {
+    fn default() -> Self { Self::new() }
+}
+impl <const M: usize> Arbiter<M> for RoundRobinArbiter<M> {
+    #[track_caller]
+    fn drive_req(&mut self, idx: usize) { self.req.drive(idx); }
+    fn grants(&self) -> Vec<usize> {
+        (0..M).map(|off| (self.ptr + off) % M)
+            .filter(|idx| self.req.req[*idx])
+            .take(self.req.max_grants).collect()
+    }
+}
+impl <const M: usize> Clocked for RoundRobinArbiter<M> {
+    fn update(&mut self) {
+        if let Some(last) = self.grants().last() {
+            self.ptr = (last + 1) % M;
+        }
+        self.req.clear();
+    }
+    fn is_dirty(&self) -> bool { self.req.any() }
+}
+impl <const M: usize> Resettable for RoundRobinArbiter<M> {
+    fn reset(&mut self) {
+        self.req.clear();
+        self.ptr = 0;
+    }
+}
+
+/// Grants the oldest requesters, where age is tracked with a matrix of
+/// bits (`older[i][j]` is set when `i` is older than `j`).
+///
+/// A requester becomes the youngest when it's granted, or when it's
+/// (re)allocated with [MatrixArbiter::drive_alloc] (ie. when an issue
+/// queue entry is filled). Without any allocations, this is a
+/// least-recently-granted arbiter.
+#[derive(Clone, Copy)]
+pub struct MatrixArbiter<const M: usize> {
+    req: Requests<M>,
+    older: [[bool; M]; M],
+    /// Requesters allocated on this cycle (in order)
+    alloc: [Option<usize>; M],
+}
+impl <const M: usize> MatrixArbiter<M> {
+    /// Create an arbiter where lower indices are initially older.
+    pub fn new() -> Self {
+        let mut older = [[false; M]; M];
+        for (i, row) in older.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = i < j;
+            }
+        }
+        Self { req: Requests::new(), older, alloc: [None; M] }
+    }
+    pub fn with_grants(mut self, n: usize) -> Self {
+        self.req = self.req.with_grants(n);
+        self
+    }
+
+    /// Make `idx` the youngest requester at the next clock edge.
+    ///
+    /// NOTE: Each requester can only be allocated once on each cycle (so 
+    /// there are never more than `M` allocations).
+    #[track_caller]
+    pub fn drive_alloc(&mut self, idx: usize) {
+        assert!(idx < M, "Arbiter request {} out of bounds", idx);
+        assert!(!self.alloc.contains(&Some(idx)), 
+            "Arbiter request {} allocated twice on the same cycle", idx);
+        let slot = self.alloc.iter_mut().find(|x| x.is_none()).unwrap();
+        *slot = Some(idx);
+    }
+
+    /// Returns true if `i` is older than `j`.
+    pub fn is_older(&self, i: usize, j: usize) -> bool {
+        self.older[i][j]
+    }
+
+    fn make_youngest(&mut self, idx: usize) {
+        for other in 0..M {
+            if other != idx {
+                self.older[idx][other] = false;
+                self.older[other][idx] = true;
+            }
+        }
+    }
+}
+impl <const M: usize> Default for MatrixArbiter<M> {
+    fn default() -> Self { Self::new() }
+}
+impl <const M: usize> Arbiter<M> for MatrixArbiter<M> {
+    #[track_caller]
+    fn drive_req(&mut self, idx: usize) { self.req.drive(idx); }
+    fn grants(&self) -> Vec<usize> {
+        // The age of a requester is the number of requesters younger than it
+        let mut res: Vec<usize> = (0..M).filter(|idx| self.req.req[*idx])
+            .collect();
+        res.sort_by_key(|i| std::cmp::Reverse(
+            self.older[*i].iter().filter(|x| **x).count()
+        ));
+        res.truncate(self.req.max_grants);
+        res
+    }
+}
+impl <const M: usize> Clocked for MatrixArbiter<M> {
+    fn update(&mut self) {
+        for idx in self.grants() {
+            self.make_youngest(idx);
+        }
+        for idx in std::mem::replace(&mut self.alloc, [None; M]).iter().flatten() {
+            self.make_youngest(*idx);
+        }
+        self.req.clear();
+    }
+    fn is_dirty(&self) -> bool {
+        self.req.any() || self.alloc[0].is_some()
+    }
+}
+impl <const M: usize> Resettable for MatrixArbiter<M> {
+    fn reset(&mut self) {
+        let mut req = self.req;
+        req.clear();
+        *self = Self { req, ..Self::new() };
+    }
+}
+
+/// A two-level tree of round-robin arbiters: requesters are split into
+/// groups of `arity` consecutive requesters, and a root arbiter picks
+/// between groups.
+///
+/// Each *group* gets an equal share of grants when all groups are
+/// requesting (regardless of the number of requesters in each group).
+#[derive(Clone)]
+pub struct TreeArbiter<const M: usize> {
+    req: Requests<M>,
+    arity: usize,
+    /// Root pointer (the group with the highest priority)
+    root: usize,
+    /// Pointer for each group (relative to the start of the group)
+    leaf: Vec<usize>,
+}
+impl <const M: usize> TreeArbiter<M> {
+    pub fn new(arity: usize) -> Self {
+        assert!(arity != 0, "TreeArbiter arity must be nonzero");
+        Self {
+            req: Requests::new(),
+            arity,
+            root: 0,
+            leaf: vec![0; M.div_ceil(arity)],
+        }
+    }
+    pub fn with_grants(mut self, n: usize) -> Self {
+        self.req = self.req.with_grants(n);
+        self
+    }
+    pub fn num_groups(&self) -> usize { self.leaf.len() }
+
+    /// Pick winners, returning the grants and the pointers afterwards.
+    fn arbitrate(&self) -> (Vec<usize>, usize, Vec<usize>) {
+        let ngroups = self.num_groups();
+        let mut req = self.req.req;
+        let mut root = self.root;
+        let mut leaf = self.leaf.clone();
+        let mut res = Vec::new();
+        while res.len() < self.req.max_grants {
+            let group_req = |g: usize, req: &[bool; M]| {
+                (g * self.arity..((g + 1) * self.arity).min(M)).any(|i| req[i])
+            };
+            let Some(g) = (0..ngroups).map(|off| (root + off) % ngroups)
+                .find(|g| group_req(*g, &req)) else { break };
+            let base = g * self.arity;
+            let size = self.arity.min(M - base);
+            let off = (0..size).map(|off| (leaf[g] + off) % size)
+                .find(|off| req[base + off]).unwrap();
+            res.push(base + off);
+            req[base + off] = false;
+            leaf[g] = (off + 1) % size;
+            root = (g + 1) % ngroups;
+        }
+        (res, root, leaf)
+    }
+}
+impl <const M: usize> Arbiter<M> for TreeArbiter<M> {
+    #[track_caller]
+    fn drive_req(&mut self, idx: usize) { self.req.drive(idx); }
+    fn grants(&self) -> Vec<usize> {
+        self.arbitrate().0
+    }
+}
+impl <const M: usize> Clocked for TreeArbiter<M> {
+    fn update(&mut self) {
+        let (_, root, leaf) = self.arbitrate();
+        self.root = root;
+        self.leaf = leaf;
+        self.req.clear();
+    }
+    fn is_dirty(&self) -> bool { self.req.any() }
+}
+impl <const M: usize> Resettable for TreeArbiter<M> {
+    fn reset(&mut self) {
+        self.req.clear();
+        self.root = 0;
+        self.leaf.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Drive requests from `reqs` (a function of the cycle) for 'cycles'
+    /// cycles, and return the number of grants for each requester.
+    fn run<const M: usize>(arb: &mut impl Arbiter<M>, cycles: usize,
+        reqs: impl Fn(usize) -> [bool; M]) -> [usize; M]
+    {
+        let mut res = [0; M];
+        for cyc in 0..cycles {
+            arb.drive_reqs(&reqs(cyc));
+            for g in arb.grants() {
+                assert!(reqs(cyc)[g], "granted {} without a request", g);
+                res[g] += 1;
+            }
+            arb.update();
+        }
+        res
+    }
+
+    #[test]
+    fn fixed_priority() {
+        let mut arb = FixedPriorityArbiter::<4>::new().with_grants(2);
+        arb.drive_reqs(&[false, true, true, true]);
+        assert_eq!(arb.grants(), vec![1, 2]);
+        arb.update();
+        // Requester 3 always loses to 0 and 1
+        let res = run(&mut arb, 10, |_| [true, true, false, true]);
+        assert_eq!(res, [10, 10, 0, 0]);
+    }
+
+    #[test]
+    fn round_robin_fair() {
+        let mut arb = RoundRobinArbiter::<5>::new();
+        let res = run(&mut arb, 50, |_| [true; 5]);
+        assert_eq!(res, [10; 5]);
+
+        // Two grants per cycle, with a requester dropping out
+        let mut arb = RoundRobinArbiter::<5>::new().with_grants(2);
+        let res = run(&mut arb, 40, |_| [true, true, false, true, true]);
+        assert_eq!(res, [20, 20, 0, 20, 20]);
+    }
+
+    /// No requester waits for more than M-1 grants to other requesters.
+    #[test]
+    fn round_robin_bounded_wait() {
+        let mut arb = RoundRobinArbiter::<4>::new();
+        let mut waiting = [0usize; 4];
+        for cyc in 0..100 {
+            let req = [true, cyc % 3 == 0, true, cyc % 2 == 0];
+            arb.drive_reqs(&req);
+            let g = arb.grants();
+            for idx in 0..4 {
+                if g.contains(&idx) || !req[idx] {
+                    waiting[idx] = 0;
+                } else {
+                    waiting[idx] += 1;
+                    assert!(waiting[idx] < 4, "requester {} starved", idx);
+                }
+            }
+            arb.update();
+        }
+    }
+
+    #[test]
+    fn matrix_least_recently_granted() {
+        let mut arb = MatrixArbiter::<3>::new();
+        let res = run(&mut arb, 30, |_| [true; 3]);
+        assert_eq!(res, [10; 3]);
+
+        // Requester 0 was granted least recently
+        arb.drive_reqs(&[true, true, false]);
+        assert_eq!(arb.grants(), vec![0]);
+        arb.update();
+        assert!(arb.is_older(1, 0));
+    }
+
+    /// Entries are granted oldest-first (by allocation order), like an
+    /// issue queue.
+    #[test]
+    fn matrix_age_order() {
+        let mut arb = MatrixArbiter::<4>::new().with_grants(2);
+        for idx in [2, 0, 3, 1] {
+            arb.drive_alloc(idx);
+            arb.update();
+        }
+        arb.drive_reqs(&[true; 4]);
+        assert_eq!(arb.grants(), vec![2, 0]);
+        arb.update();
+        arb.drive_reqs(&[true, true, false, true]);
+        assert_eq!(arb.grants(), vec![3, 1]);
+        arb.reset();
+        arb.drive_reqs(&[true; 4]);
+        assert_eq!(arb.grants(), vec![0, 1]);
+    }
+
+    #[test]
+    fn tree_fair_between_groups() {
+        // Groups {0,1,2} and {3,4,5}: requester 3 is alone in its group
+        let mut arb = TreeArbiter::<6>::new(3);
+        assert_eq!(arb.num_groups(), 2);
+        let res = run(&mut arb, 60, |_| [true, true, true, true, false, false]);
+        assert_eq!(res, [10, 10, 10, 30, 0, 0]);
+
+        // Uneven groups, with all requesters active
+        let mut arb = TreeArbiter::<5>::new(2).with_grants(3);
+        let res = run(&mut arb, 40, |_| [true; 5]);
+        assert_eq!(res.iter().sum::<usize>(), 120);
+        assert_eq!(res[4], 40);
+        assert_eq!(res[0] + res[1], 40);
+        assert!(res[0].abs_diff(res[1]) <= 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn arbiter_bad_request() {
+        let mut arb = RoundRobinArbiter::<2>::new();
+        arb.drive_req(2);
+    }
+
+    #[test]
+    #[should_panic(expected = "allocated twice")]
+    fn arbiter_double_alloc() {
+        let mut arb = MatrixArbiter::<2>::new();
+        arb.drive_alloc(1);
+        arb.drive_alloc(1);
+    }
+}
@@ -0,0 +1,608 @@
+//! An arena which owns clocked components.
+//!
+//! Components tracked by a [ClockedState] are shared with [StateRef], so
+//! every access goes through a [RefCell] (where a mis-nested borrow is a
+//! panic at runtime), and nothing can be sent to another thread.
+//!
+//! Instead, an [Arena] owns all of its components, and gives out typed
+//! [Handle]s which are used to access them. Accesses are checked by the
+//! borrow checker (through the arena), and the whole arena is [Send] as
+//! long as all of its components are.
+//!
+//! ```
+//! use sim::lle::*;
+//! use sim::lle::arena::*;
+//! use sim::lle::register::*;
+//!
+//! let mut arena = Arena::new();
+//! let pc = arena.insert(Reg::new(0u32));
+//! for _ in 0..4 {
+//!     let x = arena[pc].sample();
+//!     arena[pc].drive(x + 4);
+//!     arena.update();
+//! }
+//! assert_eq!(arena[pc].sample(), 16);
+//! ```
+//!
+//! Parallel Updates
+//! ================
+//!
+//! Since each component only changes its own state at a clock edge, the
+//! updates of different components are independent and can run in any
+//! order. With [Arena::with_threads], the components are split into
+//! partitions which are updated on separate threads, with the same results
+//! as a sequential update.
+//!
+//! The worker threads are started on the first parallel update, and kept
+//! until the arena is dropped. Each clock edge still costs a round-trip
+//! through a channel for every worker, so this is only worth it when each
+//! partition does a lot of work (ie. large memories and caches), and when
+//! the host actually has a core for each thread. With fewer cores than
+//! threads (or with small partitions), a parallel update is *slower* than
+//! a sequential one.
+//!
+//! NOTE: Components must not share state with each other (ie. with an
+//! `Arc<Mutex<T>>`) or the results may not be deterministic.
+//!
+//! NOTE: A [ClockedState] can't update its components in parallel, since
+//! they're shared with [StateRef] (which isn't [Send]). Instead, an [Arena]
+//! holding the expensive part of a design can be tracked by a
+//! [ClockedState] like any other component.
+
+use std::any::Any;
+use std::fmt;
+use std::hash::{ Hash, Hasher };
+use std::marker::PhantomData;
+use std::ops::{ Index, IndexMut };
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use crate::lle::*;
+use crate::lle::drc::Violation;
+use crate::lle::trace::*;
+
+/// Used to give each [Arena] a unique ID.
+static NEXT_ARENA_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A reference to a component of type `T` owned by an [Arena].
+pub struct Handle<T> {
+    /// ID of the arena which created this handle
+    arena: usize,
+    /// Index of the component in the arena
+    idx: usize,
+    _t: PhantomData<fn() -> T>,
+}
+impl <T> Handle<T> {
+    pub fn index(&self) -> usize { self.idx }
+}
+impl <T> Clone for Handle<T> {
+    fn clone(&self) -> Self { *self }
+}
+impl <T> Copy for Handle<T> {}
+impl <T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.arena == other.arena && self.idx == other.idx
+    }
+}
+impl <T> Eq for Handle<T> {}
+impl <T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.arena, self.idx).hash(state);
+    }
+}
+impl <T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}:{})", self.arena, self.idx)
+    }
+}
+
+/// A component which can be owned by an [Arena].
+pub trait ArenaComponent: Clocked + Any + Send {}
+impl <T: Clocked + Any + Send> ArenaComponent for T {}
+
+/// Traces a component (after casting it back to its concrete type).
+type TraceFn = fn(&dyn Any, &str, &mut dyn Tracer);
+
+/// Resets a component (after casting it back to its concrete type).
+type ResetFn = fn(&mut dyn Any);
+
+struct Entry {
+    obj: Box<dyn ArenaComponent>,
+    /// Name and trace function (for named components)
+    traced: Option<(String, TraceFn)>,
+    /// Reset function (for resettable components)
+    reset: Option<ResetFn>,
+}
+
+/// Update the dirty components in `entries` (in order).
+fn update_entries(entries: &mut [Entry]) -> ActivityStats {
+    let mut res = ActivityStats::default();
+    for entry in entries.iter_mut() {
+        if entry.obj.is_dirty() {
+            entry.obj.update();
+            res.updates += 1;
+        } else {
+            res.skipped += 1;
+        }
+    }
+    res
+}
+
+/// A partition of the entries in an [Arena], sent to a worker thread.
+struct Job {
+    idx: usize,
+    entries: *mut Entry,
+    len: usize,
+    drc: drc::Snapshot,
+}
+// SAFETY: Entries are [Send], and the thread which sends a job waits until
+// it's completed before accessing the entries again (see [WorkerPool::wait]).
+unsafe impl Send for Job {}
+
+type JobResult = std::thread::Result<(ActivityStats, Vec<Violation>)>;
+
+/// Threads which update partitions of an [Arena] (kept across clock edges).
+struct WorkerPool {
+    jobs: Vec<mpsc::Sender<Job>>,
+    results: mpsc::Receiver<(usize, JobResult)>,
+    threads: Vec<JoinHandle<()>>,
+}
+impl WorkerPool {
+    fn new(n: usize) -> Self {
+        let (res_tx, results) = mpsc::channel();
+        let mut jobs = Vec::new();
+        let mut threads = Vec::new();
+        for _ in 0..n {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let res_tx = res_tx.clone();
+            threads.push(std::thread::spawn(move || {
+                while let Ok(job) = rx.recv() {
+                    // SAFETY: See [Job]
+                    let part = unsafe {
+                        std::slice::from_raw_parts_mut(job.entries, job.len)
+                    };
+                    let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        job.drc.install();
+                        (update_entries(part), drc::take_violations())
+                    }));
+                    if res_tx.send((job.idx, res)).is_err() {
+                        break;
+                    }
+                }
+            }));
+            jobs.push(tx);
+        }
+        Self { jobs, results, threads }
+    }
+
+    /// Send each partition to a worker, returning the number of jobs.
+    fn send<'a>(&self, parts: impl Iterator<Item = &'a mut [Entry]>) -> usize {
+        let drc = drc::Snapshot::take();
+        let mut num_jobs = 0;
+        for (idx, part) in parts.enumerate() {
+            let job = Job { idx, entries: part.as_mut_ptr(), len: part.len(), drc };
+            self.jobs[idx].send(job).expect("Arena worker thread exited");
+            num_jobs += 1;
+        }
+        num_jobs
+    }
+
+    /// Wait for `num_jobs` jobs to complete, returning their results in the
+    /// order they were sent.
+    fn wait(&self, num_jobs: usize) -> Vec<JobResult> {
+        let mut res: Vec<Option<JobResult>> = (0..num_jobs).map(|_| None).collect();
+        for _ in 0..num_jobs {
+            let (idx, result) = self.results.recv()
+                .expect("Arena worker thread exited");
+            res[idx] = Some(result);
+        }
+        res.into_iter().map(|x| x.unwrap()).collect()
+    }
+}
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Workers exit when their job channel is closed
+        self.jobs.clear();
+        for t in self.threads.drain(..) {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Owns a set of components sharing the same clock signal.
+///
+/// Like [ClockedState], components are updated in the order they were
+/// inserted, and only when dirty (see [Clocked::is_dirty]). Only components
+/// inserted with [Arena::insert_resettable] (or 
+/// [Arena::insert_named_resettable]) are reset with the arena.
+pub struct Arena {
+    id: usize,
+    cycle: usize,
+    entries: Vec<Entry>,
+    activity: ActivityStats,
+    /// Number of partitions updated in parallel
+    threads: usize,
+    /// Worker threads (started on the first parallel update)
+    pool: Option<WorkerPool>,
+}
+impl Arena {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_ARENA_ID.fetch_add(1, Ordering::Relaxed),
+            cycle: 0,
+            entries: Vec::new(),
+            activity: ActivityStats::default(),
+            threads: 1,
+            pool: None,
+        }
+    }
+
+    /// Split the components into (at most) `n` partitions of consecutive
+    /// components, which are updated in parallel on each clock edge (on
+    /// the current thread, and `n - 1` worker threads).
+    pub fn with_threads(mut self, n: usize) -> Self {
+        assert!(n != 0, "Arena needs at least one thread");
+        self.threads = n;
+        self.pool = None;
+        self
+    }
+
+    /// Move a component into the arena.
+    pub fn insert<T: ArenaComponent>(&mut self, obj: T) -> Handle<T> {
+        self.entries.push(Entry { obj: Box::new(obj), traced: None, reset: None });
+        Handle { arena: self.id, idx: self.entries.len() - 1, _t: PhantomData }
+    }
+
+    /// Like [Arena::insert], but the component is also reset with the arena.
+    pub fn insert_resettable<T>(&mut self, obj: T) -> Handle<T>
+        where T: ArenaComponent + Resettable
+    {
+        let h = self.insert(obj);
+        self.set_resettable(h);
+        h
+    }
+
+    /// Like [Arena::insert], but also give the component a name in the
+    /// hierarchy so that it can be traced.
+    pub fn insert_named<T>(&mut self, name: &str, obj: T) -> Handle<T>
+        where T: ArenaComponent + Trace
+    {
+        let h = self.insert(obj);
+        let trace: TraceFn = |obj, path, t| {
+            obj.downcast_ref::<T>().unwrap().trace(path, t)
+        };
+        self.entries[h.idx].traced = Some((name.to_string(), trace));
+        h
+    }
+
+    /// Like [Arena::insert_named], but the component is also reset with the
+    /// arena.
+    pub fn insert_named_resettable<T>(&mut self, name: &str, obj: T) -> Handle<T>
+        where T: ArenaComponent + Resettable + Trace
+    {
+        let h = self.insert_named(name, obj);
+        self.set_resettable(h);
+        h
+    }
+
+    fn set_resettable<T: ArenaComponent + Resettable>(&mut self, h: Handle<T>) {
+        let reset: ResetFn = |obj| obj.downcast_mut::<T>().unwrap().reset();
+        self.entries[h.idx].reset = Some(reset);
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+    pub fn cycle(&self) -> usize { self.cycle }
+
+    /// Returns the number of component updates performed (and skipped) so
+    /// far (see [ClockedState::activity]).
+    pub fn activity(&self) -> ActivityStats { self.activity }
+
+    #[track_caller]
+    fn check<T>(&self, h: Handle<T>) {
+        assert!(h.arena == self.id, "{:?} belongs to a different Arena", h);
+    }
+
+    #[track_caller]
+    pub fn get<T: ArenaComponent>(&self, h: Handle<T>) -> &T {
+        self.check(h);
+        let obj: &dyn Any = self.entries[h.idx].obj.as_ref();
+        obj.downcast_ref().unwrap()
+    }
+
+    #[track_caller]
+    pub fn get_mut<T: ArenaComponent>(&mut self, h: Handle<T>) -> &mut T {
+        self.check(h);
+        let obj: &mut dyn Any = self.entries[h.idx].obj.as_mut();
+        obj.downcast_mut().unwrap()
+    }
+
+    /// Mutably access two different components at the same time.
+    #[track_caller]
+    pub fn get2_mut<A, B>(&mut self, a: Handle<A>, b: Handle<B>)
+        -> (&mut A, &mut B)
+        where A: ArenaComponent, B: ArenaComponent
+    {
+        self.check(a);
+        self.check(b);
+        assert!(a.idx != b.idx, "{:?} accessed twice", a);
+        let (lo, hi) = self.entries.split_at_mut(a.idx.max(b.idx));
+        let (ea, eb) = if a.idx < b.idx {
+            (&mut lo[a.idx], &mut hi[0])
+        } else {
+            (&mut hi[0], &mut lo[b.idx])
+        };
+        let ea: &mut dyn Any = ea.obj.as_mut();
+        let eb: &mut dyn Any = eb.obj.as_mut();
+        (ea.downcast_mut().unwrap(), eb.downcast_mut().unwrap())
+    }
+}
+impl Arena {
+    /// Update each partition in parallel: the first on the current thread,
+    /// and the others on the worker threads.
+    ///
+    /// Design rule violations are reported on each thread, and then merged
+    /// (in partition order) into the violations for this thread.
+    fn update_parallel(&mut self) -> ActivityStats {
+        let size = self.entries.len().div_ceil(self.threads);
+        let pool = self.pool.get_or_insert_with(|| WorkerPool::new(self.threads - 1));
+        let mut parts = self.entries.chunks_mut(size);
+        let first = parts.next().unwrap();
+        let num_jobs = pool.send(parts);
+
+        // Wait for every worker before unwinding, since they still have
+        // access to the entries
+        let first = std::panic::catch_unwind(AssertUnwindSafe(|| update_entries(first)));
+        let results = pool.wait(num_jobs);
+
+        let mut res = first.unwrap_or_else(|e| std::panic::resume_unwind(e));
+        for result in results {
+            let (activity, violations) = result
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            res.updates += activity.updates;
+            res.skipped += activity.skipped;
+            drc::merge(violations);
+        }
+        res
+    }
+}
+impl Default for Arena {
+    fn default() -> Self { Self::new() }
+}
+
+impl <T: ArenaComponent> Index<Handle<T>> for Arena {
+    type Output = T;
+    #[track_caller]
+    fn index(&self, h: Handle<T>) -> &T { self.get(h) }
+}
+impl <T: ArenaComponent> IndexMut<Handle<T>> for Arena {
+    #[track_caller]
+    fn index_mut(&mut self, h: Handle<T>) -> &mut T { self.get_mut(h) }
+}
+
+impl Clocked for Arena {
+    fn update(&mut self) {
+        let _drc = drc::CycleScope::enter(self.cycle);
+        let res = if self.threads > 1 && self.entries.len() > 1 {
+            self.update_parallel()
+        } else {
+            update_entries(&mut self.entries)
+        };
+        self.activity.updates += res.updates;
+        self.activity.skipped += res.skipped;
+        self.cycle += 1;
+    }
+}
+impl Resettable for Arena {
+    fn reset(&mut self) {
+        for entry in self.entries.iter_mut() {
+            if let Some(reset) = entry.reset {
+                let obj: &mut dyn Any = entry.obj.as_mut();
+                reset(obj);
+            }
+        }
+    }
+}
+impl Trace for Arena {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        for entry in self.entries.iter() {
+            if let Some((name, trace)) = &entry.traced {
+                let obj: &dyn Any = entry.obj.as_ref();
+                trace(obj, &format!("{}.{}", path, name), t);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::register::*;
+    use crate::lle::queue::*;
+    use crate::lle::mem::*;
+
+    #[test]
+    fn arena_access() {
+        let mut arena = Arena::new();
+        let r = arena.insert_resettable(Reg::new(1u32));
+        let q = arena.insert_resettable(Queue::<u32>::new());
+        let p = arena.insert(Reg::new(1u32));
+        for _ in 0..3 {
+            let x = arena[p].sample();
+            arena[p].drive(x + 1);
+            let (r, q) = arena.get2_mut(r, q);
+            q.enq(r.sample());
+            r.drive(r.sample() * 2);
+            arena.update();
+        }
+        assert_eq!(arena[r].sample(), 8);
+        assert_eq!(arena[q].data, [1, 2, 4]);
+        assert_eq!(arena.activity().updates, 9);
+
+        // Only resettable components are reset
+        arena.reset();
+        assert_eq!(arena[r].sample(), 1);
+        assert!(arena[q].data.is_empty());
+        assert_eq!(arena[p].sample(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "different Arena")]
+    fn arena_wrong_handle() {
+        let mut a = Arena::new();
+        let b = Arena::new();
+        let _ = a.insert(Reg::new(0u32));
+        let h = a.insert(Reg::new(0u32));
+        let _ = b[h].sample();
+    }
+
+    #[test]
+    #[should_panic(expected = "accessed twice")]
+    fn arena_alias() {
+        let mut arena = Arena::new();
+        let h = arena.insert(Reg::new(0u32));
+        let _ = arena.get2_mut(h, h);
+    }
+
+    /// Drive some of 'n' memories on each cycle.
+    fn drive_mems(arena: &mut Arena, mems: &[Handle<Mem<u64, 256>>], 
+        cyc: usize) 
+    {
+        for (i, m) in mems.iter().enumerate() {
+            if (cyc + i) % 3 != 0 {
+                arena[*m].drive((cyc * 7 + i) % 256, (cyc * i) as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn arena_parallel() {
+        let mut seq = Arena::new();
+        let mut par = Arena::new().with_threads(4);
+        let ms: Vec<_> = (0..10).map(|_| seq.insert(Mem::new_init_val(0))).collect();
+        let mp: Vec<_> = (0..10).map(|_| par.insert(Mem::new_init_val(0))).collect();
+        for cyc in 0..100 {
+            drive_mems(&mut seq, &ms, cyc);
+            drive_mems(&mut par, &mp, cyc);
+            seq.update();
+            par.update();
+        }
+        for (a, b) in ms.iter().zip(mp.iter()) {
+            for idx in 0..256 {
+                assert_eq!(seq[*a].sample(idx), par[*b].sample(idx));
+            }
+        }
+        assert_eq!(seq.activity(), par.activity());
+    }
+
+    /// Violations on each thread are reported in component order.
+    #[test]
+    fn arena_parallel_drc() {
+        drc::enable();
+        let mut arena = Arena::new().with_threads(3);
+        let names = ["r0", "r1", "r2", "r3", "r4"];
+        let regs: Vec<_> = names.iter()
+            .map(|n| arena.insert(Reg::new(0u32).named(n))).collect();
+        arena.update();
+        for r in regs.iter().rev() {
+            arena[*r].drive(1);
+            arena[*r].drive(2);
+        }
+        arena.update();
+        let v = drc::take_violations();
+        let got: Vec<_> = v.iter().map(|v| v.signal.name.unwrap()).collect();
+        assert_eq!(got, names);
+        assert!(v.iter().all(|x| x.cycle == v[0].cycle));
+        drc::disable();
+    }
+
+    #[test]
+    #[should_panic(expected = "CircularQueue underflow")]
+    fn arena_parallel_panic() {
+        let mut arena = Arena::new().with_threads(2);
+        let _ = arena.insert(Reg::new(0u32));
+        let q = arena.insert(CircularQueue::<u32, 4>::new());
+        arena[q].deq_ok = true;
+        arena.update();
+    }
+
+    /// Records the thread it was updated on.
+    struct ThreadProbe(std::sync::Arc<std::sync::Mutex<Vec<std::thread::ThreadId>>>);
+    impl Clocked for ThreadProbe {
+        fn update(&mut self) {
+            self.0.lock().unwrap().push(std::thread::current().id());
+        }
+    }
+
+    /// The same worker threads are used on every clock edge.
+    #[test]
+    fn arena_parallel_pool() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut arena = Arena::new().with_threads(3);
+        for _ in 0..3 {
+            arena.insert(ThreadProbe(log.clone()));
+        }
+        for _ in 0..10 {
+            arena.update();
+        }
+        let mut ids = log.lock().unwrap().clone();
+        assert_eq!(ids.len(), 30);
+        assert_eq!(ids[0], std::thread::current().id());
+        ids.sort_by_key(|id| format!("{:?}", id));
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+    }
+
+    /// Compare the time spent updating large memories on one thread, and
+    /// on multiple threads.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    ///
+    /// NOTE: A speedup is only expected (and checked) on a host with at
+    /// least 4 cores.
+    #[test]
+    #[ignore]
+    fn arena_parallel_speedup() {
+        let run = |mut arena: Arena| {
+            let mems: Vec<Handle<Mem<u64, 4096>>> = (0..16)
+                .map(|_| arena.insert(Mem::new_init_val(0))).collect();
+            let start = std::time::Instant::now();
+            for cyc in 0..2_000 {
+                for m in mems.iter() {
+                    arena[*m].drive(cyc % 4096, cyc as u64);
+                }
+                arena.update();
+            }
+            start.elapsed()
+        };
+        let seq = run(Arena::new());
+        let par = run(Arena::new().with_threads(4));
+        println!("1 thread: {:?}, 4 threads: {:?}", seq, par);
+
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        if cores >= 4 {
+            assert!(par * 2 < seq, "expected a 2x speedup with {} cores", cores);
+        } else {
+            println!("Only {} core(s), not checking the speedup", cores);
+        }
+    }
+
+    /// A whole simulation can be moved to (and back from) another thread.
+    #[test]
+    fn arena_send() {
+        let mut arena = Arena::new();
+        let r = arena.insert_named("r", Reg::new(0u64));
+        let res = std::thread::spawn(move || {
+            for _ in 0..100 {
+                let x = arena[r].sample();
+                arena[r].drive(x + 1);
+                arena.update();
+            }
+            arena
+        }).join().unwrap();
+        assert_eq!(res[r].sample(), 100);
+        assert_eq!(res.cycle(), 100);
+    }
+}
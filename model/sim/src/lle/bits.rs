@@ -0,0 +1,354 @@
+//! Fixed-width integers.
+//!
+//! [UInt] and [SInt] are `N`-bit integers (with `N` at most 64), which
+//! behave like the `UInt` and `SInt` types in Chisel:
+//!
+//! - Constructing a value that doesn't fit with `new` is a panic, so a
+//!   field which is narrower in the model than in the RTL shows up as an
+//!   error instead of silently carrying extra bits
+//! - Arithmetic wraps (like `+%` and `-%` in Chisel), and shifts left
+//!   truncate to the width of the value
+//! - Changing the width is always explicit (see [UInt::zext], [SInt::sext],
+//!   [UInt::slice], [UInt::cat] and [UInt::resize])
+//!
+//! NOTE: Without `generic_const_exprs`, the width of a result can't be
+//! computed from the widths of the operands. Instead, the caller names the
+//! width of the result, and it's checked at compile time.
+
+use std::fmt;
+use std::ops::*;
+
+use crate::lle::trace::*;
+
+const fn mask(n: usize) -> u64 {
+    if n == 64 { u64::MAX } else { (1 << n) - 1 }
+}
+
+/// An unsigned integer with `N` bits.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct UInt<const N: usize>(u64);
+
+impl <const N: usize> UInt<N> {
+    pub const WIDTH: usize = N;
+    pub const MASK: u64 = {
+        assert!(N >= 1 && N <= 64, "UInt width must be in 1..=64");
+        mask(N)
+    };
+    pub const ZERO: Self = Self(0);
+    pub const MAX: Self = Self(Self::MASK);
+
+    /// Create a new value (which must fit in `N` bits).
+    #[track_caller]
+    pub fn new(x: u64) -> Self {
+        match Self::try_new(x) {
+            Some(res) => res,
+            None => panic!("{:#x} does not fit in UInt<{}>", x, N),
+        }
+    }
+    /// Create a new value, or [None] if it doesn't fit in `N` bits.
+    pub fn try_new(x: u64) -> Option<Self> {
+        if x & !Self::MASK == 0 { Some(Self(x)) } else { None }
+    }
+    /// Create a new value from the low `N` bits of `x`.
+    pub fn truncate(x: u64) -> Self {
+        Self(x & Self::MASK)
+    }
+    /// Create a constant (which must fit in `N` bits).
+    ///
+    /// NOTE: Unlike [UInt::new], this can be used in `const` items (and
+    /// then in patterns), where the check happens at compile time.
+    pub const fn lit(x: u64) -> Self {
+        assert!(x & !Self::MASK == 0, "constant does not fit in UInt");
+        Self(x)
+    }
+
+    pub fn get(self) -> u64 { self.0 }
+    pub fn as_usize(self) -> usize { self.0 as usize }
+
+    /// Returns bit `idx`.
+    #[track_caller]
+    pub fn bit(self, idx: usize) -> bool {
+        assert!(idx < N, "bit {} out of range for UInt<{}>", idx, N);
+        (self.0 >> idx) & 1 != 0
+    }
+
+    /// Returns bits `lo + M - 1` down to `lo` (ie. `x(hi, lo)` in Chisel).
+    #[track_caller]
+    pub fn slice<const M: usize>(self, lo: usize) -> UInt<M> {
+        assert!(lo + M <= N, "slice [{}:{}] out of range for UInt<{}>",
+            lo + M - 1, lo, N);
+        UInt::truncate(self.0 >> lo)
+    }
+
+    /// Concatenate with `lo` (in the least-significant bits), where `R`
+    /// must be the sum of both widths (ie. `Cat(self, lo)` in Chisel).
+    ///
+    /// ```compile_fail
+    /// # use sim::lle::bits::*;
+    /// let x: UInt<8> = UInt::<4>::new(1).cat(UInt::<5>::new(1));
+    /// ```
+    pub fn cat<const M: usize, const R: usize>(self, lo: UInt<M>) -> UInt<R> {
+        const { 
+            assert!(R == N + M, "result of cat must be the sum of both widths");
+            assert!(R <= 64, "UInt width must be in 1..=64");
+        }
+        UInt((self.0 << M) | lo.0)
+    }
+
+    /// Zero-extend to `M` bits (where `M` is at least `N`).
+    ///
+    /// ```compile_fail
+    /// # use sim::lle::bits::*;
+    /// let x: UInt<4> = UInt::<8>::new(1).zext();
+    /// ```
+    pub fn zext<const M: usize>(self) -> UInt<M> {
+        const { 
+            assert!(M >= N, "cannot zero-extend to a narrower UInt");
+            assert!(M <= 64, "UInt width must be in 1..=64");
+        }
+        UInt(self.0)
+    }
+
+    /// Zero-extend or truncate to `M` bits.
+    pub fn resize<const M: usize>(self) -> UInt<M> {
+        UInt::truncate(self.0)
+    }
+
+    /// Reinterpret the bits as a signed value.
+    pub fn as_sint(self) -> SInt<N> {
+        SInt::from_bits(self)
+    }
+}
+
+/// A signed (two's complement) integer with `N` bits.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SInt<const N: usize>(i64);
+
+impl <const N: usize> SInt<N> {
+    pub const WIDTH: usize = N;
+    pub const MIN: Self = {
+        assert!(N >= 1 && N <= 64, "SInt width must be in 1..=64");
+        Self(if N == 64 { i64::MIN } else { -(1 << (N - 1)) })
+    };
+    pub const MAX: Self = Self(if N == 64 { i64::MAX } else { (1 << (N - 1)) - 1 });
+    pub const ZERO: Self = Self(0);
+
+    /// Create a new value (which must fit in `N` bits).
+    #[track_caller]
+    pub fn new(x: i64) -> Self {
+        match Self::try_new(x) {
+            Some(res) => res,
+            None => panic!("{} does not fit in SInt<{}>", x, N),
+        }
+    }
+    /// Create a new value, or [None] if it doesn't fit in `N` bits.
+    pub fn try_new(x: i64) -> Option<Self> {
+        if (Self::MIN.0..=Self::MAX.0).contains(&x) { Some(Self(x)) } else { None }
+    }
+    /// Create a new value from the low `N` bits of `x`.
+    pub fn truncate(x: i64) -> Self {
+        let _ = Self::MIN;
+        let shift = 64 - N;
+        Self((x << shift) >> shift)
+    }
+    /// Interpret some bits as a signed value.
+    pub fn from_bits(x: UInt<N>) -> Self {
+        Self::truncate(x.0 as i64)
+    }
+
+    pub fn get(self) -> i64 { self.0 }
+
+    /// Returns the sign bit.
+    pub fn is_negative(self) -> bool { self.0 < 0 }
+
+    /// Reinterpret the value as unsigned bits.
+    pub fn as_uint(self) -> UInt<N> {
+        UInt::truncate(self.0 as u64)
+    }
+
+    /// Sign-extend to `M` bits (where `M` is at least `N`).
+    ///
+    /// ```compile_fail
+    /// # use sim::lle::bits::*;
+    /// let x: SInt<4> = SInt::<8>::new(-1).sext();
+    /// ```
+    pub fn sext<const M: usize>(self) -> SInt<M> {
+        const { 
+            assert!(M >= N, "cannot sign-extend to a narrower SInt");
+            assert!(M <= 64, "SInt width must be in 1..=64");
+        }
+        SInt(self.0)
+    }
+
+    /// Sign-extend or truncate to `M` bits.
+    pub fn resize<const M: usize>(self) -> SInt<M> {
+        SInt::truncate(self.0)
+    }
+}
+
+macro_rules! impl_wrapping_ops {
+    ($ty:ident, $prim:ty, $($tr:ident::$f:ident => $op:ident),*) => { $(
+        impl <const N: usize> $tr for $ty<N> {
+            type Output = Self;
+            fn $f(self, rhs: Self) -> Self {
+                Self::truncate(self.0.$op(rhs.0))
+            }
+        }
+    )* }
+}
+impl_wrapping_ops!(UInt, u64,
+    Add::add => wrapping_add, Sub::sub => wrapping_sub,
+    Mul::mul => wrapping_mul,
+    BitAnd::bitand => bitand, BitOr::bitor => bitor, BitXor::bitxor => bitxor
+);
+impl_wrapping_ops!(SInt, i64,
+    Add::add => wrapping_add, Sub::sub => wrapping_sub,
+    Mul::mul => wrapping_mul,
+    BitAnd::bitand => bitand, BitOr::bitor => bitor, BitXor::bitxor => bitxor
+);
+
+impl <const N: usize> Not for UInt<N> {
+    type Output = Self;
+    fn not(self) -> Self { Self::truncate(!self.0) }
+}
+impl <const N: usize> Not for SInt<N> {
+    type Output = Self;
+    fn not(self) -> Self { Self(!self.0) }
+}
+impl <const N: usize> Neg for SInt<N> {
+    type Output = Self;
+    fn neg(self) -> Self { Self::truncate(self.0.wrapping_neg()) }
+}
+
+/// Shifting left truncates to `N` bits.
+impl <const N: usize> Shl<usize> for UInt<N> {
+    type Output = Self;
+    fn shl(self, rhs: usize) -> Self {
+        if rhs >= N { Self::ZERO } else { Self::truncate(self.0 << rhs) }
+    }
+}
+impl <const N: usize> Shr<usize> for UInt<N> {
+    type Output = Self;
+    fn shr(self, rhs: usize) -> Self {
+        if rhs >= N { Self::ZERO } else { Self(self.0 >> rhs) }
+    }
+}
+/// Shifting left truncates to `N` bits.
+impl <const N: usize> Shl<usize> for SInt<N> {
+    type Output = Self;
+    fn shl(self, rhs: usize) -> Self {
+        if rhs >= N { Self::ZERO } else { Self::truncate(self.0 << rhs) }
+    }
+}
+/// Shifting right is an arithmetic shift.
+impl <const N: usize> Shr<usize> for SInt<N> {
+    type Output = Self;
+    fn shr(self, rhs: usize) -> Self {
+        Self(self.0 >> rhs.min(63))
+    }
+}
+
+impl <const N: usize> fmt::Debug for UInt<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}u{}", self.0, N)
+    }
+}
+impl <const N: usize> fmt::Display for UInt<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+impl <const N: usize> fmt::LowerHex for UInt<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+impl <const N: usize> fmt::Debug for SInt<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}s{}", self.0, N)
+    }
+}
+impl <const N: usize> fmt::Display for SInt<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl <const N: usize> TraceValue for UInt<N> {
+    const WIDTH: u32 = N as u32;
+    fn trace_bits(&self) -> Option<u64> { Some(self.0) }
+}
+impl <const N: usize> TraceValue for SInt<N> {
+    const WIDTH: u32 = N as u32;
+    fn trace_bits(&self) -> Option<u64> { Some(self.as_uint().0) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::*;
+    use crate::lle::register::*;
+
+    #[test]
+    fn uint_wrapping() {
+        let x = UInt::<4>::new(0xe);
+        assert_eq!(x + UInt::new(3), UInt::new(1));
+        assert_eq!(UInt::<4>::ZERO - UInt::new(1), UInt::MAX);
+        assert_eq!(x << 1, UInt::new(0xc));
+        assert_eq!(!x, UInt::new(0x1));
+        assert_eq!(UInt::<64>::MAX + UInt::new(1), UInt::ZERO);
+        assert_eq!(UInt::<5>::try_new(32), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in UInt<19>")]
+    fn uint_overflow() {
+        UInt::<19>::new(1 << 19);
+    }
+
+    #[test]
+    fn uint_slice_cat() {
+        let x = UInt::<16>::new(0xabcd);
+        assert_eq!(x.slice::<4>(4), UInt::new(0xc));
+        assert_eq!(x.slice::<8>(8), UInt::new(0xab));
+        assert!(x.bit(0) && !x.bit(1));
+        let y: UInt<20> = x.cat(UInt::<4>::new(0x1));
+        assert_eq!(y, UInt::new(0xabcd1));
+        assert_eq!(x.zext::<32>().get(), 0xabcd);
+        assert_eq!(x.resize::<8>(), UInt::new(0xcd));
+    }
+
+    #[test]
+    fn uint_lit() {
+        const X: UInt<4> = UInt::lit(0xa);
+        assert!(matches!(UInt::<4>::new(0xa), X));
+        assert!(!matches!(UInt::<4>::new(0xb), X));
+    }
+
+    #[test]
+    fn sint_extend() {
+        let x = SInt::<12>::new(-1);
+        assert_eq!(x.as_uint(), UInt::new(0xfff));
+        assert_eq!(x.sext::<32>().get(), -1);
+        assert_eq!(UInt::<12>::new(0x800).as_sint().get(), -2048);
+        assert_eq!(SInt::<12>::MAX + SInt::new(1), SInt::MIN);
+        assert_eq!(-SInt::<8>::MIN, SInt::MIN);
+        assert_eq!(SInt::<8>::new(-64) >> 2, SInt::new(-16));
+        assert_eq!(SInt::<8>::new(64) << 1, SInt::new(-128));
+        assert_eq!(SInt::<8>::try_new(128), None);
+        assert_eq!(SInt::<64>::truncate(-5).get(), -5);
+    }
+
+    #[test]
+    fn bits_in_reg() {
+        let mut r = Reg::new(UInt::<3>::ZERO);
+        for _ in 0..9 {
+            let x = r.sample();
+            r.drive(x + UInt::new(1));
+            r.update();
+        }
+        assert_eq!(r.sample(), UInt::new(1));
+        assert_eq!(<UInt<3> as TraceValue>::WIDTH, 3);
+        assert_eq!(SInt::<4>::new(-1).trace_bits(), Some(0xf));
+    }
+}
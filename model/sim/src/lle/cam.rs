@@ -1,5 +1,11 @@
 
 use std::collections::*;
+use std::cell::*;
+
+use crate::lle::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
+use crate::lle::repl::*;
 
 pub struct AsyncReadCam<K: Ord + Copy, V: Copy> {
     pub wp_pending: Vec<(K, V)>,
@@ -21,12 +27,29 @@ impl <K: Ord + Copy, V: Copy> AsyncReadCam<K, V> {
     pub fn drive_wp(&mut self, key: K, value: V) {
         self.wp_pending.push((key, value));
     }
-
-    pub fn update(&mut self) {
+}
+impl <K: Ord + Copy, V: Copy> Clocked for AsyncReadCam<K, V> {
+    fn update(&mut self) {
         while let Some((k,v)) = self.wp_pending.pop() {
             self.data.insert(k, v);
         }
     }
+    fn is_dirty(&self) -> bool {
+        !self.wp_pending.is_empty()
+    }
+}
+/// NOTE: The number of entries isn't bounded, and reads are asynchronous.
+impl <K: Ord + Copy + HwBits, V: Copy + HwBits> Cost for AsyncReadCam<K, V> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("AsyncReadCam", self.data.len(),
+            K::BITS + V::BITS + 1).unbounded());
+    }
+}
+impl <K: Ord + Copy, V: Copy> Resettable for AsyncReadCam<K, V> {
+    fn reset(&mut self) {
+        self.wp_pending.clear();
+        self.data.clear();
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -81,14 +104,6 @@ SyncReadCam<K, V, NUM_RP, NUM_WP>
         self.rp_val[port]
     }
 
-    pub fn update(&mut self) {
-        if let Some(update_fn) = self.update_fn {
-            (update_fn)(self);
-        } else {
-            self.default_update();
-        }
-    }
-
     // Default update strategy.
     fn default_update(&mut self) {
         for idx in 0..NUM_RP {
@@ -113,5 +128,433 @@ SyncReadCam<K, V, NUM_RP, NUM_WP>
         }
     }
 }
+impl <K: Ord + Copy, V: Copy, const NUM_RP: usize, const NUM_WP: usize> 
+Clocked for SyncReadCam<K, V, NUM_RP, NUM_WP> 
+{
+    fn update(&mut self) {
+        if let Some(update_fn) = self.update_fn {
+            (update_fn)(self);
+        } else {
+            self.default_update();
+        }
+    }
+}
+impl <K: Ord + Copy, V: Copy, const NUM_RP: usize, const NUM_WP: usize> 
+Resettable for SyncReadCam<K, V, NUM_RP, NUM_WP> 
+{
+    /// NOTE: The update function is preserved.
+    fn reset(&mut self) {
+        self.wp_pending = [None; NUM_WP];
+        self.rp_key = [None; NUM_RP];
+        self.rp_val = [None; NUM_RP];
+        self.data.clear();
+    }
+}
+/// NOTE: The number of entries isn't bounded.
+impl <K, V, const NUM_RP: usize, const NUM_WP: usize>
+Cost for SyncReadCam<K, V, NUM_RP, NUM_WP>
+    where K: Ord + Copy + HwBits, V: Copy + HwBits
+{
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("SyncReadCam", self.data.len(),
+            K::BITS + V::BITS + 1).with_ports(NUM_RP, NUM_WP).unbounded());
+    }
+}
+
+
+/// Selects an entry to be replaced when a [BoundedCam] is full.
+pub trait CamReplacement {
+    /// Entry `idx` was updated in place, or matched by a read.
+    fn touch(&mut self, idx: usize);
+    /// Entry `idx` was filled with a new key.
+    ///
+    /// By default, this is the same as [CamReplacement::touch].
+    fn insert(&mut self, idx: usize) {
+        self.touch(idx);
+    }
+    /// Entry `idx` was invalidated.
+    fn invalidate(&mut self, _idx: usize) {}
+    /// Select one of `size` (valid) entries to be replaced.
+    fn victim(&mut self, size: usize) -> usize;
+}
+
+/// Any [ReplacementPolicy] with a single set can be used with a
+/// [BoundedCam] (with one way for each entry).
+impl <P: ReplacementPolicy> CamReplacement for P {
+    fn touch(&mut self, idx: usize) {
+        ReplacementPolicy::touch(self, 0, idx);
+    }
+    fn insert(&mut self, idx: usize) {
+        ReplacementPolicy::insert(self, 0, idx);
+    }
+    fn invalidate(&mut self, idx: usize) {
+        ReplacementPolicy::invalidate(self, 0, idx);
+    }
+    fn victim(&mut self, size: usize) -> usize {
+        assert!(self.num_sets() == 1 && self.num_ways() == size,
+            "replacement policy doesn't match the CAM size");
+        ReplacementPolicy::victim(self, 0)
+    }
+}
+
+/// Replace entries in the order they were allocated (round-robin).
+///
+/// NOTE: Unlike the policies in [crate::lle::repl], hits are ignored.
+#[derive(Default)]
+pub struct FifoReplacement { next: usize }
+impl CamReplacement for FifoReplacement {
+    fn touch(&mut self, _idx: usize) {}
+    fn victim(&mut self, size: usize) -> usize {
+        let res = self.next % size;
+        self.next = res + 1;
+        res
+    }
+}
+
+/// A command driven on a write port of a [BoundedCam].
+#[derive(Clone, Copy, Debug)]
+pub enum CamWriteCmd<K, V> {
+    /// Update the entry matching the key, or allocate a new entry.
+    Update(K, V),
+    /// Invalidate the entry matching the key (if it exists).
+    Invalidate(K),
+}
+
+/// Comparison counts for a [BoundedCam] (ie. for energy estimates).
+///
+/// Every lookup compares the key against all `SZ` entries.
+#[derive(Clone, Debug, Default)]
+pub struct CamStats {
+    pub cycles: usize,
+    pub comparisons: usize,
+    /// The largest number of comparisons in a single cycle
+    pub max_comparisons: usize,
+    /// Number of valid entries replaced because the CAM was full
+    pub replacements: usize,
+}
+impl CamStats {
+    pub fn avg_comparisons(&self) -> f64 {
+        if self.cycles == 0 { 0.0 }
+        else { self.comparisons as f64 / self.cycles as f64 }
+    }
+}
+
+/// Content-addressible memory with `SZ` entries, asynchronous reads and 
+/// `NUM_WP` synchronous write ports.
+///
+/// Writes are applied at the clock edge in port order. When an update
+/// misses and there are no free entries, an entry is selected by the 
+/// replacement policy (see [CamReplacement]). Entries that match a read 
+/// are reported to the replacement policy at the next clock edge.
+pub struct BoundedCam<K: PartialEq + Copy, V: Copy, 
+    const SZ: usize, const NUM_WP: usize> 
+{
+    entries: [Option<(K, V)>; SZ],
+    wp_pending: [Option<CamWriteCmd<K, V>>; NUM_WP],
+    repl: Box<dyn CamReplacement>,
+    /// Entries matched by reads on this cycle
+    read_hits: RefCell<Vec<usize>>,
+    /// Comparisons made on this cycle
+    comparisons: Cell<usize>,
+    /// Entries replaced at the last clock edge
+    evicted: Vec<(K, V)>,
+    stats: CamStats,
+}
+impl <K: PartialEq + Copy, V: Copy, const SZ: usize, const NUM_WP: usize> 
+BoundedCam<K, V, SZ, NUM_WP> 
+{
+    pub fn new() -> Self {
+        Self {
+            entries: [None; SZ],
+            wp_pending: [None; NUM_WP],
+            repl: Box::new(RandomPolicy::new(1, SZ)),
+            read_hits: RefCell::new(Vec::new()),
+            comparisons: Cell::new(0),
+            evicted: Vec::new(),
+            stats: CamStats::default(),
+        }
+    }
+    /// Use a different replacement policy (the default is 
+    /// [RandomPolicy]).
+    pub fn with_replacement(mut self, repl: impl CamReplacement + 'static) 
+        -> Self 
+    {
+        self.repl = Box::new(repl);
+        self
+    }
+
+    pub fn capacity(&self) -> usize { SZ }
+    pub fn num_valid(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+    pub fn is_full(&self) -> bool { self.num_valid() == SZ }
+    pub fn stats(&self) -> &CamStats { &self.stats }
+
+    /// Returns the entries replaced at the last clock edge.
+    pub fn evicted(&self) -> &[(K, V)] { &self.evicted }
+
+    /// Returns the contents of an entry (without a lookup).
+    pub fn entry(&self, idx: usize) -> Option<(K, V)> { self.entries[idx] }
+
+    /// Compare all entries against `f`, returning a bitvector of matches.
+    fn compare(&self, f: impl Fn(&K) -> bool) -> [bool; SZ] {
+        self.comparisons.set(self.comparisons.get() + SZ);
+        self.entries.map(|e| e.is_some_and(|(k, _)| f(&k)))
+    }
+
+    /// Returns the indices of all entries matching `f`.
+    pub fn match_all(&self, f: impl Fn(&K) -> bool) -> Vec<usize> {
+        let hits = self.compare(f);
+        let res: Vec<usize> = (0..SZ).filter(|idx| hits[*idx]).collect();
+        self.read_hits.borrow_mut().extend(res.iter());
+        res
+    }
+
+    /// Returns the index of the first entry matching `f` (like a priority
+    /// encoder), and the number of matching entries.
+    pub fn match_first(&self, f: impl Fn(&K) -> bool) -> Option<(usize, usize)> {
+        let hits = self.compare(f);
+        let first = hits.iter().position(|h| *h)?;
+        self.read_hits.borrow_mut().push(first);
+        Some((first, hits.iter().filter(|h| **h).count()))
+    }
+
+    /// Combinational read: returns the value of the first entry matching
+    /// `key` (if it exists).
+    pub fn sample_rp(&self, key: K) -> Option<V> {
+        let (idx, _) = self.match_first(|k| *k == key)?;
+        self.entries[idx].map(|(_, v)| v)
+    }
+
+    /// Drive a command on a write port (applied at the next clock edge).
+    pub fn drive_wp(&mut self, port: usize, cmd: CamWriteCmd<K, V>) {
+        self.wp_pending[port] = Some(cmd);
+    }
+    pub fn drive_update(&mut self, port: usize, key: K, value: V) {
+        self.drive_wp(port, CamWriteCmd::Update(key, value));
+    }
+    pub fn drive_invalidate(&mut self, port: usize, key: K) {
+        self.drive_wp(port, CamWriteCmd::Invalidate(key));
+    }
+}
+impl <K: PartialEq + Copy, V: Copy, const SZ: usize, const NUM_WP: usize> 
+Default for BoundedCam<K, V, SZ, NUM_WP> 
+{
+    fn default() -> Self { Self::new() }
+}
+impl <K: PartialEq + Copy, V: Copy, const SZ: usize, const NUM_WP: usize> 
+Clocked for BoundedCam<K, V, SZ, NUM_WP> 
+{
+    fn update(&mut self) {
+        for idx in self.read_hits.get_mut().drain(..) {
+            self.repl.touch(idx);
+        }
+        self.evicted.clear();
+
+        for port in 0..NUM_WP {
+            let Some(cmd) = self.wp_pending[port].take() else { continue };
+            match cmd {
+                CamWriteCmd::Update(key, value) => {
+                    let hits = self.compare(|k| *k == key);
+                    let free = self.entries.iter().position(|e| e.is_none());
+                    match (hits.iter().position(|h| *h), free) {
+                        (Some(idx), _) => {
+                            self.entries[idx] = Some((key, value));
+                            self.repl.touch(idx);
+                        },
+                        (None, Some(idx)) => {
+                            self.entries[idx] = Some((key, value));
+                            self.repl.insert(idx);
+                        },
+                        (None, None) => {
+                            let idx = self.repl.victim(SZ);
+                            self.evicted.push(self.entries[idx].unwrap());
+                            self.stats.replacements += 1;
+                            self.entries[idx] = Some((key, value));
+                            self.repl.insert(idx);
+                        },
+                    }
+                },
+                CamWriteCmd::Invalidate(key) => {
+                    let hits = self.compare(|k| *k == key);
+                    for (idx, hit) in hits.into_iter().enumerate() {
+                        if hit { 
+                            self.entries[idx] = None; 
+                            self.repl.invalidate(idx);
+                        }
+                    }
+                },
+            }
+        }
+
+        let num = self.comparisons.take();
+        self.stats.cycles += 1;
+        self.stats.comparisons += num;
+        self.stats.max_comparisons = self.stats.max_comparisons.max(num);
+    }
+}
+impl <K: PartialEq + Copy, V: Copy, const SZ: usize, const NUM_WP: usize> 
+Resettable for BoundedCam<K, V, SZ, NUM_WP> 
+{
+    /// NOTE: The replacement policy and statistics are preserved.
+    fn reset(&mut self) {
+        self.entries = [None; SZ];
+        self.wp_pending = [None; NUM_WP];
+        self.read_hits.get_mut().clear();
+        self.comparisons.set(0);
+        self.evicted.clear();
+    }
+}
+/// NOTE: Reads are asynchronous, so the number of read ports isn't known.
+impl <K, V, const SZ: usize, const NUM_WP: usize>
+Cost for BoundedCam<K, V, SZ, NUM_WP>
+    where K: PartialEq + Copy + HwBits, V: Copy + HwBits
+{
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("BoundedCam", SZ, K::BITS + V::BITS + 1)
+            .with_write_ports(NUM_WP));
+    }
+}
+/// NOTE: Only the number of valid entries is traced.
+impl <K: PartialEq + Copy, V: Copy, const SZ: usize, const NUM_WP: usize> 
+Trace for BoundedCam<K, V, SZ, NUM_WP> 
+{
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(&format!("{}.num_valid", path), 32, 
+            Some(self.num_valid() as u64));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cam_update_invalidate() {
+        let mut cam = BoundedCam::<u32, u32, 4, 2>::new();
+        cam.drive_update(0, 10, 1);
+        cam.drive_update(1, 20, 2);
+        assert_eq!(cam.sample_rp(10), None);
+        cam.update();
+        assert_eq!(cam.sample_rp(10), Some(1));
+        assert_eq!(cam.sample_rp(20), Some(2));
+
+        // Update in place, then invalidate
+        cam.drive_update(0, 10, 3);
+        cam.update();
+        assert_eq!(cam.num_valid(), 2);
+        assert_eq!(cam.sample_rp(10), Some(3));
+        cam.drive_invalidate(1, 10);
+        cam.update();
+        assert_eq!(cam.sample_rp(10), None);
+        assert_eq!(cam.num_valid(), 1);
+    }
+
+    #[test]
+    fn cam_replacement() {
+        let mut cam = BoundedCam::<u32, u32, 2, 1>::new()
+            .with_replacement(LruPolicy::new(1, 2));
+        for key in [1, 2] {
+            cam.drive_update(0, key, key);
+            cam.update();
+        }
+        assert!(cam.is_full());
+
+        // Reading '1' makes '2' the least-recently used
+        assert_eq!(cam.sample_rp(1), Some(1));
+        cam.update();
+        cam.drive_update(0, 3, 3);
+        cam.update();
+        assert_eq!(cam.evicted(), &[(2, 2)]);
+        assert_eq!(cam.sample_rp(2), None);
+        assert_eq!(cam.sample_rp(1), Some(1));
+        assert_eq!(cam.stats().replacements, 1);
+
+        let mut cam = BoundedCam::<u32, u32, 2, 1>::new()
+            .with_replacement(FifoReplacement::default());
+        for key in 0..4 {
+            cam.drive_update(0, key, key);
+            cam.update();
+        }
+        assert_eq!(cam.evicted(), &[(1, 1)]);
+        assert_eq!(cam.entry(0), Some((2, 2)));
+        assert_eq!(cam.entry(1), Some((3, 3)));
 
+        // A single-set ReplacementPolicy
+        let mut cam = BoundedCam::<u32, u32, 4, 1>::new()
+            .with_replacement(TreePlruPolicy::new(1, 4));
+        for key in 0..4 {
+            cam.drive_update(0, key, key);
+            cam.update();
+        }
+        assert_eq!(cam.sample_rp(0), Some(0));
+        cam.update();
+        cam.drive_update(0, 4, 4);
+        cam.update();
+        assert_eq!(cam.evicted(), &[(2, 2)]);
+    }
+
+    #[test]
+    fn cam_rrip() {
+        // New entries are inserted with a "long" RRPV, and hits with 0
+        let mut cam = BoundedCam::<u32, u32, 4, 1>::new()
+            .with_replacement(RripPolicy::srrip(1, 4));
+        for key in 0..4 {
+            cam.drive_update(0, key, key);
+            cam.update();
+        }
+        assert_eq!(cam.sample_rp(0), Some(0));
+        cam.drive_invalidate(0, 3);
+        cam.update();
 
+        // Fills the invalidated entry
+        cam.drive_update(0, 4, 4);
+        cam.update();
+        assert!(cam.evicted().is_empty());
+
+        // Entry 0 was hit, so the oldest insertion is replaced
+        cam.drive_update(0, 5, 5);
+        cam.update();
+        assert_eq!(cam.evicted(), &[(1, 1)]);
+        assert_eq!(cam.sample_rp(0), Some(0));
+    }
+
+    #[test]
+    fn cam_multi_match() {
+        let mut cam = BoundedCam::<u32, u32, 8, 1>::new();
+        for key in [5, 12, 7, 9] {
+            cam.drive_update(0, key, key * 10);
+            cam.update();
+        }
+        assert_eq!(cam.match_all(|k| *k >= 7), vec![1, 2, 3]);
+        assert_eq!(cam.match_first(|k| *k >= 7), Some((1, 3)));
+        assert_eq!(cam.match_first(|k| *k > 100), None);
+    }
+
+    #[test]
+    fn cam_comparisons() {
+        let mut cam = BoundedCam::<u32, u32, 8, 1>::new();
+        cam.drive_update(0, 1, 1);
+        cam.update();
+        cam.sample_rp(1);
+        cam.sample_rp(2);
+        cam.update();
+        let stats = cam.stats();
+        assert_eq!(stats.cycles, 2);
+        assert_eq!(stats.comparisons, 24);
+        assert_eq!(stats.max_comparisons, 16);
+        assert_eq!(stats.avg_comparisons(), 12.0);
+    }
+
+    #[test]
+    fn cam_random() {
+        // The default policy eventually replaces every entry
+        let mut cam = BoundedCam::<u32, u32, 4, 1>::new();
+        for key in 0..64 {
+            cam.drive_update(0, key, key);
+            cam.update();
+        }
+        assert_eq!(cam.stats().replacements, 60);
+        assert!((0..4).all(|key| cam.sample_rp(key).is_none()));
+    }
+}
@@ -0,0 +1,234 @@
+//! Clock-domain crossing.
+//!
+//! [AsyncFifo] is a dual-clock FIFO in the usual style: each side keeps a
+//! binary pointer, and publishes it (as a Gray code) to the other side
+//! through a two-flop synchronizer. The full/empty flags are computed from
+//! synchronized pointers, so they're conservative: a slot freed by the
+//! reader isn't visible to the writer until two writer clock edges later
+//! (and vice-versa).
+//!
+//! The two sides are separate [Clocked] components which should be tracked
+//! in different clock domains (see [crate::lle::clock]).
+
+use crate::lle::*;
+
+fn to_gray(x: usize) -> usize { x ^ (x >> 1) }
+fn from_gray(mut g: usize) -> usize {
+    let mut x = 0;
+    while g != 0 {
+        x ^= g;
+        g >>= 1;
+    }
+    x
+}
+
+/// Storage and Gray-coded pointers shared by both sides of an [AsyncFifo].
+struct FifoCore<T, const DEPTH: usize> {
+    data: [Option<T>; DEPTH],
+    /// Write pointer register (in the write domain)
+    wptr_gray: usize,
+    /// Read pointer register (in the read domain)
+    rptr_gray: usize,
+}
+
+/// Constructor for a dual-clock FIFO with `DEPTH` entries.
+///
+/// `DEPTH` must be a power of two.
+pub struct AsyncFifo<T, const DEPTH: usize>(std::marker::PhantomData<T>);
+impl <T: Copy, const DEPTH: usize> AsyncFifo<T, DEPTH> {
+    /// Returns the write side and the read side of a new FIFO.
+    pub fn channel() -> (AsyncFifoWr<T, DEPTH>, AsyncFifoRd<T, DEPTH>) {
+        assert!(DEPTH.is_power_of_two());
+        let core = Rc::new(RefCell::new(FifoCore {
+            data: [None; DEPTH],
+            wptr_gray: 0,
+            rptr_gray: 0,
+        }));
+        let wr = AsyncFifoWr {
+            core: core.clone(), wptr: 0, rptr_sync: [0; 2], next: None
+        };
+        let rd = AsyncFifoRd {
+            core, rptr: 0, wptr_sync: [0; 2], deq_ok: false
+        };
+        (wr, rd)
+    }
+}
+
+/// Pointers wrap at twice the depth, so that full and empty are distinct.
+fn wrap<const DEPTH: usize>(ptr: usize) -> usize { ptr & (2 * DEPTH - 1) }
+
+/// The write side of an [AsyncFifo].
+pub struct AsyncFifoWr<T, const DEPTH: usize> {
+    core: StateRef<FifoCore<T, DEPTH>>,
+    wptr: usize,
+    /// Synchronizer for the read pointer ([1] is the output)
+    rptr_sync: [usize; 2],
+    next: Option<T>,
+}
+impl <T: Copy, const DEPTH: usize> AsyncFifoWr<T, DEPTH> {
+    /// Returns true if the FIFO is full (from the perspective of the writer).
+    pub fn is_full(&self) -> bool {
+        let rptr = from_gray(self.rptr_sync[1]);
+        wrap::<DEPTH>(self.wptr.wrapping_sub(rptr)) == DEPTH
+    }
+    /// Drive a new element, which is written at the next clock edge.
+    pub fn enq(&mut self, data: T) {
+        assert!(!self.is_full(), "AsyncFifo overflow");
+        self.next = Some(data);
+    }
+}
+impl <T: Copy, const DEPTH: usize> Clocked for AsyncFifoWr<T, DEPTH> {
+    fn update(&mut self) {
+        let mut core = self.core.borrow_mut();
+        self.rptr_sync[1] = self.rptr_sync[0];
+        self.rptr_sync[0] = core.rptr_gray;
+        if let Some(data) = self.next.take() {
+            core.data[self.wptr % DEPTH] = Some(data);
+            self.wptr = wrap::<DEPTH>(self.wptr + 1);
+            core.wptr_gray = to_gray(self.wptr);
+        }
+    }
+}
+impl <T: Copy, const DEPTH: usize> Resettable for AsyncFifoWr<T, DEPTH> {
+    fn reset(&mut self) {
+        self.wptr = 0;
+        self.rptr_sync = [0; 2];
+        self.next = None;
+        self.core.borrow_mut().wptr_gray = 0;
+    }
+}
+
+/// The read side of an [AsyncFifo].
+pub struct AsyncFifoRd<T, const DEPTH: usize> {
+    core: StateRef<FifoCore<T, DEPTH>>,
+    rptr: usize,
+    /// Synchronizer for the write pointer ([1] is the output)
+    wptr_sync: [usize; 2],
+    deq_ok: bool,
+}
+impl <T: Copy, const DEPTH: usize> AsyncFifoRd<T, DEPTH> {
+    /// Returns true if the FIFO is empty (from the perspective of the reader).
+    pub fn is_empty(&self) -> bool {
+        self.wptr_sync[1] == to_gray(self.rptr)
+    }
+    /// Returns the oldest entry (if the FIFO isn't empty).
+    pub fn front(&self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.core.borrow().data[self.rptr % DEPTH]
+    }
+    /// Drive 'deq_ok', indicating that the oldest entry is removed at the
+    /// next clock edge.
+    pub fn set_deq(&mut self) {
+        assert!(!self.is_empty(), "AsyncFifo underflow");
+        self.deq_ok = true;
+    }
+}
+impl <T: Copy, const DEPTH: usize> Clocked for AsyncFifoRd<T, DEPTH> {
+    fn update(&mut self) {
+        let mut core = self.core.borrow_mut();
+        self.wptr_sync[1] = self.wptr_sync[0];
+        self.wptr_sync[0] = core.wptr_gray;
+        if self.deq_ok {
+            core.data[self.rptr % DEPTH] = None;
+            self.rptr = wrap::<DEPTH>(self.rptr + 1);
+            core.rptr_gray = to_gray(self.rptr);
+            self.deq_ok = false;
+        }
+    }
+}
+impl <T: Copy, const DEPTH: usize> Resettable for AsyncFifoRd<T, DEPTH> {
+    /// NOTE: Both sides of the FIFO should be reset together.
+    fn reset(&mut self) {
+        self.rptr = 0;
+        self.wptr_sync = [0; 2];
+        self.deq_ok = false;
+        let mut core = self.core.borrow_mut();
+        core.rptr_gray = 0;
+        core.data = [None; DEPTH];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::clock::*;
+
+    #[test]
+    fn cdc_gray() {
+        for x in 0..64usize {
+            assert_eq!(from_gray(to_gray(x)), x);
+            // Adjacent codes differ by a single bit
+            let next = wrap::<32>(x + 1);
+            assert_eq!((to_gray(x) ^ to_gray(next)).count_ones(), 1);
+        }
+    }
+
+    /// Send values across a FIFO, returning the values received.
+    fn transfer(wr_period: u64, rd_period: u64, num: u32) -> Vec<u32> {
+        let mut sched = Scheduler::new();
+        let wclk = sched.add_domain("wr", wr_period, 0);
+        let rclk = sched.add_domain("rd", rd_period, 0);
+        let (wr, rd) = AsyncFifo::<u32, 4>::channel();
+        let wr = Rc::new(RefCell::new(wr));
+        let rd = Rc::new(RefCell::new(rd));
+        sched.domain_mut(wclk).state_mut().track(&wr);
+        sched.domain_mut(rclk).state_mut().track(&rd);
+
+        let mut sent = 0;
+        let mut res = Vec::new();
+        while res.len() < num as usize {
+            sched.step(|id| {
+                if id == wclk {
+                    let mut wr = wr.borrow_mut();
+                    if sent < num && !wr.is_full() {
+                        wr.enq(sent);
+                        sent += 1;
+                    }
+                } else {
+                    let mut rd = rd.borrow_mut();
+                    if let Some(x) = rd.front() {
+                        res.push(x);
+                        rd.set_deq();
+                    }
+                }
+            });
+            assert!(sched.time() < 10_000);
+        }
+        res
+    }
+
+    #[test]
+    fn cdc_fast_to_slow() {
+        assert_eq!(transfer(1, 3, 32), (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cdc_slow_to_fast() {
+        assert_eq!(transfer(5, 2, 32), (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cdc_sync_latency() {
+        let (mut wr, mut rd) = AsyncFifo::<u32, 2>::channel();
+        wr.enq(7);
+        wr.update();
+        // Two edges in the read domain before the write is visible
+        assert!(rd.is_empty());
+        rd.update();
+        assert!(rd.is_empty());
+        rd.update();
+        assert_eq!(rd.front(), Some(7));
+
+        wr.enq(8);
+        wr.update();
+        assert!(wr.is_full());
+        rd.set_deq();
+        rd.update();
+        wr.update();
+        assert!(wr.is_full());
+        wr.update();
+        assert!(!wr.is_full());
+    }
+}
@@ -0,0 +1,168 @@
+//! Multiple clock domains.
+//!
+//! A [Scheduler] owns a set of [ClockDomain]s, each with a period measured
+//! in some global unit of time (ie. picoseconds). For example, a core at
+//! 3x the frequency of the memory system would have a period of 1, and the
+//! memory system would have a period of 3.
+//!
+//! Each domain has its own [ClockedState] which tracks the components in
+//! that domain. [Scheduler::step] advances global time to the next clock
+//! edge (in any domain), and updates all domains with an edge at that time.
+//!
+//! Signals crossing between domains should go through a synchronizer, ie.
+//! an [AsyncFifo](crate::lle::cdc::AsyncFifo).
+
+use crate::lle::*;
+
+/// Identifies a [ClockDomain] in a [Scheduler].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DomainId(pub usize);
+
+pub struct ClockDomain {
+    name: String,
+    /// Time between rising edges
+    period: u64,
+    /// Time of the next rising edge
+    next_edge: u64,
+    state: ClockedState,
+}
+impl ClockDomain {
+    pub fn name(&self) -> &str { &self.name }
+    pub fn period(&self) -> u64 { self.period }
+    /// Returns the number of clock edges that have occurred in this domain.
+    pub fn cycle(&self) -> usize { self.state.cycle() }
+    pub fn state(&self) -> &ClockedState { &self.state }
+    pub fn state_mut(&mut self) -> &mut ClockedState { &mut self.state }
+}
+
+/// Advances a set of clock domains in global time order.
+pub struct Scheduler {
+    time: u64,
+    domains: Vec<ClockDomain>,
+}
+impl Scheduler {
+    pub fn new() -> Self {
+        Self { time: 0, domains: Vec::new() }
+    }
+
+    /// Add a clock domain with the given period. The first rising edge
+    /// occurs after `phase` units of time.
+    pub fn add_domain(&mut self, name: &str, period: u64, phase: u64) -> DomainId {
+        assert!(period != 0, "Clock domain '{}' has a zero period", name);
+        self.domains.push(ClockDomain {
+            name: name.to_string(),
+            period,
+            next_edge: phase,
+            state: ClockedState::new(),
+        });
+        DomainId(self.domains.len() - 1)
+    }
+
+    pub fn domain(&self, id: DomainId) -> &ClockDomain { &self.domains[id.0] }
+    pub fn domain_mut(&mut self, id: DomainId) -> &mut ClockDomain {
+        &mut self.domains[id.0]
+    }
+
+    /// Returns the time of the most recent clock edge.
+    pub fn time(&self) -> u64 { self.time }
+
+    /// Returns the time of the next clock edge (in any domain).
+    pub fn next_edge(&self) -> u64 {
+        self.domains.iter().map(|d| d.next_edge).min()
+            .expect("No clock domains")
+    }
+
+    /// Advance to the next clock edge, returning the time of the edge.
+    ///
+    /// `eval` is called for each domain with an edge at this time (before
+    /// any of them are updated), and should drive the inputs to the
+    /// components in that domain.
+    ///
+    /// NOTE: When edges in different domains coincide, domains are updated
+    /// in the order they were added. A synchronizer sampling a signal from
+    /// another domain on a coincident edge may observe either the old or
+    /// the new value (which is also true in hardware).
+    pub fn step(&mut self, mut eval: impl FnMut(DomainId)) -> u64 {
+        let time = self.next_edge();
+        let edges: Vec<DomainId> = (0..self.domains.len())
+            .filter(|idx| self.domains[*idx].next_edge == time)
+            .map(DomainId).collect();
+
+        for id in edges.iter() {
+            eval(*id);
+        }
+        for id in edges.iter() {
+            let d = &mut self.domains[id.0];
+            d.state.update();
+            d.next_edge += d.period;
+        }
+        self.time = time;
+        time
+    }
+
+    /// Step until global time reaches `time`.
+    pub fn run_until(&mut self, time: u64, mut eval: impl FnMut(DomainId)) {
+        while self.next_edge() <= time {
+            self.step(&mut eval);
+        }
+    }
+}
+impl Default for Scheduler {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::register::*;
+
+    #[test]
+    fn clock_ratio() {
+        let mut sched = Scheduler::new();
+        let core = sched.add_domain("core", 1, 0);
+        let mem = sched.add_domain("mem", 3, 0);
+
+        let r_core = Rc::new(RefCell::new(Reg::new(0u32)));
+        let r_mem = Rc::new(RefCell::new(Reg::new(0u32)));
+        sched.domain_mut(core).state_mut().track(&r_core);
+        sched.domain_mut(mem).state_mut().track(&r_mem);
+
+        let mut log = Vec::new();
+        sched.run_until(8, |id| {
+            log.push((id, r_core.borrow().sample(), r_mem.borrow().sample()));
+            if id == core {
+                let x = r_core.borrow().sample();
+                r_core.borrow_mut().drive(x + 1);
+            } else {
+                let x = r_mem.borrow().sample();
+                r_mem.borrow_mut().drive(x + 1);
+            }
+        });
+        assert_eq!(sched.domain(core).cycle(), 9);
+        assert_eq!(sched.domain(mem).cycle(), 3);
+        assert_eq!(sched.time(), 8);
+
+        // Both domains observe values from before a coincident edge
+        assert_eq!(log[0], (core, 0, 0));
+        assert_eq!(log[1], (mem, 0, 0));
+        assert_eq!(log[2], (core, 1, 1));
+        assert_eq!(log[4], (core, 3, 1));
+        assert_eq!(log[5], (mem, 3, 1));
+    }
+
+    #[test]
+    fn clock_phase() {
+        let mut sched = Scheduler::new();
+        let a = sched.add_domain("a", 4, 0);
+        let b = sched.add_domain("b", 4, 2);
+        let mut edges = Vec::new();
+        for _ in 0..4 {
+            let mut ids = Vec::new();
+            let time = sched.step(|id| ids.push(id));
+            edges.push((time, ids));
+        }
+        assert_eq!(edges, vec![
+            (0, vec![a]), (2, vec![b]), (4, vec![a]), (6, vec![b]),
+        ]);
+    }
+}
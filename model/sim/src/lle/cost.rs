@@ -0,0 +1,339 @@
+//! Storage cost estimates.
+//!
+//! Components implementing [Cost] report the storage they would need in
+//! hardware to a [CostReport]: the number of entries, the width of each
+//! entry, and the number of read/write ports. Like [Trace](crate::lle::trace::Trace),
+//! each component has a hierarchical path (ie. `top.core.rob`), and a
+//! [CostReport] can aggregate the number of bits under any part of the
+//! hierarchy. This is meant for comparing the cost of configurations
+//! before writing any RTL.
+//!
+//! The width of a value is given by [HwBits], which has to be implemented
+//! for any type held in a storage element. This is the number of bits a
+//! value needs in hardware, which is usually much smaller than the size of
+//! the Rust type (ie. a physical register index is a `UInt<8>` for 256
+//! physical registers, but takes up a whole `u64` in the model).
+
+use std::fmt;
+
+use crate::lle::bits::*;
+use crate::lle::ring::*;
+
+pub use sim_derive::{Cost, HwBits};
+
+/// Returns the number of bits needed to select one of `n` values (ie. the
+/// tag of an enum with `n` variants).
+pub const fn index_bits(n: usize) -> u64 {
+    n.next_power_of_two().trailing_zeros() as u64
+}
+
+/// A value with a fixed width in hardware.
+pub trait HwBits {
+    /// Number of bits needed to store this value.
+    const BITS: u64;
+}
+macro_rules! impl_hw_bits {
+    ($($ty:ty),*) => { $(
+        impl HwBits for $ty {
+            const BITS: u64 = <$ty>::BITS as u64;
+        }
+    )* }
+}
+impl_hw_bits!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl HwBits for () {
+    const BITS: u64 = 0;
+}
+impl HwBits for bool {
+    const BITS: u64 = 1;
+}
+/// An [Option] needs an extra 'valid' bit.
+impl <T: HwBits> HwBits for Option<T> {
+    const BITS: u64 = T::BITS + 1;
+}
+impl <T: HwBits, const N: usize> HwBits for [T; N] {
+    const BITS: u64 = T::BITS * N as u64;
+}
+impl <A: HwBits, B: HwBits> HwBits for (A, B) {
+    const BITS: u64 = A::BITS + B::BITS;
+}
+impl <const N: usize> HwBits for UInt<N> {
+    const BITS: u64 = N as u64;
+}
+impl <const N: usize> HwBits for SInt<N> {
+    const BITS: u64 = N as u64;
+}
+/// An index into `N` entries, plus the wrap bit.
+impl <const N: usize> HwBits for RingPtr<N> {
+    const BITS: u64 = index_bits(N) + 1;
+}
+
+/// The storage used by a single component.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageCost {
+    /// Hierarchical path to the component
+    pub path: String,
+    /// Type of storage (ie. `Reg` or `SyncMem`)
+    pub kind: &'static str,
+    /// Number of entries
+    pub entries: u64,
+    /// Width of each entry (in bits)
+    pub width: u64,
+    /// Number of read ports (or [None] when this isn't fixed by the model)
+    pub read_ports: Option<usize>,
+    /// Number of write ports (or [None] when this isn't fixed by the model)
+    pub write_ports: Option<usize>,
+    /// The model doesn't bound the number of entries, and `entries` is
+    /// only the current occupancy.
+    pub unbounded: bool,
+}
+impl StorageCost {
+    pub fn new(kind: &'static str, entries: usize, width: u64) -> Self {
+        Self {
+            path: String::new(),
+            kind,
+            entries: entries as u64,
+            width,
+            read_ports: None,
+            write_ports: None,
+            unbounded: false,
+        }
+    }
+    pub fn with_ports(mut self, read: usize, write: usize) -> Self {
+        self.read_ports = Some(read);
+        self.write_ports = Some(write);
+        self
+    }
+    pub fn with_read_ports(mut self, read: usize) -> Self {
+        self.read_ports = Some(read);
+        self
+    }
+    pub fn with_write_ports(mut self, write: usize) -> Self {
+        self.write_ports = Some(write);
+        self
+    }
+    /// Mark the number of entries as the current occupancy.
+    pub fn unbounded(mut self) -> Self {
+        self.unbounded = true;
+        self
+    }
+
+    /// Returns the total number of bits.
+    pub fn bits(&self) -> u64 {
+        self.entries * self.width
+    }
+}
+
+/// A component with storage.
+pub trait Cost {
+    /// Report the storage in this component, where `path` is the
+    /// hierarchical name of this component.
+    fn cost(&self, path: &str, r: &mut CostReport);
+}
+impl <T: Cost, const N: usize> Cost for [T; N] {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        for (idx, x) in self.iter().enumerate() {
+            x.cost(&format!("{}[{}]", path, idx), r);
+        }
+    }
+}
+impl <T: Cost> Cost for Vec<T> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        for (idx, x) in self.iter().enumerate() {
+            x.cost(&format!("{}[{}]", path, idx), r);
+        }
+    }
+}
+impl <T: Cost + ?Sized> Cost for Box<T> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        self.as_ref().cost(path, r);
+    }
+}
+
+/// The storage used by a set of components.
+#[derive(Clone, Debug, Default)]
+pub struct CostReport {
+    items: Vec<StorageCost>,
+}
+impl CostReport {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+    /// Collect the storage used by `root` (and everything below it).
+    pub fn of(path: &str, root: &dyn Cost) -> Self {
+        let mut res = Self::new();
+        root.cost(path, &mut res);
+        res
+    }
+
+    /// Record the storage used by the component at `path`.
+    pub fn add(&mut self, path: &str, mut item: StorageCost) {
+        item.path = path.to_string();
+        self.items.push(item);
+    }
+
+    /// Returns every component (in the order they were reported).
+    pub fn items(&self) -> &[StorageCost] { &self.items }
+
+    /// Returns the total number of bits.
+    pub fn total_bits(&self) -> u64 {
+        self.items.iter().map(|x| x.bits()).sum()
+    }
+
+    /// Returns the number of bits used by the component at `path`, and
+    /// everything below it.
+    pub fn bits(&self, path: &str) -> u64 {
+        self.items.iter().filter(|x| Self::is_under(&x.path, path))
+            .map(|x| x.bits()).sum()
+    }
+
+    fn is_under(path: &str, scope: &str) -> bool {
+        match path.strip_prefix(scope) {
+            Some(rest) => rest.is_empty() || rest.starts_with(['.', '[']),
+            None => false,
+        }
+    }
+
+    /// Returns every scope in the hierarchy (in the order they were first
+    /// reported), with the depth of each scope.
+    fn scopes(&self) -> Vec<(&str, usize)> {
+        let mut res: Vec<(&str, usize)> = Vec::new();
+        for item in self.items.iter() {
+            let path = item.path.as_str();
+            let ends = path.match_indices('.').map(|(idx, _)| idx)
+                .chain(std::iter::once(path.len()));
+            for (depth, end) in ends.enumerate() {
+                let scope = &path[..end];
+                if !res.iter().any(|(s, _)| *s == scope) {
+                    res.push((scope, depth));
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Prints the hierarchy with the number of bits in each scope, and the
+/// organization of each component. Ports are given as `<read>r<write>w`
+/// (or `?` when unknown), and unbounded entries are marked with `*`.
+impl fmt::Display for CostReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let port = |x: Option<usize>| x.map_or("?".to_string(), |x| x.to_string());
+        for (scope, depth) in self.scopes() {
+            let name = scope.rsplit('.').next().unwrap();
+            let indent = format!("{:1$}{2}", "", depth * 2, name);
+            write!(f, "{:<32} {:>10}", indent, self.bits(scope))?;
+            if let Some(item) = self.items.iter().find(|x| x.path == scope) {
+                let org = format!("{}{} x {}", item.entries,
+                    if item.unbounded { "*" } else { "" }, item.width);
+                write!(f, "  {:<14} {:<12} {}r{}w", item.kind, org,
+                    port(item.read_ports), port(item.write_ports))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::register::*;
+    use crate::lle::mem::*;
+    use crate::lle::syncmem::*;
+    use crate::lle::cam::*;
+    use crate::lle::queue::*;
+    use crate::lle::alloc::*;
+
+    #[derive(Cost)]
+    struct Backend {
+        rob: CircularQueue<(u32, bool), 32>,
+        prf: SyncMem<u32, 64, 4, 2>,
+        frl: BitAllocator<64, 2, 2>,
+    }
+
+    #[derive(Cost)]
+    struct Core {
+        pc: Reg<u32>,
+        btb: BoundedCam<UInt<20>, u32, 16, 1>,
+        bht: Mem<UInt<2>, 256>,
+        be: Backend,
+        #[cost(skip)]
+        name: &'static str,
+    }
+
+    fn core() -> Core {
+        Core {
+            pc: Reg::new(0),
+            btb: BoundedCam::new(),
+            bht: Mem::new_init_val(UInt::ZERO).with_ports(2, 1),
+            be: Backend {
+                rob: CircularQueue::new(),
+                prf: SyncMem::new_init_val(0),
+                frl: BitAllocator::new(),
+            },
+            name: "core",
+        }
+    }
+
+    #[test]
+    fn cost_primitives() {
+        let r = CostReport::of("core", &core());
+        let find = |path: &str| r.items().iter().find(|x| x.path == path)
+            .unwrap().clone();
+
+        assert_eq!(r.bits("core.pc"), 32);
+        // Key, value and a valid bit
+        assert_eq!(find("core.btb").width, 20 + 32 + 1);
+        assert_eq!(find("core.btb").write_ports, Some(1));
+        assert_eq!(find("core.btb").read_ports, None);
+        assert_eq!(r.bits("core.bht"), 512);
+        assert_eq!(find("core.bht").read_ports, Some(2));
+        // Entries (with a valid bit) and two 6-bit pointers
+        assert_eq!(r.bits("core.be.rob"), 32 * 34 + 2 * 6);
+        assert_eq!(find("core.be.prf").read_ports, Some(4));
+        assert_eq!(find("core.be.prf").write_ports, Some(2));
+        assert_eq!(r.bits("core.be.frl"), 64);
+    }
+
+    #[test]
+    fn cost_hierarchy() {
+        let r = CostReport::of("core", &core());
+        let be = 32 * 34 + 2 * 6 + 64 * 32 + 64;
+        assert_eq!(r.bits("core.be"), be);
+        assert_eq!(r.bits("core"), r.total_bits());
+        assert_eq!(r.total_bits(), 32 + 16 * 53 + 512 + be);
+        // Only whole path components match
+        assert_eq!(r.bits("core.b"), 0);
+
+        let out = r.to_string();
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("core "));
+        assert!(lines[1].starts_with("  pc "));
+        assert!(lines.iter().any(|l| l.starts_with("    prf ")
+            && l.contains(&format!("{}", 64 * 32)) && l.ends_with("4r2w")));
+        assert!(lines.iter().any(|l| l.starts_with("  btb ") && l.ends_with("?r1w")));
+    }
+
+    #[test]
+    fn cost_hw_bits_derive() {
+        #[derive(HwBits)]
+        enum Op { Nop, Add, Sub }
+        #[derive(HwBits)]
+        enum Exit {
+            Sequential,
+            Jmp(UInt<3>),
+            Call { idx: UInt<3>, ret: bool },
+        }
+        #[derive(HwBits)]
+        struct Uop {
+            op: Op,
+            exit: Exit,
+            rd: Option<UInt<5>>,
+        }
+        assert_eq!(Op::BITS, 2);
+        // A 2-bit tag, and the largest variant
+        assert_eq!(Exit::BITS, 2 + 4);
+        assert_eq!(Uop::BITS, 2 + 6 + 6);
+    }
+}
@@ -0,0 +1,344 @@
+//! Bounded channels with a ready/valid handshake.
+//!
+//! [Decoupled] behaves like a Chisel `Queue` between two `Decoupled`
+//! interfaces. Each cycle, the producer drives 'valid' (with
+//! [Decoupled::drive_enq]) and the consumer drives 'ready' (with
+//! [Decoupled::drive_deq_ready]). An element moves across either side of
+//! the channel at the next clock edge when both 'ready' and 'valid' are
+//! asserted on that side.
+//!
+//! The producer must keep 'valid' asserted until the element is accepted.
+//! Dropping 'valid' before 'ready' is a protocol violation, and causes a
+//! panic at the clock edge.
+//!
+//! Variants
+//! ========
+//!
+//! - A *pipe* channel is ready to accept a new element while full if the
+//!   oldest element is being dequeued on the same cycle. The consumer must
+//!   drive 'ready' before the producer samples [Decoupled::enq_ready].
+//!
+//! - A *flow* channel passes an element straight through to the consumer
+//!   (with no latency) when empty. The producer must drive 'valid' before
+//!   the consumer samples [Decoupled::deq].
+
+use std::collections::*;
+
+use crate::lle::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
+
+/// Occupancy statistics for a [Decoupled] channel.
+///
+/// NOTE: These aren't part of the simulated state, and are *not* cleared
+/// when the channel is reset or flushed.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelStats {
+    /// Number of clock edges observed
+    pub cycles: usize,
+    /// Number of elements accepted from the producer
+    pub enqs: usize,
+    /// Number of elements delivered to the consumer
+    pub deqs: usize,
+    /// Cycles where the producer was stalled ('valid' without 'ready')
+    pub enq_stalls: usize,
+    /// Cycles where the consumer was starved ('ready' without 'valid')
+    pub deq_stalls: usize,
+    /// Number of cycles spent with each occupancy (indexed by occupancy)
+    pub occupancy: Vec<usize>,
+}
+impl ChannelStats {
+    /// Returns the average number of entries in use.
+    pub fn avg_occupancy(&self) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        let sum: usize = self.occupancy.iter().enumerate()
+            .map(|(occ, cycles)| occ * cycles).sum();
+        sum as f64 / self.cycles as f64
+    }
+    /// Returns the largest occupancy observed.
+    pub fn max_occupancy(&self) -> usize {
+        self.occupancy.iter().rposition(|c| *c != 0).unwrap_or(0)
+    }
+}
+
+/// A bounded FIFO channel with a ready/valid handshake on both sides.
+pub struct Decoupled<T> {
+    data: VecDeque<T>,
+    depth: usize,
+    pipe: bool,
+    flow: bool,
+
+    /// 'valid' (and 'bits') driven by the producer on this cycle
+    enq: Option<T>,
+    /// 'ready' driven by the consumer on this cycle
+    deq_ready: bool,
+    /// Flush driven on this cycle
+    flush: bool,
+    /// Set when 'valid' was asserted on the last cycle without 'ready'
+    enq_stalled: bool,
+
+    stats: ChannelStats,
+}
+impl <T> Decoupled<T> {
+    /// Create a channel with `depth` entries.
+    pub fn new(depth: usize) -> Self {
+        assert!(depth != 0, "Decoupled channel must have a nonzero depth");
+        Self {
+            data: VecDeque::with_capacity(depth),
+            depth,
+            pipe: false,
+            flow: false,
+            enq: None,
+            deq_ready: false,
+            flush: false,
+            enq_stalled: false,
+            stats: ChannelStats {
+                occupancy: vec![0; depth + 1],
+                ..Default::default()
+            },
+        }
+    }
+    /// Allow enqueuing while full when the oldest entry is being dequeued.
+    pub fn with_pipe(mut self) -> Self {
+        self.pipe = true;
+        self
+    }
+    /// Allow elements to bypass the channel when it's empty.
+    pub fn with_flow(mut self) -> Self {
+        self.flow = true;
+        self
+    }
+
+    pub fn depth(&self) -> usize { self.depth }
+    pub fn len(&self) -> usize { self.data.len() }
+    pub fn is_empty(&self) -> bool { self.data.is_empty() }
+    pub fn is_full(&self) -> bool { self.data.len() == self.depth }
+    pub fn stats(&self) -> &ChannelStats { &self.stats }
+
+    /// Returns an iterator over the stored entries (oldest first).
+    pub fn iter(&self) -> impl Iterator<Item=&T> {
+        self.data.iter()
+    }
+
+    /// Sample 'ready' on the producer side.
+    pub fn enq_ready(&self) -> bool {
+        !self.is_full() || (self.pipe && self.deq_ready)
+    }
+
+    /// Drive 'valid' on the producer side for this cycle. The element is
+    /// accepted at the next clock edge if [Decoupled::enq_ready] is true.
+    pub fn drive_enq(&mut self, data: T) {
+        self.enq = Some(data);
+    }
+
+    /// Returns true if the element driven by the producer on this cycle
+    /// will be accepted.
+    pub fn enq_fire(&self) -> bool {
+        self.enq.is_some() && self.enq_ready()
+    }
+
+    /// Sample 'valid' (and 'bits') on the consumer side.
+    pub fn deq(&self) -> Option<&T> {
+        match self.data.front() {
+            Some(data) => Some(data),
+            None if self.flow => self.enq.as_ref(),
+            None => None,
+        }
+    }
+    /// Sample 'valid' on the consumer side.
+    pub fn deq_valid(&self) -> bool {
+        self.deq().is_some()
+    }
+
+    /// Drive 'ready' on the consumer side for this cycle. The oldest element
+    /// is removed at the next clock edge if [Decoupled::deq_valid] is true.
+    pub fn drive_deq_ready(&mut self, ready: bool) {
+        self.deq_ready = ready;
+    }
+
+    /// Returns true if the consumer will take an element on this cycle.
+    pub fn deq_fire(&self) -> bool {
+        self.deq_ready && self.deq_valid()
+    }
+
+    /// Drive a flush for this cycle. All entries are discarded at the next
+    /// clock edge (including anything being enqueued on this cycle).
+    pub fn flush(&mut self) {
+        self.flush = true;
+    }
+}
+impl <T> Clocked for Decoupled<T> {
+    fn update(&mut self) {
+        let enq_fire = self.enq_fire();
+        let deq_fire = self.deq_fire();
+
+        assert!(!self.enq_stalled || self.enq.is_some(),
+            "Decoupled protocol violation: 'valid' dropped without 'ready'");
+        self.enq_stalled = self.enq.is_some() && !enq_fire && !self.flush;
+
+        self.stats.cycles += 1;
+        self.stats.occupancy[self.data.len()] += 1;
+        if self.enq.is_some() && !enq_fire { self.stats.enq_stalls += 1; }
+        if self.deq_ready && !deq_fire { self.stats.deq_stalls += 1; }
+        if enq_fire { self.stats.enqs += 1; }
+        if deq_fire { self.stats.deqs += 1; }
+
+        let enq = self.enq.take();
+        self.deq_ready = false;
+        if std::mem::take(&mut self.flush) {
+            self.data.clear();
+            return;
+        }
+
+        // An element flowing straight through is never stored
+        if deq_fire && self.data.is_empty() {
+            return;
+        }
+        if deq_fire {
+            self.data.pop_front();
+        }
+        if enq_fire {
+            self.data.push_back(enq.unwrap());
+        }
+    }
+}
+impl <T> Resettable for Decoupled<T> {
+    fn reset(&mut self) {
+        self.data.clear();
+        self.enq = None;
+        self.deq_ready = false;
+        self.flush = false;
+        self.enq_stalled = false;
+    }
+}
+impl <T: HwBits> Cost for Decoupled<T> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("Decoupled", self.depth, T::BITS)
+            .with_ports(1, 1));
+    }
+}
+/// NOTE: Only the number of entries is traced.
+impl <T> Trace for Decoupled<T> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(&format!("{}.len", path), 32, Some(self.data.len() as u64));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Drive one cycle of a producer (sending 'next' if ready) and a
+    /// consumer (taking an element when 'ready' is asserted).
+    fn cycle(ch: &mut Decoupled<u32>, next: &mut u32, ready: bool,
+        out: &mut Vec<u32>)
+    {
+        ch.drive_deq_ready(ready);
+        if let Some(x) = ch.deq() {
+            if ready { out.push(*x); }
+        }
+        ch.drive_enq(*next);
+        if ch.enq_ready() {
+            *next += 1;
+        }
+        ch.update();
+    }
+
+    #[test]
+    fn decoupled_backpressure() {
+        let mut ch = Decoupled::new(2);
+        let mut next = 0;
+        let mut out = Vec::new();
+        for _ in 0..4 {
+            cycle(&mut ch, &mut next, false, &mut out);
+        }
+        assert!(ch.is_full());
+        assert_eq!(next, 2);
+
+        // Without 'pipe', a full channel can't enqueue while dequeuing
+        cycle(&mut ch, &mut next, true, &mut out);
+        assert_eq!(ch.len(), 1);
+        for _ in 0..5 {
+            cycle(&mut ch, &mut next, true, &mut out);
+        }
+        assert_eq!(out, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(ch.stats().enqs, 7);
+        assert_eq!(ch.stats().deqs, 6);
+        assert_eq!(ch.stats().max_occupancy(), 2);
+    }
+
+    #[test]
+    fn decoupled_pipe() {
+        let mut ch = Decoupled::new(1).with_pipe();
+        let mut next = 0;
+        let mut out = Vec::new();
+        for _ in 0..5 {
+            cycle(&mut ch, &mut next, true, &mut out);
+        }
+        // Full throughput with a single entry
+        assert_eq!(out, vec![0, 1, 2, 3]);
+        assert_eq!(ch.stats().occupancy, vec![1, 4]);
+    }
+
+    #[test]
+    fn decoupled_flow() {
+        let mut ch = Decoupled::new(2).with_flow();
+        let mut out = Vec::new();
+        ch.drive_enq(7);
+        ch.drive_deq_ready(true);
+        out.extend(ch.deq().copied());
+        ch.update();
+        assert!(ch.is_empty());
+        assert_eq!(out, vec![7]);
+
+        // Stored normally when the consumer isn't ready
+        ch.drive_enq(8);
+        ch.update();
+        assert_eq!(ch.len(), 1);
+        ch.drive_enq(9);
+        assert_eq!(ch.deq(), Some(&8));
+    }
+
+    #[test]
+    fn decoupled_flush() {
+        let mut ch = Decoupled::new(4);
+        ch.drive_enq(1);
+        ch.update();
+        ch.drive_enq(2);
+        ch.flush();
+        ch.update();
+        assert!(ch.is_empty());
+        // A stalled element may be dropped by a flush
+        ch.update();
+        assert_eq!(ch.stats().enqs, 2);
+    }
+
+    #[test]
+    fn decoupled_stats() {
+        let mut ch = Decoupled::new(2);
+        ch.drive_deq_ready(true);
+        ch.update();
+        ch.drive_enq(1);
+        ch.update();
+        ch.update();
+        let stats = ch.stats();
+        assert_eq!(stats.cycles, 3);
+        assert_eq!(stats.deq_stalls, 1);
+        assert_eq!(stats.occupancy, vec![2, 1, 0]);
+        assert!((stats.avg_occupancy() - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "'valid' dropped")]
+    fn decoupled_valid_drop() {
+        let mut ch = Decoupled::new(1);
+        ch.drive_enq(1);
+        ch.update();
+        ch.drive_enq(2);
+        ch.update();
+        // Stalled on the last cycle, so 'valid' must stay asserted
+        ch.update();
+    }
+}
@@ -0,0 +1,432 @@
+//! Design rule checking for simulated signals.
+//!
+//! When DRC is enabled (see [enable]), [Reg](crate::lle::register::Reg)
+//! and [Wire](crate::lle::wire::Wire) record where they were created and
+//! driven, and report the following at each clock edge:
+//!
+//! - Multiple drivers (a signal was driven more than once in a cycle)
+//! - Undriven wires (a wire was never driven before the clock edge)
+//! - Reads-before-drive (a wire was sampled before it was driven)
+//!
+//! Inside a block of combinational logic run by an
+//! [Evaluator](crate::lle::eval::Evaluator), named wires which are driven
+//! or sampled without being declared by the block are also reported (see
+//! [BlockScope]).
+//!
+//! Violations are collected until they're taken with [take_violations].
+//! The state is thread-local, so tests running in parallel don't interfere
+//! with each other.
+//!
+//! Locations are obtained with `#[track_caller]`, so methods that drive
+//! signals on behalf of their caller should also be `#[track_caller]`.
+//!
+//! NOTE: Violations are stamped with the cycle count of the container
+//! being updated (see [CycleScope]), so components in a nested
+//! [ClockedState](crate::lle::ClockedState), or in another clock domain of
+//! a [Scheduler](crate::lle::clock::Scheduler), are stamped with their own
+//! cycle count. Designs that update components directly can call [tick] at
+//! the end of each cycle instead.
+
+use std::cell::*;
+use std::fmt;
+use std::panic::Location;
+
+/// The name and creation site of a signal.
+#[derive(Clone, Copy, Debug)]
+pub struct SignalInfo {
+    pub name: Option<&'static str>,
+    pub site: &'static Location<'static>,
+}
+impl SignalInfo {
+    #[track_caller]
+    pub fn new() -> Self {
+        Self { name: None, site: Location::caller() }
+    }
+}
+impl Default for SignalInfo {
+    #[track_caller]
+    fn default() -> Self { Self::new() }
+}
+impl fmt::Display for SignalInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' (created at {})", self.name.unwrap_or("<unnamed>"),
+            self.site)
+    }
+}
+
+/// Per-cycle record of the drivers and readers of a signal.
+#[derive(Clone, Debug, Default)]
+pub struct DriveSites {
+    /// Every site that drove the signal (in order)
+    pub drivers: Vec<&'static Location<'static>>,
+    /// The first site that sampled the signal before it was driven
+    pub early_read: Option<&'static Location<'static>>,
+}
+impl DriveSites {
+    pub fn record_drive(&mut self, site: &'static Location<'static>) {
+        self.drivers.push(site);
+    }
+    pub fn record_read(&mut self, site: &'static Location<'static>) {
+        if self.drivers.is_empty() && self.early_read.is_none() {
+            self.early_read = Some(site);
+        }
+    }
+
+    /// Report any violations for this cycle and reset.
+    ///
+    /// NOTE: The list of drivers is cleared (instead of dropped), so it's
+    /// only allocated once for each signal.
+    pub fn check(&mut self, signal: &SignalInfo) {
+        if enabled() {
+            if self.drivers.len() > 1 {
+                report(ViolationKind::MultipleDrivers, signal, 
+                    self.drivers.clone());
+            }
+            if let Some(read) = self.early_read {
+                let mut sites = vec![read];
+                sites.extend(self.drivers.first());
+                report(ViolationKind::ReadBeforeDrive, signal, sites);
+            }
+        }
+        self.drivers.clear();
+        self.early_read = None;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    MultipleDrivers,
+    Undriven,
+    ReadBeforeDrive,
+    /// Driven by a combinational block which doesn't declare it as a write
+    UndeclaredDrive { block: &'static str },
+    /// Sampled by a combinational block which doesn't declare it as a read
+    UndeclaredRead { block: &'static str },
+}
+
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub cycle: usize,
+    pub kind: ViolationKind,
+    pub signal: SignalInfo,
+    /// Source locations associated with this violation:
+    ///
+    /// - For [ViolationKind::MultipleDrivers], every driver (in order)
+    /// - For [ViolationKind::Undriven], nothing
+    /// - For [ViolationKind::ReadBeforeDrive], the reader, then the driver
+    ///   (if the signal was driven)
+    /// - For [ViolationKind::UndeclaredDrive] and
+    ///   [ViolationKind::UndeclaredRead], the driver or reader
+    pub sites: Vec<&'static Location<'static>>,
+}
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            ViolationKind::MultipleDrivers => "multiple drivers".to_string(),
+            ViolationKind::Undriven => "undriven".to_string(),
+            ViolationKind::ReadBeforeDrive => "read before drive".to_string(),
+            ViolationKind::UndeclaredDrive { block } => {
+                format!("undeclared drive in block '{}'", block)
+            },
+            ViolationKind::UndeclaredRead { block } => {
+                format!("undeclared read in block '{}'", block)
+            },
+        };
+        write!(f, "[cycle {}] {}: {}", self.cycle, kind, self.signal)?;
+        for site in self.sites.iter() {
+            write!(f, "\n    at {}", site)?;
+        }
+        Ok(())
+    }
+}
+
+/// The signals declared by a combinational block.
+#[derive(Clone)]
+struct BlockDecl {
+    name: &'static str,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+}
+
+#[derive(Default)]
+struct DrcState {
+    enabled: bool,
+    cycle: usize,
+    violations: Vec<Violation>,
+    /// The combinational block being evaluated
+    block: Option<BlockDecl>,
+}
+
+thread_local! {
+    static DRC: RefCell<DrcState> = RefCell::new(DrcState::default());
+}
+
+/// Enable design rule checking (on this thread).
+pub fn enable() {
+    DRC.with(|drc| drc.borrow_mut().enabled = true);
+}
+
+/// Disable design rule checking (on this thread).
+pub fn disable() {
+    DRC.with(|drc| drc.borrow_mut().enabled = false);
+}
+
+pub fn enabled() -> bool {
+    DRC.with(|drc| drc.borrow().enabled)
+}
+
+/// Advance the cycle count used to stamp violations (outside of any
+/// [CycleScope]).
+pub fn tick() {
+    DRC.with(|drc| drc.borrow_mut().cycle += 1);
+}
+
+/// Stamps violations with the cycle count of a container while it's being
+/// updated. The previous cycle count is restored when this is dropped.
+pub struct CycleScope {
+    prev: usize,
+}
+impl CycleScope {
+    pub fn enter(cycle: usize) -> Self {
+        let prev = DRC.with(|drc| {
+            std::mem::replace(&mut drc.borrow_mut().cycle, cycle)
+        });
+        Self { prev }
+    }
+}
+impl Drop for CycleScope {
+    fn drop(&mut self) {
+        DRC.with(|drc| drc.borrow_mut().cycle = self.prev);
+    }
+}
+
+/// Checks accesses to named signals against the declarations of a
+/// combinational block while it's being evaluated. The previous block (if
+/// any) is restored when this is dropped.
+///
+/// NOTE: Only [Wire](crate::lle::wire::Wire) is checked. The output of a
+/// register is fixed until the clock edge, so it doesn't need to be 
+/// declared.
+pub struct BlockScope {
+    prev: Option<BlockDecl>,
+}
+impl BlockScope {
+    pub fn enter(name: &'static str, reads: &[&'static str], 
+        writes: &[&'static str]) -> Self 
+    {
+        let decl = BlockDecl { 
+            name, reads: reads.to_vec(), writes: writes.to_vec() 
+        };
+        let prev = DRC.with(|drc| drc.borrow_mut().block.replace(decl));
+        Self { prev }
+    }
+}
+impl Drop for BlockScope {
+    fn drop(&mut self) {
+        DRC.with(|drc| drc.borrow_mut().block = self.prev.take());
+    }
+}
+
+/// Report a named signal driven by the current block without declaring it.
+pub fn check_drive(signal: &SignalInfo, site: &'static Location<'static>) {
+    check_access(signal, site, true);
+}
+
+/// Report a named signal sampled by the current block without declaring it
+/// (as a read or a write).
+pub fn check_read(signal: &SignalInfo, site: &'static Location<'static>) {
+    check_access(signal, site, false);
+}
+
+fn check_access(signal: &SignalInfo, site: &'static Location<'static>,
+    write: bool)
+{
+    let Some(name) = signal.name else { return };
+    let kind = DRC.with(|drc| {
+        let drc = drc.borrow();
+        if !drc.enabled {
+            return None;
+        }
+        let block = drc.block.as_ref()?;
+        if block.writes.contains(&name) {
+            return None;
+        }
+        if write {
+            Some(ViolationKind::UndeclaredDrive { block: block.name })
+        } else if !block.reads.contains(&name) {
+            Some(ViolationKind::UndeclaredRead { block: block.name })
+        } else {
+            None
+        }
+    });
+    if let Some(kind) = kind {
+        report(kind, signal, vec![site]);
+    }
+}
+
+/// The DRC state of a thread (used to run part of a design on another
+/// thread, see [crate::lle::arena::Arena::with_threads]).
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Snapshot {
+    enabled: bool,
+    cycle: usize,
+}
+impl Snapshot {
+    pub(crate) fn take() -> Self {
+        DRC.with(|drc| {
+            let drc = drc.borrow();
+            Self { enabled: drc.enabled, cycle: drc.cycle }
+        })
+    }
+    /// Use this state on the current thread (discarding any violations).
+    pub(crate) fn install(&self) {
+        DRC.with(|drc| *drc.borrow_mut() = DrcState {
+            enabled: self.enabled,
+            cycle: self.cycle,
+            violations: Vec::new(),
+            block: None,
+        });
+    }
+}
+
+/// Add violations reported on another thread.
+pub(crate) fn merge(violations: Vec<Violation>) {
+    DRC.with(|drc| drc.borrow_mut().violations.extend(violations));
+}
+
+/// Take all violations reported so far.
+pub fn take_violations() -> Vec<Violation> {
+    DRC.with(|drc| std::mem::take(&mut drc.borrow_mut().violations))
+}
+
+/// Report a violation (when DRC is enabled).
+pub fn report(kind: ViolationKind, signal: &SignalInfo,
+    sites: Vec<&'static Location<'static>>)
+{
+    DRC.with(|drc| {
+        let mut drc = drc.borrow_mut();
+        if drc.enabled {
+            let cycle = drc.cycle;
+            drc.violations.push(Violation { cycle, kind, signal: *signal, sites });
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::*;
+    use crate::lle::register::*;
+    use crate::lle::wire::*;
+    use crate::lle::clock::*;
+
+    #[test]
+    fn drc_multiple_drivers() {
+        enable();
+        let mut r = Reg::new(0u32).named("r_count");
+        r.drive(1);
+        r.drive(2);
+        r.update();
+        assert_eq!(r.sample(), 2);
+
+        let v = take_violations();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].kind, ViolationKind::MultipleDrivers);
+        assert_eq!(v[0].signal.name, Some("r_count"));
+        assert_eq!(v[0].sites.len(), 2);
+        assert_eq!(v[0].sites[0].line() + 1, v[0].sites[1].line());
+        assert!(v[0].to_string().contains("r_count"));
+    }
+
+    /// Every driver is reported (not just the first and last).
+    #[test]
+    fn drc_three_drivers() {
+        enable();
+        let mut w = Wire::<u32>::new().named("w_three");
+        w.drive(1);
+        w.drive(2);
+        w.drive(3);
+        w.update();
+
+        let v = take_violations();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].kind, ViolationKind::MultipleDrivers);
+        assert_eq!(v[0].sites.len(), 3);
+        assert_eq!(v[0].sites[0].line() + 1, v[0].sites[1].line());
+        assert_eq!(v[0].sites[1].line() + 1, v[0].sites[2].line());
+
+        // The drivers are cleared at the clock edge
+        w.drive(4);
+        w.update();
+        assert!(take_violations().is_empty());
+    }
+
+    #[test]
+    fn drc_undriven_wire() {
+        enable();
+        let mut state = ClockedState::new();
+        let w = Rc::new(RefCell::new(Wire::<bool>::new().named("w_valid")));
+        state.track(&w);
+
+        w.borrow_mut().drive(true);
+        state.update();
+        state.update();
+
+        let v = take_violations();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].kind, ViolationKind::Undriven);
+        assert_eq!(v[0].cycle, 1);
+        assert_eq!(v[0].signal.name, Some("w_valid"));
+    }
+
+    /// Violations are stamped with the cycle count of their own domain.
+    #[test]
+    fn drc_domain_cycle() {
+        enable();
+        let mut sched = Scheduler::new();
+        let fast = sched.add_domain("fast", 1, 0);
+        let slow = sched.add_domain("slow", 4, 0);
+        let w = Rc::new(RefCell::new(Wire::<bool>::new().named("w_slow")));
+        sched.domain_mut(slow).state_mut().track(&w);
+
+        // Only drive the wire on the first edge in the slow domain
+        let mut edges = 0;
+        sched.run_until(4, |id| if id == slow {
+            if edges == 0 {
+                w.borrow_mut().drive(true);
+            }
+            edges += 1;
+        });
+        assert_eq!(sched.domain(fast).cycle(), 5);
+
+        let v = take_violations();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].kind, ViolationKind::Undriven);
+        assert_eq!(v[0].cycle, 1);
+    }
+
+    #[test]
+    fn drc_read_before_drive() {
+        enable();
+        let mut w = Wire::<u32>::new().named("w_data");
+        w.drive(1);
+        w.update();
+
+        // The stale value from the previous cycle is observed
+        assert_eq!(w.sample(), 1);
+        w.drive(2);
+        w.update();
+
+        let v = take_violations();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].kind, ViolationKind::ReadBeforeDrive);
+        assert_eq!(v[0].sites.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "'w_ready'")]
+    fn drc_disabled_names_wire() {
+        let mut w = Wire::<bool>::new().named("w_ready");
+        w.update();
+    }
+}
@@ -0,0 +1,310 @@
+//! Ordering combinational logic.
+//!
+//! Each cycle has two phases:
+//!
+//! 1. An *eval* phase, where combinational logic samples state and drives
+//!    signals (ie. wires, and the inputs to registers)
+//! 2. An *update* phase (the clock edge), see [Clocked::update]
+//!
+//! Without any help, the combinational logic in a cycle has to be written
+//! in an order where every signal is driven before it's sampled. Instead,
+//! an [Evaluator] holds a set of combinational blocks which each declare
+//! the signals they read and write. The blocks are sorted so that writers
+//! always run before readers, and so they can be added in any order.
+//!
+//! Signals are identified by name. Only signals which are driven and read
+//! within the same cycle need to be declared: the outputs of registers are
+//! fixed during the eval phase, and don't create any ordering.
+//!
+//! When design rule checking is enabled (see [crate::lle::drc]), a named
+//! [Wire](crate::lle::wire::Wire) which is driven or sampled by a block
+//! without being declared is reported as a violation.
+
+use std::cmp::Reverse;
+use std::collections::*;
+use std::fmt;
+
+use crate::lle::*;
+
+/// A problem with the signals declared by a set of combinational blocks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EvalError {
+    /// A path from the output of a block back to its own input, given as
+    /// the names of blocks in the loop (where the first is repeated at the
+    /// end).
+    CombLoop(Vec<&'static str>),
+    /// A signal is written by more than one block.
+    MultipleWriters { signal: &'static str, blocks: [&'static str; 2] },
+}
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CombLoop(path) => {
+                write!(f, "combinational loop: {}", path.join(" -> "))
+            },
+            Self::MultipleWriters { signal, blocks } => {
+                write!(f, "signal '{}' written by '{}' and '{}'",
+                    signal, blocks[0], blocks[1])
+            },
+        }
+    }
+}
+impl std::error::Error for EvalError {}
+
+/// A block of combinational logic operating on some context `C`.
+struct Block<C> {
+    name: &'static str,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+    f: Box<dyn FnMut(&mut C)>,
+}
+
+/// A set of combinational blocks, which are evaluated in dependency order.
+pub struct Evaluator<C> {
+    blocks: Vec<Block<C>>,
+    /// Order of evaluation (computed when first needed)
+    order: Option<Vec<usize>>,
+}
+impl <C> Evaluator<C> {
+    pub fn new() -> Self {
+        Self { blocks: Vec::new(), order: None }
+    }
+
+    /// Add a block named `name`, which samples the signals in `reads` and
+    /// drives the signals in `writes`.
+    pub fn add(&mut self, name: &'static str,
+        reads: &[&'static str], writes: &[&'static str],
+        f: impl FnMut(&mut C) + 'static)
+    {
+        self.blocks.push(Block {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            f: Box::new(f),
+        });
+        self.order = None;
+    }
+
+    /// Returns the order of evaluation (as the names of each block).
+    ///
+    /// Blocks without any dependencies between them are evaluated in the
+    /// order they were added.
+    pub fn order(&mut self) -> Result<Vec<&'static str>, EvalError> {
+        let order = self.sort()?;
+        Ok(order.iter().map(|idx| self.blocks[*idx].name).collect())
+    }
+
+    /// Run all blocks (in order).
+    ///
+    /// Panics if the blocks can't be ordered (see [Evaluator::order]).
+    pub fn eval(&mut self, ctx: &mut C) {
+        if self.order.is_none() {
+            match self.sort() {
+                Ok(order) => self.order = Some(order),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        for idx in self.order.as_ref().unwrap() {
+            let blk = &mut self.blocks[*idx];
+            let _drc = drc::enabled().then(|| {
+                drc::BlockScope::enter(blk.name, &blk.reads, &blk.writes)
+            });
+            (blk.f)(ctx);
+        }
+    }
+
+    /// Simulate a whole cycle: run all blocks, then the clock edge.
+    pub fn step(&mut self, ctx: &mut C) where C: Clocked {
+        self.eval(ctx);
+        ctx.update();
+    }
+
+    /// Topologically sort the blocks, where an edge from 'a' to 'b' means
+    /// that 'a' writes a signal read by 'b'.
+    fn sort(&self) -> Result<Vec<usize>, EvalError> {
+        let n = self.blocks.len();
+        let mut writer: HashMap<&'static str, usize> = HashMap::new();
+        for (idx, blk) in self.blocks.iter().enumerate() {
+            for sig in blk.writes.iter() {
+                if let Some(prev) = writer.insert(sig, idx) {
+                    return Err(EvalError::MultipleWriters {
+                        signal: sig,
+                        blocks: [self.blocks[prev].name, blk.name],
+                    });
+                }
+            }
+        }
+        let mut succs: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+        for (idx, blk) in self.blocks.iter().enumerate() {
+            for sig in blk.reads.iter() {
+                if let Some(w) = writer.get(sig) {
+                    succs[*w].insert(idx);
+                }
+            }
+        }
+
+        // Kahn's algorithm, always taking the earliest ready block
+        let mut indeg = vec![0; n];
+        for s in succs.iter().flatten() {
+            indeg[*s] += 1;
+        }
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..n)
+            .filter(|idx| indeg[*idx] == 0).map(Reverse).collect();
+        let mut res = Vec::with_capacity(n);
+        while let Some(Reverse(idx)) = ready.pop() {
+            res.push(idx);
+            for s in succs[idx].iter() {
+                indeg[*s] -= 1;
+                if indeg[*s] == 0 {
+                    ready.push(Reverse(*s));
+                }
+            }
+        }
+        if res.len() == n {
+            return Ok(res);
+        }
+
+        // Every remaining block has a predecessor which also remains, so
+        // walking backwards must eventually find a loop.
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for (idx, s) in succs.iter().enumerate() {
+            for x in s.iter() {
+                preds[*x].push(idx);
+            }
+        }
+        let mut path = vec![(0..n).find(|idx| indeg[*idx] != 0).unwrap()];
+        loop {
+            let cur = *path.last().unwrap();
+            let prev = *preds[cur].iter().find(|p| indeg[**p] != 0).unwrap();
+            if let Some(pos) = path.iter().position(|x| *x == prev) {
+                let mut cycle: Vec<_> = path[pos..].iter().rev()
+                    .map(|idx| self.blocks[*idx].name).collect();
+                cycle.rotate_right(1);
+                cycle.push(cycle[0]);
+                return Err(EvalError::CombLoop(cycle));
+            }
+            path.push(prev);
+        }
+    }
+}
+impl <C> Default for Evaluator<C> {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::register::*;
+    use crate::lle::wire::*;
+    use crate::lle::drc::ViolationKind;
+
+    #[derive(Clocked)]
+    struct Pipe {
+        pc: Reg<u32>,
+        fetch: Wire<u32>,
+        decode: Wire<u32>,
+        out: Reg<u32>,
+    }
+
+    #[test]
+    fn eval_any_order() {
+        let mut e: Evaluator<Pipe> = Evaluator::new();
+        // Written from the back of the pipeline to the front
+        e.add("writeback", &["decode"], &[], |p| {
+            p.out.drive(p.decode.sample());
+        });
+        e.add("decode", &["fetch"], &["decode"], |p| {
+            p.decode.drive(p.fetch.sample() * 2);
+        });
+        e.add("fetch", &[], &["fetch"], |p| {
+            p.fetch.drive(p.pc.sample());
+            p.pc.drive(p.pc.sample() + 1);
+        });
+        assert_eq!(e.order().unwrap(), vec!["fetch", "decode", "writeback"]);
+
+        let mut p = Pipe {
+            pc: Reg::new(0), fetch: Wire::new(), decode: Wire::new(),
+            out: Reg::new(0),
+        };
+        for _ in 0..4 {
+            e.step(&mut p);
+        }
+        assert_eq!(p.out.sample(), 6);
+        assert_eq!(p.pc.sample(), 4);
+    }
+
+    #[test]
+    fn eval_stable_order() {
+        let mut e: Evaluator<()> = Evaluator::new();
+        e.add("a", &[], &[], |_| {});
+        e.add("c", &["x"], &[], |_| {});
+        e.add("b", &[], &[], |_| {});
+        e.add("d", &[], &["x"], |_| {});
+        assert_eq!(e.order().unwrap(), vec!["a", "b", "d", "c"]);
+    }
+
+    #[test]
+    fn eval_loop() {
+        let mut e: Evaluator<()> = Evaluator::new();
+        e.add("src", &[], &["a"], |_| {});
+        e.add("x", &["a", "z"], &["x"], |_| {});
+        e.add("y", &["x"], &["y"], |_| {});
+        e.add("z", &["y"], &["z"], |_| {});
+        e.add("sink", &["z"], &[], |_| {});
+        let err = e.order().unwrap_err();
+        assert_eq!(err, EvalError::CombLoop(vec!["x", "y", "z", "x"]));
+        assert_eq!(err.to_string(), "combinational loop: x -> y -> z -> x");
+    }
+
+    #[test]
+    fn eval_multiple_writers() {
+        let mut e: Evaluator<()> = Evaluator::new();
+        e.add("a", &[], &["x"], |_| {});
+        e.add("b", &[], &["x"], |_| {});
+        assert_eq!(e.order().unwrap_err(), EvalError::MultipleWriters {
+            signal: "x", blocks: ["a", "b"],
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "combinational loop: a -> a")]
+    fn eval_self_loop() {
+        let mut e: Evaluator<()> = Evaluator::new();
+        e.add("a", &["x"], &["x"], |_| {});
+        e.eval(&mut ());
+    }
+
+    #[test]
+    fn eval_undeclared() {
+        drc::enable();
+        let mut e: Evaluator<Pipe> = Evaluator::new();
+        e.add("fetch", &[], &["fetch"], |p| {
+            p.fetch.drive(p.pc.sample());
+            // Not declared as a write
+            p.decode.drive(0);
+        });
+        // Not declared as a read
+        e.add("writeback", &[], &[], |p| {
+            p.out.drive(p.fetch.sample());
+        });
+
+        let mut p = Pipe {
+            pc: Reg::new(0), fetch: Wire::new().named("fetch"),
+            decode: Wire::new().named("decode"), out: Reg::new(0),
+        };
+        e.step(&mut p);
+        let v = drc::take_violations();
+        assert_eq!(v.len(), 2);
+        assert_eq!(v[0].kind, ViolationKind::UndeclaredDrive { block: "fetch" });
+        assert_eq!(v[0].signal.name, Some("decode"));
+        assert_eq!(v[1].kind, 
+            ViolationKind::UndeclaredRead { block: "writeback" });
+        assert_eq!(v[1].signal.name, Some("fetch"));
+
+        // Outside of the evaluator, nothing is declared
+        p.decode.drive(0);
+        p.fetch.drive(p.decode.sample());
+        assert!(drc::take_violations().is_empty());
+        drc::disable();
+    }
+}
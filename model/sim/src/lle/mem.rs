@@ -4,31 +4,76 @@ use std::rc::*;
 
 use crate::lle::*;
 use crate::lle::register::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
 
 /// An array of registers (asynchronous read, synchronous write)
 pub struct Mem<D: Copy + Default, const SZ: usize> {
     data: [ Reg<D>; SZ ],
+    /// Number of read/write ports (for cost estimates)
+    ports: Option<(usize, usize)>,
 }
 impl <D: Copy + Default, const SZ: usize> Mem<D, SZ> {
+    #[track_caller]
     pub fn new_init_val(init: D) -> Self { 
-        Self { data: [ Reg::new(init); SZ ] }
+        let reg = Reg::new(init);
+        Self { data: std::array::from_fn(|_| reg.clone()), ports: None }
     }
+    #[track_caller]
     pub fn new_init_array(init: &[D; SZ]) -> Self { 
-        let data: [Reg<D>; SZ] = init.map(Reg::new);
-        Self { data }
+        let reg = Reg::new(D::default());
+        let mut data: [Reg<D>; SZ] = std::array::from_fn(|_| reg.clone());
+        for (r, x) in data.iter_mut().zip(init.iter()) {
+            *r = Reg::new(*x);
+        }
+        Self { data, ports: None }
+    }
+    /// Declare the number of read and write ports (for cost estimates).
+    ///
+    /// NOTE: This isn't checked, since any entry can be read or written.
+    pub fn with_ports(mut self, read: usize, write: usize) -> Self {
+        self.ports = Some((read, write));
+        self
     }
 
+    #[track_caller]
     pub fn drive(&mut self, idx: usize, val: D) { 
         self.data[idx].drive(val);
     }
     pub fn sample(&self, idx: usize) -> D {
         self.data[idx].sample()
     }
-    pub fn update(&mut self) {
+}
+impl <D: Copy + Default, const SZ: usize> Clocked for Mem<D, SZ> {
+    fn update(&mut self) {
         for r in self.data.iter_mut() {
             r.update();
         }
     }
+    fn is_dirty(&self) -> bool {
+        self.data.is_dirty()
+    }
+}
+impl <D: Copy + Default, const SZ: usize> Resettable for Mem<D, SZ> {
+    fn reset(&mut self) {
+        self.data.reset();
+    }
+}
+impl <D: Copy + Default + HwBits, const SZ: usize> Cost for Mem<D, SZ> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        let mut res = StorageCost::new("Mem", SZ, D::BITS);
+        if let Some((read, write)) = self.ports {
+            res = res.with_ports(read, write);
+        }
+        r.add(path, res);
+    }
+}
+impl <D: Copy + Default + TraceValue, const SZ: usize> Trace for Mem<D, SZ> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        for (idx, r) in self.data.iter().enumerate() {
+            r.trace(&format!("{}[{}]", path, idx), t);
+        }
+    }
 }
 impl <D: Copy + Default, const SZ: usize> 
 std::ops::Index<usize> for Mem<D, SZ> 
@@ -41,6 +86,7 @@ std::ops::Index<usize> for Mem<D, SZ>
 impl <D: Copy + Default, const SZ: usize> 
 std::ops::IndexMut<usize> for Mem<D, SZ> 
 {
+    #[track_caller]
     fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
         self.data[idx].next_as_mut()
     }
@@ -54,6 +100,9 @@ pub struct RegisterFile<T: Copy + Default + Debug, const SIZE: usize>
 {
     data:  [T; SIZE],
     input: [Option<T>; SIZE],
+    init:  [T; SIZE],
+    /// Number of read/write ports (for cost estimates)
+    ports: Option<(usize, usize)>,
 }
 impl <T, const SIZE: usize> RegisterFile<T, SIZE>
     where T: Copy + Default + Debug
@@ -62,8 +111,15 @@ impl <T, const SIZE: usize> RegisterFile<T, SIZE>
         Self { 
             data:  init,
             input: [None; SIZE],
+            init,
+            ports: None,
         }
     }
+    /// Declare the number of read and write ports (for cost estimates).
+    pub fn with_ports(mut self, read: usize, write: usize) -> Self {
+        self.ports = Some((read, write));
+        self
+    }
 
     pub fn read(&self, idx: usize) -> T {
         self.data[idx]
@@ -82,6 +138,29 @@ impl <T, const SIZE: usize> Clocked for RegisterFile<T, SIZE>
             }
         }
     }
+    fn is_dirty(&self) -> bool {
+        self.input.iter().any(|x| x.is_some())
+    }
+}
+impl <T, const SIZE: usize> Resettable for RegisterFile<T, SIZE> 
+    where T: Copy + Default + Debug
+{
+    fn reset(&mut self) {
+        self.data = self.init;
+        self.input = [None; SIZE];
+    }
+}
+
+impl <T, const SIZE: usize> Cost for RegisterFile<T, SIZE>
+    where T: Copy + Default + Debug + HwBits
+{
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        let mut res = StorageCost::new("RegisterFile", SIZE, T::BITS);
+        if let Some((read, write)) = self.ports {
+            res = res.with_ports(read, write);
+        }
+        r.add(path, res);
+    }
 }
 
 #[cfg(test)]
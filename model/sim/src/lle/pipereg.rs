@@ -0,0 +1,278 @@
+//! Pipeline registers.
+//!
+//! A [PipeReg] is a register with a 'valid' bit, which separates two stages
+//! in a pipeline. On each cycle:
+//!
+//! - The upstream stage drives the next value with [PipeReg::drive]
+//! - The downstream stage may hold the current value with [PipeReg::stall]
+//! - Either stage may invalidate the register with [PipeReg::flush]
+//!
+//! At the clock edge, these take effect with the following priority:
+//!
+//! 1. When flushed, the register becomes invalid (a bubble)
+//! 2. When stalled, the register holds its value, and anything driven by
+//!    the upstream stage is discarded (the upstream stage is expected to
+//!    check [PipeReg::is_stalled] and hold its own input)
+//! 3. Otherwise, the register takes the value driven on this cycle, or
+//!    becomes invalid if nothing was driven
+//!
+//! Unlike a [Reg](crate::lle::register::Reg), an undriven [PipeReg] does
+//! *not* hold its value: a stage which produces nothing inserts a bubble.
+
+use std::panic::Location;
+
+use crate::lle::*;
+use crate::lle::drc::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
+
+/// Control and occupancy statistics for a [PipeReg].
+///
+/// NOTE: These aren't part of the simulated state, and are *not* cleared
+/// when the register is reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipeStats {
+    /// Number of clock edges observed
+    pub cycles: usize,
+    /// Cycles where the register held a valid value
+    pub valid: usize,
+    /// Cycles where the register was invalid
+    pub bubbles: usize,
+    /// Cycles where a valid value was held by a stall
+    pub stalls: usize,
+    /// Cycles where the register was flushed
+    pub flushes: usize,
+}
+impl PipeStats {
+    /// Returns the fraction of cycles with a valid value.
+    pub fn occupancy(&self) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        self.valid as f64 / self.cycles as f64
+    }
+}
+impl std::fmt::Display for PipeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "cycles={} valid={} bubbles={} stalls={} flushes={} \
+            occupancy={:.2}", self.cycles, self.valid, self.bubbles, 
+            self.stalls, self.flushes, self.occupancy())
+    }
+}
+
+/// A pipeline register with valid, stall and flush inputs.
+pub struct PipeReg<T: Copy> {
+    /// The current value (or [None] for a bubble)
+    data: Option<T>,
+    /// The value driven on this cycle
+    next: Option<T>,
+    /// Stall driven on this cycle
+    stall: bool,
+    /// Flush driven on this cycle
+    flush: bool,
+    /// The reset value
+    init: Option<T>,
+    /// Name and creation site (for design rule checking).
+    info: SignalInfo,
+    /// Drivers on this cycle (for design rule checking).
+    sites: DriveSites,
+
+    stats: PipeStats,
+}
+impl <T: Copy> PipeReg<T> {
+    /// Create a pipeline register which is initially invalid.
+    #[track_caller]
+    pub fn new() -> Self {
+        Self::with_init(None)
+    }
+    /// Create a pipeline register which initially holds `init`.
+    #[track_caller]
+    pub fn new_valid(init: T) -> Self {
+        Self::with_init(Some(init))
+    }
+    #[track_caller]
+    fn with_init(init: Option<T>) -> Self {
+        Self {
+            data: init,
+            next: None,
+            stall: false,
+            flush: false,
+            init,
+            info: SignalInfo::new(),
+            sites: DriveSites::default(),
+            stats: PipeStats::default(),
+        }
+    }
+    /// Name this register (for design rule checking).
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.info.name = Some(name);
+        self
+    }
+    pub fn info(&self) -> &SignalInfo { &self.info }
+    pub fn stats(&self) -> &PipeStats { &self.stats }
+
+    /// Sample the current value (or [None] for a bubble).
+    pub fn sample(&self) -> Option<T> { self.data }
+    /// Sample the current value (as a reference).
+    pub fn sample_ref(&self) -> Option<&T> { self.data.as_ref() }
+    pub fn is_valid(&self) -> bool { self.data.is_some() }
+
+    /// Drive a valid value for the next cycle.
+    #[track_caller]
+    pub fn drive(&mut self, val: T) {
+        self.sites.record_drive(Location::caller());
+        self.next = Some(val);
+    }
+    /// Hold the current value for another cycle.
+    pub fn stall(&mut self) {
+        self.stall = true;
+    }
+    /// Invalidate the register at the next clock edge.
+    pub fn flush(&mut self) {
+        self.flush = true;
+    }
+    /// Returns true if a stall was driven on this cycle (and the register
+    /// can't accept a new value).
+    pub fn is_stalled(&self) -> bool {
+        self.stall && !self.flush
+    }
+}
+impl <T: Copy> Default for PipeReg<T> {
+    #[track_caller]
+    fn default() -> Self { Self::new() }
+}
+impl <T: Copy> Clocked for PipeReg<T> {
+    fn update(&mut self) {
+        self.sites.check(&self.info);
+        self.stats.cycles += 1;
+        let next = self.next.take();
+        if std::mem::take(&mut self.flush) {
+            self.stats.flushes += 1;
+            self.data = None;
+        } else if self.stall {
+            if self.data.is_some() {
+                self.stats.stalls += 1;
+            }
+        } else {
+            self.data = next;
+        }
+        self.stall = false;
+        if self.data.is_some() {
+            self.stats.valid += 1;
+        } else {
+            self.stats.bubbles += 1;
+        }
+    }
+}
+impl <T: Copy> Resettable for PipeReg<T> {
+    fn reset(&mut self) {
+        self.data = self.init;
+        self.next = None;
+        self.stall = false;
+        self.flush = false;
+        self.sites = DriveSites::default();
+    }
+}
+/// NOTE: The valid bit is included in the width.
+impl <T: Copy + HwBits> Cost for PipeReg<T> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("PipeReg", 1, T::BITS + 1).with_ports(1, 1));
+    }
+}
+impl <T: Copy + TraceValue> Trace for PipeReg<T> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(&format!("{}.valid", path), 1, Some(self.data.is_some() as u64));
+        t.signal(&format!("{}.bits", path), T::WIDTH,
+            self.data.and_then(|v| v.trace_bits()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pipereg_bubble() {
+        let mut r = PipeReg::new();
+        r.drive(1u32);
+        r.update();
+        assert_eq!(r.sample(), Some(1));
+        // Nothing driven
+        r.update();
+        assert_eq!(r.sample(), None);
+        assert_eq!(*r.stats(), PipeStats {
+            cycles: 2, valid: 1, bubbles: 1, stalls: 0, flushes: 0,
+        });
+    }
+
+    #[test]
+    fn pipereg_priority() {
+        let mut r = PipeReg::new_valid(1u32);
+
+        // Stall beats new input
+        r.stall();
+        assert!(r.is_stalled());
+        r.drive(2);
+        r.update();
+        assert_eq!(r.sample(), Some(1));
+
+        // Flush beats stall and new input
+        r.stall();
+        r.drive(3);
+        r.flush();
+        assert!(!r.is_stalled());
+        r.update();
+        assert_eq!(r.sample(), None);
+
+        // Stalling a bubble isn't counted
+        r.stall();
+        r.update();
+        assert_eq!(r.stats().stalls, 1);
+        assert_eq!(r.stats().flushes, 1);
+        assert_eq!(r.stats().bubbles, 2);
+
+        r.reset();
+        assert_eq!(r.sample(), Some(1));
+    }
+
+    /// Two-stage pipeline where the second stage takes two cycles for
+    /// every value.
+    #[test]
+    fn pipereg_stall_chain() {
+        let mut s1 = PipeReg::new();
+        let mut s2 = PipeReg::new();
+        let mut next = 0u32;
+        let mut busy = false;
+        let mut out = Vec::new();
+        for _ in 0..8 {
+            // Stage 2
+            if let Some(x) = s2.sample() {
+                if busy {
+                    out.push(x);
+                    busy = false;
+                } else {
+                    s2.stall();
+                    busy = true;
+                }
+            }
+            // Stage 1 can't move forward while stage 2 is stalled
+            if s2.is_stalled() {
+                s1.stall();
+            } else if let Some(x) = s1.sample() {
+                s2.drive(x);
+            }
+            // Stage 0
+            if !s1.is_stalled() {
+                s1.drive(next);
+                next += 1;
+            }
+            s1.update();
+            s2.update();
+        }
+        assert_eq!(out, vec![0, 1, 2]);
+        assert_eq!(s2.stats().stalls, 3);
+        assert!((s2.stats().occupancy() - 7.0 / 8.0).abs() < 1e-9);
+        assert_eq!(s2.stats().to_string(), 
+            "cycles=8 valid=7 bubbles=1 stalls=3 flushes=0 occupancy=0.88");
+    }
+}
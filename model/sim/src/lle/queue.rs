@@ -1,10 +1,15 @@
 
 use std::collections::*;
 
+use crate::lle::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
+use crate::lle::ring::*;
+
 /// Simple queue implementation. 
 ///
-/// FIXME: This is a high-level version; convert to [Clocked]
 /// FIXME: There's no bound on the size of this queue
+/// (see [Decoupled](crate::lle::decoupled::Decoupled) for a bounded channel).
 pub struct Queue<T> {
     pub next: Option<T>,
     pub deq_ok: bool,
@@ -35,9 +40,9 @@ impl <T> Queue<T> {
     pub fn front(&self) -> Option<&T> {
         self.data.front()
     }
-
-    /// Update the state of the queue. 
-    pub fn update(&mut self) {
+}
+impl <T> Clocked for Queue<T> {
+    fn update(&mut self) {
         // Add a new element being driven this cycle
         if let Some(next) = self.next.take() {
             self.data.push_back(next);
@@ -48,13 +53,36 @@ impl <T> Queue<T> {
             self.deq_ok = false;
         }
     }
+    fn is_dirty(&self) -> bool {
+        self.next.is_some() || self.deq_ok
+    }
+}
+impl <T> Resettable for Queue<T> {
+    fn reset(&mut self) {
+        self.next = None;
+        self.deq_ok = false;
+        self.data.clear();
+    }
+}
+
+/// NOTE: The number of entries isn't bounded.
+impl <T: HwBits> Cost for Queue<T> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("Queue", self.data.len(), T::BITS)
+            .with_ports(1, 1).unbounded());
+    }
+}
+/// NOTE: Only the number of entries is traced.
+impl <T> Trace for Queue<T> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(&format!("{}.len", path), 32, Some(self.data.len() as u64));
+    }
 }
 
-/// FIXME: This is a high-level version; convert to [Clocked]
 pub struct CircularQueue<T: Copy, const SZ: usize> {
     pub next: Option<T>,
-    pub enq_ptr: usize,
-    pub deq_ptr: usize,
+    pub enq_ptr: RingPtr<SZ>,
+    pub deq_ptr: RingPtr<SZ>,
     pub deq_ok: bool,
     pub data: [Option<T>; SZ],
 
@@ -66,8 +94,8 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
     pub fn new() -> Self {
         Self {
             next: None,
-            enq_ptr: 0,
-            deq_ptr: 0,
+            enq_ptr: RingPtr::new(),
+            deq_ptr: RingPtr::new(),
             deq_ok: false,
             data: [None; SZ],
 
@@ -96,10 +124,13 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
     }
 
     pub fn is_full(&self) -> bool {
-        self.enq_ptr == self.deq_ptr && self.data[self.enq_ptr].is_some()
+        RingPtr::is_full(self.enq_ptr, self.deq_ptr)
     }
     pub fn is_empty(&self) -> bool {
-        self.enq_ptr == self.deq_ptr && self.data[self.deq_ptr].is_none()
+        RingPtr::is_empty(self.enq_ptr, self.deq_ptr)
+    }
+    pub fn num_used(&self) -> usize {
+        RingPtr::num_used(self.enq_ptr, self.deq_ptr)
     }
 
     // Drive an entry to be enqueued on the following cycle. 
@@ -111,7 +142,7 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
             None
         } else {
             self.next = Some(value);
-            Some(self.enq_ptr)
+            Some(self.enq_ptr.idx())
         }
     }
 
@@ -120,26 +151,11 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
     }
 
     pub fn front(&self) -> Option<T> {
-        self.data[self.deq_ptr]
-    }
-
-    fn inc_deq_ptr(&mut self) {
-        if self.deq_ptr == SZ - 1 {
-            self.deq_ptr = 0;
-        } else {
-            self.deq_ptr += 1;
-        }
+        self.data[self.deq_ptr.idx()]
     }
-    fn inc_enq_ptr(&mut self) {
-        if self.deq_ptr == SZ - 1 {
-            self.deq_ptr = 0;
-        } else {
-            self.deq_ptr += 1;
-        }
-    }
-
-
-    pub fn update(&mut self) {
+}
+impl <T: Copy, const SZ: usize> Clocked for CircularQueue<T, SZ> {
+    fn update(&mut self) {
         // Each entry in 'wp_pending' corresponds to a write port being
         // driven on the current cycle. 
         while let Some((idx, value)) = self.wp_pending.pop() {
@@ -147,21 +163,87 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
             self.data[idx] = Some(value);
         }
 
-        // FIXME: actually handle overflow conditions
-
+        // NOTE: [CircularQueue::enq] refuses to drive an entry when the 
+        // queue is full, even if the oldest entry is being dequeued.
         if self.deq_ok {
-            self.data[self.deq_ptr] = None;
+            assert!(!self.is_empty(), "CircularQueue underflow");
+            self.data[self.deq_ptr.idx()] = None;
             self.deq_ok = false;
-            self.inc_deq_ptr();
+            self.deq_ptr.inc();
         }
 
         if let Some(next) = self.next.take() {
-            self.data[self.enq_ptr] = Some(next);
-            self.inc_enq_ptr();
+            assert!(self.data[self.enq_ptr.idx()].is_none(), 
+                "CircularQueue overflow");
+            self.data[self.enq_ptr.idx()] = Some(next);
+            self.enq_ptr.inc();
         }
 
 
     }
+    fn is_dirty(&self) -> bool {
+        self.next.is_some() || self.deq_ok || !self.wp_pending.is_empty()
+    }
+}
+impl <T: Copy, const SZ: usize> Resettable for CircularQueue<T, SZ> {
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+/// NOTE: Entries can also be read and written at any index (see
+/// [CircularQueue::sample_idx]), so the number of ports isn't known.
+impl <T: Copy + HwBits, const SZ: usize> Cost for CircularQueue<T, SZ> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(&format!("{}.data", path),
+            StorageCost::new("CircularQueue", SZ, Option::<T>::BITS));
+        r.add(&format!("{}.ptrs", path),
+            StorageCost::new("Reg", 2, RingPtr::<SZ>::BITS).with_ports(1, 1));
+    }
+}
+impl <T: Copy, const SZ: usize> Trace for CircularQueue<T, SZ> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        let len = self.data.iter().filter(|e| e.is_some()).count();
+        t.signal(&format!("{}.len", path), 32, Some(len as u64));
+        t.signal(&format!("{}.enq_ptr", path), 32, Some(self.enq_ptr.idx() as u64));
+        t.signal(&format!("{}.deq_ptr", path), 32, Some(self.deq_ptr.idx() as u64));
+    }
 }
 
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn circular_queue_fifo() {
+        let mut q = CircularQueue::<u32, 3>::new();
+        let mut out = Vec::new();
+        for x in 0..8 {
+            // Keep the queue partially full while wrapping around
+            if q.num_used() == 2 {
+                out.push(q.front().unwrap());
+                q.set_deq();
+            }
+            assert_eq!(q.enq(x), Some(x as usize % 3));
+            q.update();
+        }
+        assert_eq!(out, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(q.enq_ptr.idx(), 2);
+        assert_eq!(q.deq_ptr.idx(), 0);
+    }
+
+    #[test]
+    fn circular_queue_full() {
+        let mut q = CircularQueue::<u32, 2>::new();
+        q.enq(0);
+        q.update();
+        q.enq(1);
+        q.update();
+        assert!(q.is_full());
+        assert_eq!(q.enq(2), None);
+        q.set_deq();
+        q.update();
+        assert_eq!(q.front(), Some(1));
+        assert_eq!(q.num_used(), 1);
+    }
+}
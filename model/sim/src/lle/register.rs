@@ -3,7 +3,12 @@ use std::fmt::Debug;
 use std::cell::*;
 use std::rc::*;
 
+use std::panic::Location;
+
 use crate::lle::*;
+use crate::lle::drc::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
 
 /// A register. 
 ///
@@ -16,44 +21,94 @@ use crate::lle::*;
 /// by wrapping the interior type in [Option]. Might be worth turning that 
 /// into a separate type (ie. a `ValidReg`).
 ///
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Reg<T: Copy + Default> {
     /// The [instantaneous] value of this register.
     data: T,
     /// The next value of this register (effective at the next clock edge).
     next: Option<T>,
+    /// The reset value of this register.
+    init: T,
+    /// Name and creation site (for design rule checking).
+    info: SignalInfo,
+    /// Drivers on this cycle (for design rule checking).
+    sites: DriveSites,
 }
 impl <T: Copy + Default> Reg<T> {
     /// Create a new register. 
+    #[track_caller]
     pub fn new(init: T) -> Self { 
-        Self { next: None, data: init }
+        Self { next: None, data: init, init, info: SignalInfo::new(), 
+            sites: DriveSites::default() 
+        }
     }
+    /// Name this register (for design rule checking).
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.info.name = Some(name);
+        self
+    }
+    pub fn info(&self) -> &SignalInfo { &self.info }
+
+    /// NOTE: Only the first call on each cycle is treated as a driver.
+    #[track_caller]
     pub fn next_as_mut(&mut self) -> &mut T {
+        if self.next.is_none() {
+            self.sites.record_drive(Location::caller());
+        }
         self.next.get_or_insert(T::default())
     }
     /// Drive input to this register.
-    pub fn drive(&mut self, val: T) { self.next = Some(val) }
+    #[track_caller]
+    pub fn drive(&mut self, val: T) { 
+        self.sites.record_drive(Location::caller());
+        self.next = Some(val) 
+    }
     /// Sample the current value of this register.
     pub fn sample(&self) -> T { self.data }
     /// Sample the current value of this register (as a reference).
     pub fn sample_ref(&self) -> &T { &self.data }
 }
 impl <T: Copy + Default> Default for Reg<T> {
+    #[track_caller]
     fn default() -> Self {
         Self { 
             next: None,
             data: T::default(),
+            init: T::default(),
+            info: SignalInfo::new(),
+            sites: DriveSites::default(),
         }
     }
 }
 impl <T: Copy + Default> Clocked for Reg<T> {
     fn update(&mut self) {
+        self.sites.check(&self.info);
         if let Some(next) = self.next.take() {
             self.data = next;
         }
     }
+    fn is_dirty(&self) -> bool {
+        self.next.is_some()
+    }
+}
+impl <T: Copy + Default> Resettable for Reg<T> {
+    fn reset(&mut self) {
+        self.data = self.init;
+        self.next = None;
+        self.sites = DriveSites::default();
+    }
 }
 
+impl <T: Copy + Default + HwBits> Cost for Reg<T> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("Reg", 1, T::BITS).with_ports(1, 1));
+    }
+}
+impl <T: Copy + Default + TraceValue> Trace for Reg<T> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(path, T::WIDTH, self.data.trace_bits());
+    }
+}
 
 
 
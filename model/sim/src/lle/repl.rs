@@ -0,0 +1,502 @@
+//! Replacement policies for set-associative structures.
+//!
+//! A [ReplacementPolicy] holds the replacement state for every set in a
+//! structure with `sets` sets of `ways` ways each (a fully-associative
+//! structure is a single set). The structure reports each access with
+//! [ReplacementPolicy::touch] or [ReplacementPolicy::insert], and asks
+//! for a way to replace with [ReplacementPolicy::victim].
+//!
+//! Every policy prefers ways which were never filled (or invalidated with
+//! [ReplacementPolicy::invalidate]) before replacing a valid way.
+//!
+//! Available policies:
+//!
+//! - [LruPolicy]: true LRU
+//! - [TreePlruPolicy]: tree pseudo-LRU (one bit per node)
+//! - [BitPlruPolicy]: bit pseudo-LRU (one MRU bit per way)
+//! - [RripPolicy]: static and bimodal re-reference interval prediction
+//!   (SRRIP/BRRIP)
+//! - [RandomPolicy]: seeded pseudo-random
+
+/// Selects a way to be replaced in each set of a set-associative structure.
+pub trait ReplacementPolicy {
+    fn num_sets(&self) -> usize;
+    fn num_ways(&self) -> usize;
+
+    /// Way `way` in set `set` was accessed (ie. on a hit).
+    fn touch(&mut self, set: usize, way: usize);
+
+    /// Way `way` in set `set` was filled with a new entry.
+    ///
+    /// By default, this is the same as [ReplacementPolicy::touch].
+    fn insert(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    /// Way `way` in set `set` is no longer valid, and should be replaced
+    /// before any valid way.
+    fn invalidate(&mut self, set: usize, way: usize);
+
+    /// Select a way to be replaced in set `set`.
+    ///
+    /// NOTE: This may change the replacement state (ie. for [RripPolicy]
+    /// or [RandomPolicy]), and should only be used when an entry is
+    /// actually being replaced.
+    fn victim(&mut self, set: usize) -> usize;
+}
+
+/// Valid bits for each way (shared by all policies).
+#[derive(Clone)]
+struct ValidBits {
+    ways: usize,
+    valid: Vec<bool>,
+}
+impl ValidBits {
+    fn new(sets: usize, ways: usize) -> Self {
+        assert!(sets != 0 && ways != 0);
+        Self { ways, valid: vec![false; sets * ways] }
+    }
+    fn sets(&self) -> usize { self.valid.len() / self.ways }
+    #[track_caller]
+    fn idx(&self, set: usize, way: usize) -> usize {
+        assert!(set < self.sets(), "set {} out of bounds", set);
+        assert!(way < self.ways, "way {} out of bounds", way);
+        set * self.ways + way
+    }
+    #[track_caller]
+    fn set(&mut self, set: usize, way: usize, valid: bool) {
+        let idx = self.idx(set, way);
+        self.valid[idx] = valid;
+    }
+    /// Returns the lowest invalid way in a set (if one exists).
+    #[track_caller]
+    fn first_invalid(&self, set: usize) -> Option<usize> {
+        let base = self.idx(set, 0);
+        self.valid[base..base + self.ways].iter().position(|v| !v)
+    }
+}
+
+/// A xorshift64 generator, for policies with random decisions.
+#[derive(Clone)]
+struct Rng(u64);
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Self(seed.max(1))
+    }
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+/// True least-recently used replacement, with a timestamp for each way.
+#[derive(Clone)]
+pub struct LruPolicy {
+    valid: ValidBits,
+    /// Last-use timestamp for each way
+    stamp: Vec<u64>,
+    clock: u64,
+}
+impl LruPolicy {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self {
+            valid: ValidBits::new(sets, ways),
+            stamp: vec![0; sets * ways],
+            clock: 0,
+        }
+    }
+}
+impl ReplacementPolicy for LruPolicy {
+    fn num_sets(&self) -> usize { self.valid.sets() }
+    fn num_ways(&self) -> usize { self.valid.ways }
+    fn touch(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, true);
+        self.clock += 1;
+        self.stamp[self.valid.idx(set, way)] = self.clock;
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, false);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        if let Some(way) = self.valid.first_invalid(set) {
+            return way;
+        }
+        let base = self.valid.idx(set, 0);
+        (0..self.valid.ways).min_by_key(|w| self.stamp[base + w]).unwrap()
+    }
+}
+
+/// Tree pseudo-LRU replacement.
+///
+/// Each set has a binary tree with `ways - 1` nodes, where each node points
+/// toward the less-recently used half of its subtree. An access flips the
+/// nodes on its path to point away from it, and the victim is found by
+/// following the nodes from the root.
+#[derive(Clone)]
+pub struct TreePlruPolicy {
+    valid: ValidBits,
+    /// The nodes for each set (where node `n` has children `2n+1` and
+    /// `2n+2`, and `false` points to the left child)
+    nodes: Vec<bool>,
+}
+impl TreePlruPolicy {
+    /// NOTE: The number of ways must be a power of two.
+    pub fn new(sets: usize, ways: usize) -> Self {
+        assert!(ways.is_power_of_two(), "tree-PLRU needs a power-of-two number of ways");
+        Self {
+            valid: ValidBits::new(sets, ways),
+            nodes: vec![false; sets * (ways - 1)],
+        }
+    }
+    fn tree_mut(&mut self, set: usize) -> &mut [bool] {
+        let n = self.valid.ways - 1;
+        &mut self.nodes[set * n..(set + 1) * n]
+    }
+    /// Point every node on the path to `way` toward (or away from) it.
+    fn point(&mut self, set: usize, way: usize, toward: bool) {
+        let levels = self.valid.ways.trailing_zeros();
+        let tree = self.tree_mut(set);
+        let mut node = 0;
+        for lvl in (0..levels).rev() {
+            let right = (way >> lvl) & 1 != 0;
+            tree[node] = right == toward;
+            node = 2 * node + 1 + right as usize;
+        }
+    }
+}
+impl ReplacementPolicy for TreePlruPolicy {
+    fn num_sets(&self) -> usize { self.valid.sets() }
+    fn num_ways(&self) -> usize { self.valid.ways }
+    fn touch(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, true);
+        self.point(set, way, false);
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, false);
+        self.point(set, way, true);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        if let Some(way) = self.valid.first_invalid(set) {
+            return way;
+        }
+        let ways = self.valid.ways;
+        let tree = self.tree_mut(set);
+        let mut node = 0;
+        while node < ways - 1 {
+            node = 2 * node + 1 + tree[node] as usize;
+        }
+        node - (ways - 1)
+    }
+}
+
+/// Bit pseudo-LRU (also called "MRU-based" pseudo-LRU) replacement.
+///
+/// Each way has an MRU bit which is set on access. When every bit in a
+/// set would be set, the others are cleared. The victim is the lowest way
+/// with a clear bit.
+#[derive(Clone)]
+pub struct BitPlruPolicy {
+    valid: ValidBits,
+    mru: Vec<bool>,
+}
+impl BitPlruPolicy {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self {
+            valid: ValidBits::new(sets, ways),
+            mru: vec![false; sets * ways],
+        }
+    }
+}
+impl ReplacementPolicy for BitPlruPolicy {
+    fn num_sets(&self) -> usize { self.valid.sets() }
+    fn num_ways(&self) -> usize { self.valid.ways }
+    fn touch(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, true);
+        let base = self.valid.idx(set, 0);
+        let bits = &mut self.mru[base..base + self.valid.ways];
+        bits[way] = true;
+        if bits.iter().all(|b| *b) {
+            bits.fill(false);
+            bits[way] = true;
+        }
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, false);
+        let idx = self.valid.idx(set, way);
+        self.mru[idx] = false;
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        if let Some(way) = self.valid.first_invalid(set) {
+            return way;
+        }
+        let base = self.valid.idx(set, 0);
+        // There's always at least one clear bit (unless there's one way)
+        self.mru[base..base + self.valid.ways].iter().position(|b| !b)
+            .unwrap_or(0)
+    }
+}
+
+/// Re-reference interval prediction (see Jaleel et al., "High Performance
+/// Cache Replacement Using Re-Reference Interval Prediction", ISCA 2010).
+///
+/// Each way has an `M`-bit re-reference prediction value (RRPV), which is
+/// cleared on a hit. The victim is the lowest way with the largest RRPV
+/// (`2^M - 1`): when there isn't one, every RRPV in the set is incremented
+/// until there is.
+///
+/// New entries are inserted with a "long" RRPV (`2^M - 2`) by SRRIP. BRRIP
+/// inserts with a "distant" RRPV (`2^M - 1`) instead, except for a random
+/// 1-in-`throttle` fills which are inserted as "long".
+#[derive(Clone)]
+pub struct RripPolicy {
+    valid: ValidBits,
+    rrpv: Vec<u8>,
+    /// Largest RRPV (`2^M - 1`)
+    max: u8,
+    /// For BRRIP, one in this many fills are inserted with a "long" RRPV
+    throttle: Option<u32>,
+    rng: Rng,
+}
+impl RripPolicy {
+    /// Static RRIP with 2-bit RRPVs.
+    pub fn srrip(sets: usize, ways: usize) -> Self {
+        Self {
+            valid: ValidBits::new(sets, ways),
+            rrpv: vec![3; sets * ways],
+            max: 3,
+            throttle: None,
+            rng: Rng::new(1),
+        }
+    }
+    /// Bimodal RRIP with 2-bit RRPVs, where 1 in 32 fills are inserted
+    /// with a "long" RRPV.
+    pub fn brrip(sets: usize, ways: usize) -> Self {
+        Self { throttle: Some(32), ..Self::srrip(sets, ways) }
+    }
+    /// Use `bits`-bit RRPVs.
+    pub fn with_bits(mut self, bits: u32) -> Self {
+        assert!((1..=8).contains(&bits), "RRPVs must have 1 to 8 bits");
+        self.max = ((1u32 << bits) - 1) as u8;
+        self.rrpv.fill(self.max);
+        self
+    }
+    /// For BRRIP, insert 1 in `n` fills with a "long" RRPV.
+    pub fn with_throttle(mut self, n: u32) -> Self {
+        assert!(self.throttle.is_some() && n != 0);
+        self.throttle = Some(n);
+        self
+    }
+    /// Seed the generator used by BRRIP.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// Returns the current RRPV for a way.
+    pub fn rrpv(&self, set: usize, way: usize) -> u8 {
+        self.rrpv[self.valid.idx(set, way)]
+    }
+}
+impl ReplacementPolicy for RripPolicy {
+    fn num_sets(&self) -> usize { self.valid.sets() }
+    fn num_ways(&self) -> usize { self.valid.ways }
+    fn touch(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, true);
+        let idx = self.valid.idx(set, way);
+        self.rrpv[idx] = 0;
+    }
+    fn insert(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, true);
+        let long = self.max - 1;
+        let rrpv = match self.throttle {
+            Some(n) if !self.rng.next().is_multiple_of(n as u64) => self.max,
+            _ => long,
+        };
+        let idx = self.valid.idx(set, way);
+        self.rrpv[idx] = rrpv;
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, false);
+        let idx = self.valid.idx(set, way);
+        self.rrpv[idx] = self.max;
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        if let Some(way) = self.valid.first_invalid(set) {
+            return way;
+        }
+        let base = self.valid.idx(set, 0);
+        let rrpv = &mut self.rrpv[base..base + self.valid.ways];
+        // Aging all ways at once is the same as incrementing until one of
+        // them reaches the largest RRPV.
+        let oldest = *rrpv.iter().max().unwrap();
+        let age = self.max - oldest;
+        rrpv.iter_mut().for_each(|x| *x += age);
+        rrpv.iter().position(|x| *x == self.max).unwrap()
+    }
+}
+
+/// Pseudo-random replacement (with a seeded generator, so that simulations
+/// are reproducible).
+#[derive(Clone)]
+pub struct RandomPolicy {
+    valid: ValidBits,
+    rng: Rng,
+}
+impl RandomPolicy {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self { valid: ValidBits::new(sets, ways), rng: Rng::new(1) }
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+}
+impl ReplacementPolicy for RandomPolicy {
+    fn num_sets(&self) -> usize { self.valid.sets() }
+    fn num_ways(&self) -> usize { self.valid.ways }
+    fn touch(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, true);
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.valid.set(set, way, false);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        if let Some(way) = self.valid.first_invalid(set) {
+            return way;
+        }
+        (self.rng.next() % self.valid.ways as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Fill every way in set 0.
+    fn fill(p: &mut dyn ReplacementPolicy) {
+        for _ in 0..p.num_ways() {
+            let way = p.victim(0);
+            p.insert(0, way);
+        }
+    }
+
+    #[test]
+    fn repl_invalid_first() {
+        let mut policies: Vec<Box<dyn ReplacementPolicy>> = vec![
+            Box::new(LruPolicy::new(2, 4)),
+            Box::new(TreePlruPolicy::new(2, 4)),
+            Box::new(BitPlruPolicy::new(2, 4)),
+            Box::new(RripPolicy::srrip(2, 4)),
+            Box::new(RripPolicy::brrip(2, 4)),
+            Box::new(RandomPolicy::new(2, 4).with_seed(7)),
+        ];
+        for p in policies.iter_mut() {
+            // Ways are filled in order
+            let mut seen = Vec::new();
+            for _ in 0..4 {
+                let way = p.victim(0);
+                seen.push(way);
+                p.insert(0, way);
+            }
+            assert_eq!(seen, vec![0, 1, 2, 3]);
+
+            // Invalidated ways are replaced before valid ways
+            p.invalidate(0, 2);
+            assert_eq!(p.victim(0), 2);
+            // Other sets are unaffected
+            assert_eq!(p.victim(1), 0);
+        }
+    }
+
+    #[test]
+    fn repl_lru() {
+        let mut p = LruPolicy::new(1, 4);
+        fill(&mut p);
+        p.touch(0, 0);
+        p.touch(0, 2);
+        assert_eq!(p.victim(0), 1);
+        p.touch(0, 1);
+        assert_eq!(p.victim(0), 3);
+        p.touch(0, 3);
+        assert_eq!(p.victim(0), 0);
+    }
+
+    #[test]
+    fn repl_tree_plru() {
+        let mut p = TreePlruPolicy::new(1, 8);
+        fill(&mut p);
+        // The last fill (way 7) points every node on its path away
+        assert_eq!(p.victim(0), 0);
+        p.touch(0, 0);
+        assert_eq!(p.victim(0), 4);
+        p.touch(0, 4);
+        assert_eq!(p.victim(0), 2);
+        p.touch(0, 2);
+        assert_eq!(p.victim(0), 6);
+    }
+
+    #[test]
+    fn repl_bit_plru() {
+        let mut p = BitPlruPolicy::new(1, 4);
+        fill(&mut p);
+        // Filling way 3 cleared the other bits
+        assert_eq!(p.victim(0), 0);
+        p.touch(0, 0);
+        p.touch(0, 1);
+        assert_eq!(p.victim(0), 2);
+        // Every bit would be set, so the others are cleared
+        p.touch(0, 2);
+        assert_eq!(p.victim(0), 0);
+        p.touch(0, 0);
+        assert_eq!(p.victim(0), 1);
+    }
+
+    #[test]
+    fn repl_srrip_scan_resistant() {
+        let mut p = RripPolicy::srrip(1, 4);
+        fill(&mut p);
+        // Ways 0 and 1 are reused
+        p.touch(0, 0);
+        p.touch(0, 1);
+        // A short scan of new lines doesn't replace the reused ways (which
+        // would be replaced by LRU)
+        for _ in 0..4 {
+            let way = p.victim(0);
+            assert!(way >= 2);
+            p.insert(0, way);
+        }
+        assert_eq!(p.rrpv(0, 0), 2);
+        assert_eq!(p.rrpv(0, 1), 2);
+    }
+
+    #[test]
+    fn repl_brrip_inserts_distant() {
+        let mut p = RripPolicy::brrip(1, 4).with_throttle(4).with_seed(3);
+        let mut long = 0;
+        for _ in 0..1000 {
+            p.insert(0, 0);
+            match p.rrpv(0, 0) {
+                2 => long += 1,
+                x => assert_eq!(x, 3),
+            }
+        }
+        assert!((150..350).contains(&long), "{}", long);
+    }
+
+    #[test]
+    fn repl_random_seeded() {
+        let victims = |seed| {
+            let mut p = RandomPolicy::new(1, 8).with_seed(seed);
+            fill(&mut p);
+            (0..32).map(|_| p.victim(0)).collect::<Vec<_>>()
+        };
+        assert_eq!(victims(5), victims(5));
+        assert_ne!(victims(5), victims(6));
+        assert!(victims(5).iter().all(|w| *w < 8));
+    }
+}
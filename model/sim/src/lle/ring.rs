@@ -0,0 +1,168 @@
+//! Pointers into circular buffers.
+//!
+//! A [RingPtr] is an index into a buffer with `N` entries, plus a "wrap"
+//! bit which is flipped every time the index wraps around to zero. This is
+//! how a queue in hardware usually tells the difference between "full" and
+//! "empty" when the enqueue and dequeue pointers are equal, and how the age
+//! of two entries can be compared without keeping a separate age matrix.
+//!
+//! Internally, the pointer is a counter modulo `2 * N` (the wrap bit is
+//! the most-significant bit when `N` is a power of two).
+
+use std::fmt;
+use std::ops::{ Add, AddAssign };
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct RingPtr<const N: usize> {
+    /// Position in `0..2*N`
+    pos: usize,
+}
+impl <const N: usize> RingPtr<N> {
+    /// Create a pointer to entry zero (with the wrap bit unset).
+    pub const fn new() -> Self {
+        assert!(N != 0);
+        Self { pos: 0 }
+    }
+
+    /// Create a pointer from an index and a wrap bit.
+    pub fn from_parts(idx: usize, wrap: bool) -> Self {
+        assert!(idx < N, "RingPtr index {} out of bounds", idx);
+        Self { pos: idx + if wrap { N } else { 0 } }
+    }
+
+    /// Returns the index of the entry.
+    pub fn idx(&self) -> usize {
+        self.pos % N
+    }
+
+    /// Returns the wrap bit.
+    pub fn wrap(&self) -> bool {
+        self.pos >= N
+    }
+
+    /// Advance to the next entry.
+    pub fn inc(&mut self) {
+        *self += 1;
+    }
+
+    /// Returns the number of entries from `from` up to (but not including)
+    /// this pointer, ie. the number of entries in a queue where `from` is
+    /// the dequeue pointer and this is the enqueue pointer.
+    ///
+    /// NOTE: This is only meaningful when `from` is at most `N` entries
+    /// behind this pointer.
+    pub fn distance(&self, from: Self) -> usize {
+        (self.pos + 2 * N - from.pos) % (2 * N)
+    }
+
+    /// Returns true if this pointer refers to an entry allocated before
+    /// the entry at `other` (assuming both are in the same queue).
+    pub fn is_older_than(&self, other: Self) -> bool {
+        let d = other.distance(*self);
+        d != 0 && d <= N
+    }
+
+    /// Returns the number of used entries in a queue.
+    pub fn num_used(enq: Self, deq: Self) -> usize {
+        let res = enq.distance(deq);
+        assert!(res <= N, "RingPtr {:?} is more than {} entries ahead of {:?}",
+            enq, N, deq);
+        res
+    }
+
+    /// Returns the number of free entries in a queue.
+    pub fn num_free(enq: Self, deq: Self) -> usize {
+        N - Self::num_used(enq, deq)
+    }
+
+    /// A queue is empty when both pointers are equal.
+    pub fn is_empty(enq: Self, deq: Self) -> bool {
+        enq == deq
+    }
+
+    /// A queue is full when both pointers have the same index, but the
+    /// enqueue pointer has wrapped around one more time.
+    pub fn is_full(enq: Self, deq: Self) -> bool {
+        enq.idx() == deq.idx() && enq.wrap() != deq.wrap()
+    }
+}
+impl <const N: usize> Add<usize> for RingPtr<N> {
+    type Output = Self;
+    fn add(self, n: usize) -> Self {
+        Self { pos: (self.pos + n % (2 * N)) % (2 * N) }
+    }
+}
+impl <const N: usize> AddAssign<usize> for RingPtr<N> {
+    fn add_assign(&mut self, n: usize) {
+        *self = *self + n;
+    }
+}
+impl <const N: usize> fmt::Debug for RingPtr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.wrap() { "~" } else { "" }, self.idx())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ring_wrap() {
+        let mut p = RingPtr::<3>::new();
+        let mut seen = Vec::new();
+        for _ in 0..7 {
+            seen.push((p.idx(), p.wrap()));
+            p.inc();
+        }
+        assert_eq!(seen, vec![
+            (0, false), (1, false), (2, false),
+            (0, true), (1, true), (2, true),
+            (0, false),
+        ]);
+        assert_eq!(RingPtr::<3>::new() + 10, RingPtr::from_parts(1, true));
+    }
+
+    #[test]
+    fn ring_full_empty() {
+        let deq = RingPtr::<4>::from_parts(2, false);
+        let mut enq = deq;
+        assert!(RingPtr::is_empty(enq, deq));
+        assert_eq!(RingPtr::num_free(enq, deq), 4);
+        for used in 1..=4 {
+            enq.inc();
+            assert!(!RingPtr::is_empty(enq, deq));
+            assert_eq!(RingPtr::num_used(enq, deq), used);
+        }
+        assert!(RingPtr::is_full(enq, deq));
+        assert_eq!(RingPtr::num_free(enq, deq), 0);
+        assert_eq!(enq.idx(), deq.idx());
+    }
+
+    #[test]
+    fn ring_age() {
+        // Entries allocated in order, across the wrap boundary
+        let ptrs: Vec<RingPtr<4>> = (0..4)
+            .map(|n| RingPtr::from_parts(2, true) + n).collect();
+        for (i, a) in ptrs.iter().enumerate() {
+            for (j, b) in ptrs.iter().enumerate() {
+                assert_eq!(a.is_older_than(*b), i < j, "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    /// Check against an unwrapped counter, for sizes that aren't a power
+    /// of two.
+    #[test]
+    fn ring_distance() {
+        for n in 0..64usize {
+            for d in 0..=5usize {
+                let a = RingPtr::<5>::new() + n;
+                let b = a + d;
+                assert_eq!(b.distance(a), d);
+                assert_eq!(b.idx(), (n + d) % 5);
+                assert_eq!(b.wrap(), (n + d) / 5 % 2 == 1);
+            }
+        }
+    }
+}
@@ -4,191 +4,211 @@ use std::rc::*;
 
 use crate::lle::*;
 use crate::lle::register::*;
+use crate::lle::trace::*;
+use crate::lle::cost::*;
+
+/// What a read port observes when the same entry is written on the same
+/// cycle (like `SyncReadMem.ReadUnderWrite` in Chisel).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadDuringWrite {
+    /// The read returns the old value.
+    ReadFirst,
+    /// The read returns the new value (the write is bypassed to the read).
+    WriteFirst,
+    /// The read returns an undefined value (the default in Chisel).
+    /// Sampling the result with [SyncMem::sample_rp] panics.
+    Undefined,
+}
 
 pub struct SyncReadPort<D: Copy + Default> {
-    en: Reg<bool>,
-    idx: Reg<usize>,
+    /// The index being read on this cycle
+    idx: Option<usize>,
+    /// The result of the last read
     data: Reg<D>,
+    /// Set when the result of the last read is undefined
+    poison: Reg<bool>,
 }
 impl <D: Copy + Default> SyncReadPort<D> {
     pub fn new() -> Self {
-        Self { 
-            en: Reg::new(false),
-            idx: Reg::new(0),
+        Self {
+            idx: None,
             data: Reg::new(D::default()),
+            poison: Reg::new(false),
         }
     }
-    pub fn drive_en(&mut self, en: bool) { self.en.drive(en); }
-    pub fn drive_idx(&mut self, idx: usize) { self.idx.drive(idx); }
-    pub fn drive_data(&mut self, data: D) { self.data.drive(data); }
-    pub fn sample_en(&self) -> bool { self.en.sample() }
-    pub fn sample_idx(&self) -> usize { self.idx.sample() }
-    pub fn sample_data(&self) -> D { self.data.sample() }
-
 }
-impl <D: Copy + Default> Clocked for SyncReadPort<D> {
-    fn update(&mut self) {
-        self.en.update();
-        self.idx.update();
-        self.data.update();
+impl <D: Copy + Default> Resettable for SyncReadPort<D> {
+    fn reset(&mut self) {
+        self.idx = None;
+        self.data.reset();
+        self.poison.reset();
     }
 }
 
 pub struct SyncWritePort<D: Copy + Default> {
-    en: Reg<bool>,
-    idx: Reg<usize>,
-    data: Reg<D>,
+    /// The index and data being written on this cycle
+    req: Option<(usize, D)>,
 }
 impl <D: Copy + Default> SyncWritePort<D> {
     pub fn new() -> Self {
-        Self { 
-            en: Reg::new(false),
-            idx: Reg::new(0),
-            data: Reg::new(D::default()),
-        }
+        Self { req: None }
     }
 }
-impl <D: Copy + Default> Clocked for SyncWritePort<D> {
-    fn update(&mut self) {
-        self.en.update();
-        self.idx.update();
-        self.data.update();
+impl <D: Copy + Default> Resettable for SyncWritePort<D> {
+    fn reset(&mut self) {
+        self.req = None;
     }
 }
 
 
-
-/// Memory element (synchronous read, synchronous write).
-/// Dynamically-provisioned read and write ports. 
+/// Memory element (synchronous read, synchronous write) with `NUM_RP` read
+/// ports and `NUM_WP` write ports.
 ///
-/// FIXME: What to do about bypassing between ports?
-pub struct SyncMem<D: Copy + Default, const SZ: usize> {
+/// This is meant to behave like a Chisel `SyncReadMem`:
+///
+/// - Read and write requests are driven on one cycle, and take effect at
+///   the next clock edge
+/// - The result of a read is available on the cycle after the request,
+///   and is held until the next read on the same port
+/// - When an entry is read and written on the same cycle, the result
+///   depends on the [ReadDuringWrite] policy
+///
+/// NOTE: Writing the same entry from more than one port on the same cycle
+/// is undefined in Chisel, so we panic.
+pub struct SyncMem<D: Copy + Default, const SZ: usize,
+    const NUM_RP: usize, const NUM_WP: usize>
+{
     data: [ Reg<D>; SZ ],
-    rp: Vec<SyncReadPort<D>>,
-    wp: Vec<SyncWritePort<D>>,
-
+    rp: [SyncReadPort<D>; NUM_RP],
+    wp: [SyncWritePort<D>; NUM_WP],
+    policy: ReadDuringWrite,
 }
-impl <D: Copy + Default, const SZ: usize> SyncMem<D, SZ> {
-    pub fn new_init_array(init: &[D; SZ]) -> Self { 
+impl <D: Copy + Default, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
+SyncMem<D, SZ, NUM_RP, NUM_WP>
+{
+    pub fn new_init_array(init: &[D; SZ]) -> Self {
         let data: [Reg<D>; SZ] = init.map(Reg::new);
-        Self { 
+        Self {
             data,
-            rp: Vec::new(),
-            wp: Vec::new(),
+            rp: std::array::from_fn(|_| SyncReadPort::new()),
+            wp: std::array::from_fn(|_| SyncWritePort::new()),
+            policy: ReadDuringWrite::Undefined,
         }
     }
-    pub fn new_init_val(init: D) -> Self { 
-        Self { 
-            data: [ Reg::new(init); SZ ],
-            rp: Vec::new(),
-            wp: Vec::new(),
-        }
+    pub fn new_init_val(init: D) -> Self {
+        Self::new_init_array(&[init; SZ])
     }
 
-    pub fn with_read_ports(mut self, num: usize) -> Self { 
-        for _ in 0..num { self.rp.push(SyncReadPort::new()); }
+    /// Set the read-during-write policy (the default is
+    /// [ReadDuringWrite::Undefined]).
+    pub fn with_policy(mut self, policy: ReadDuringWrite) -> Self {
+        self.policy = policy;
         self
     }
-    pub fn with_write_ports(mut self, num: usize) -> Self { 
-        for _ in 0..num { self.wp.push(SyncWritePort::new()); }
-        self
-    }
-    pub fn new_read_port(&mut self) -> usize {
-        let rp_idx = self.rp.len();
-        self.rp.push(SyncReadPort::new());
-        rp_idx
-    }
-    pub fn new_write_port(&mut self) -> usize {
-        let wp_idx = self.rp.len();
-        self.wp.push(SyncWritePort::new());
-        wp_idx
-    }
-    pub fn num_read_ports(&self) -> usize { self.rp.len() }
-    pub fn num_write_ports(&self) -> usize { self.wp.len() }
+    pub fn policy(&self) -> ReadDuringWrite { self.policy }
 
+    pub fn num_read_ports(&self) -> usize { NUM_RP }
+    pub fn num_write_ports(&self) -> usize { NUM_WP }
+
+    /// Drive a read request on a read port.
     pub fn drive_rp(&mut self, rp: usize, en: bool, idx: usize) {
-        self.rp[rp].drive_en(en);
-        self.rp[rp].drive_idx(idx);
+        assert!(idx < SZ, "SyncMem read index {} out of bounds", idx);
+        self.rp[rp].idx = if en { Some(idx) } else { None };
+    }
+
+    /// Drive a write request on a write port.
+    pub fn drive_wp(&mut self, wp: usize, en: bool, idx: usize, data: D) {
+        assert!(idx < SZ, "SyncMem write index {} out of bounds", idx);
+        self.wp[wp].req = if en { Some((idx, data)) } else { None };
     }
+
+    /// Sample the result of the last read on a read port.
     pub fn sample_rp(&self, rp: usize) -> D {
-        self.rp[rp].sample_data()
+        assert!(!self.is_poisoned(rp),
+            "SyncMem read port {} has an undefined value \
+            (read during write)", rp);
+        self.rp[rp].data.sample()
     }
 
+    /// Returns true if the result of the last read on a read port is
+    /// undefined (see [ReadDuringWrite::Undefined]).
+    pub fn is_poisoned(&self, rp: usize) -> bool {
+        self.rp[rp].poison.sample()
+    }
 
+    /// Returns the value of an entry (without using a read port).
+    pub fn peek(&self, idx: usize) -> D {
+        self.data[idx].sample()
+    }
 }
-impl <D: Copy + Default, const SZ: usize> Clocked for SyncMem<D, SZ> {
+impl <D: Copy + Default, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
+Clocked for SyncMem<D, SZ, NUM_RP, NUM_WP>
+{
     fn update(&mut self) {
+        let mut writes: [Option<(usize, D)>; NUM_WP] = [None; NUM_WP];
+        for (wp_idx, wp) in self.wp.iter_mut().enumerate() {
+            if let Some((idx, data)) = wp.req.take() {
+                if writes.iter().flatten().any(|(i, _)| *i == idx) {
+                    panic!("SyncMem write ports conflict on entry {}", idx);
+                }
+                writes[wp_idx] = Some((idx, data));
+            }
+        }
+
         for rp in self.rp.iter_mut() {
+            let Some(idx) = rp.idx.take() else { continue };
+            let old = self.data[idx].sample();
+            let new = writes.iter().flatten().find(|(i, _)| *i == idx);
+            let (data, poison) = match (new, self.policy) {
+                (None, _) => (old, false),
+                (Some(_), ReadDuringWrite::ReadFirst) => (old, false),
+                (Some((_, d)), ReadDuringWrite::WriteFirst) => (*d, false),
+                (Some(_), ReadDuringWrite::Undefined) => (D::default(), true),
+            };
+            rp.data.drive(data);
+            rp.poison.drive(poison);
+            rp.data.update();
+            rp.poison.update();
         }
-        for wp in self.wp.iter_mut() {
+
+        for (idx, data) in writes.iter().flatten() {
+            self.data[*idx].drive(*data);
+            self.data[*idx].update();
+        }
+    }
+    fn is_dirty(&self) -> bool {
+        self.rp.iter().any(|rp| rp.idx.is_some())
+            || self.wp.iter().any(|wp| wp.req.is_some())
+    }
+}
+impl <D: Copy + Default, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
+Resettable for SyncMem<D, SZ, NUM_RP, NUM_WP>
+{
+    fn reset(&mut self) {
+        self.data.reset();
+        self.rp.reset();
+        self.wp.reset();
+    }
+}
+impl <D, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
+Cost for SyncMem<D, SZ, NUM_RP, NUM_WP>
+    where D: Copy + Default + HwBits
+{
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(path, StorageCost::new("SyncMem", SZ, D::BITS)
+            .with_ports(NUM_RP, NUM_WP));
+    }
+}
+impl <D, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
+Trace for SyncMem<D, SZ, NUM_RP, NUM_WP>
+    where D: Copy + Default + TraceValue
+{
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        for (idx, r) in self.data.iter().enumerate() {
+            r.trace(&format!("{}[{}]", path, idx), t);
         }
     }
 }
-
-
-
-//// FIXME: Should "sampling" also mean sampling the index?
-//pub struct SyncReadPort<I, D: Copy> {
-//    idx: Option<I>,
-//    data: Option<D>,
-//}
-//impl <I, D: Copy> SyncReadPort<I, D> {
-//    pub fn drive(&mut self, idx: I) { self.idx = Some(idx); }
-//    pub fn update(&mut self, data: D) { self.data = Some(data); }
-//    pub fn sample(&self) -> Option<D> { self.data }
-//    pub fn clear(&mut self) {
-//        self.idx = None;
-//        self.data = None;
-//    }
-//}
-//
-//pub struct SyncWritePort<I, D> {
-//    state: Option<(I,D)>,
-//}
-//impl <I, D> SyncWritePort<I, D> {
-//    pub fn clear(&mut self) { self.state = None; }
-//    pub fn drive(&mut self, idx: I, data: D) { 
-//        self.state = Some((idx, data)); 
-//    }
-//}
-//
-//
-//
-///// Register file with synchronous read, statically-provisioned ports, and
-///// no write-to-read forwarding.
-//pub struct SyncRegisterFile<D: Copy, 
-//    const SZ: usize, const NUM_RP: usize, const NUM_WP: usize> 
-//{
-//    data: [D; SZ],
-//    rp: [SyncReadPort<usize, D>; NUM_RP],
-//    wp: [SyncWritePort<usize, D>; NUM_WP],
-//}
-//impl <D: Copy, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
-//SyncRegisterFile<D, SZ, NUM_RP, NUM_WP> 
-//{
-//    pub fn drive_wp(&mut self, port: usize, idx: usize, data: D) {
-//        self.wp[port].drive(idx, data);
-//    }
-//    pub fn drive_rp(&mut self, port: usize, idx: usize) {
-//        self.rp[port].drive(idx);
-//    }
-//    pub fn sample_rp(&self, port: usize) -> Option<D> {
-//        self.rp[port].sample()
-//    }
-//
-//    pub fn update(&mut self) {
-//        for rp in self.rp.iter_mut() {
-//            if let Some(idx) = rp.idx.take() {
-//                rp.update(self.data[idx]);
-//            }
-//        }
-//        for wp in &mut self.wp {
-//            if let Some((idx, data)) = wp.state.take() {
-//                self.data[idx] = data;
-//            }
-//        }
-//    }
-//}
 
 #[cfg(test)]
 mod test {
@@ -196,32 +216,28 @@ mod test {
 
     #[test]
     fn syncmem_init_val() {
-        let mem = SyncMem::<u32, 32>::new_init_val(0);
+        let mem = SyncMem::<u32, 32, 1, 1>::new_init_val(0);
+        assert_eq!(mem.peek(31), 0);
     }
 
     #[test]
     fn syncmem_init_array() {
-        let mem = SyncMem::<u32, 32>::new_init_array(&[0; 32]);
+        let mut init = [0; 32];
+        init[3] = 3;
+        let mem = SyncMem::<u32, 32, 1, 1>::new_init_array(&init);
+        assert_eq!(mem.peek(3), 3);
     }
 
     #[test]
     fn syncmem_create_ports() {
-        let mut mem = SyncMem::<u32, 32>::new_init_val(0)
-            .with_read_ports(1)
-            .with_write_ports(1);
-        assert_eq!(mem.num_read_ports(), 1);
-        assert_eq!(mem.num_write_ports(), 1);
-        mem.new_read_port();
-        mem.new_write_port();
+        let mem = SyncMem::<u32, 32, 2, 1>::new_init_val(0);
         assert_eq!(mem.num_read_ports(), 2);
-        assert_eq!(mem.num_write_ports(), 2);
+        assert_eq!(mem.num_write_ports(), 1);
     }
 
     #[test]
     fn syncmem_readwrite() {
-        let mut mem = SyncMem::<u32, 32>::new_init_val(0xdeadbeef)
-            .with_read_ports(2)
-            .with_write_ports(1);
+        let mut mem = SyncMem::<u32, 32, 2, 1>::new_init_val(0xdeadbeef);
 
         assert_eq!(mem.sample_rp(0), 0x00000000);
         mem.drive_rp(0, true, 0);
@@ -231,12 +247,81 @@ mod test {
 
         assert_eq!(mem.sample_rp(0), 0xdeadbeef);
 
+        // Writes take effect at the clock edge
+        mem.drive_wp(0, true, 1, 0x1234);
+        mem.update();
+        mem.drive_rp(1, true, 1);
+        mem.update();
+        assert_eq!(mem.sample_rp(1), 0x1234);
 
+        // The result is held when the port isn't enabled
+        mem.drive_rp(1, false, 0);
+        mem.update();
+        assert_eq!(mem.sample_rp(1), 0x1234);
+        assert_eq!(mem.sample_rp(0), 0xdeadbeef);
     }
 
+    /// Read and write entry 0 on the same cycle.
+    fn read_during_write(policy: ReadDuringWrite) -> SyncMem<u32, 4, 1, 1> {
+        let mut mem = SyncMem::<u32, 4, 1, 1>::new_init_val(1)
+            .with_policy(policy);
+        mem.drive_rp(0, true, 0);
+        mem.drive_wp(0, true, 0, 2);
+        mem.update();
+        assert_eq!(mem.peek(0), 2);
+        mem
+    }
 
-}
+    #[test]
+    fn syncmem_read_first() {
+        let mem = read_during_write(ReadDuringWrite::ReadFirst);
+        assert_eq!(mem.sample_rp(0), 1);
+    }
 
+    #[test]
+    fn syncmem_write_first() {
+        let mem = read_during_write(ReadDuringWrite::WriteFirst);
+        assert_eq!(mem.sample_rp(0), 2);
+    }
 
+    #[test]
+    #[should_panic(expected = "undefined value")]
+    fn syncmem_undefined() {
+        let mut mem = read_during_write(ReadDuringWrite::Undefined);
+        assert!(mem.is_poisoned(0));
+
+        // The next read is well-defined
+        mem.drive_rp(0, true, 0);
+        mem.update();
+        assert!(!mem.is_poisoned(0));
+        assert_eq!(mem.sample_rp(0), 2);
 
+        mem.drive_rp(0, true, 1);
+        mem.drive_wp(0, true, 1, 3);
+        mem.update();
+        mem.sample_rp(0);
+    }
+
+    #[test]
+    fn syncmem_reset() {
+        let mut mem = SyncMem::<u32, 4, 1, 1>::new_init_val(1);
+        mem.drive_wp(0, true, 0, 2);
+        mem.drive_rp(0, true, 0);
+        mem.update();
+        mem.drive_wp(0, true, 1, 2);
+        mem.reset();
+        mem.update();
+        assert_eq!(mem.peek(0), 1);
+        assert_eq!(mem.peek(1), 1);
+        assert_eq!(mem.sample_rp(0), 0);
+    }
 
+    #[test]
+    #[should_panic(expected = "conflict")]
+    fn syncmem_write_conflict() {
+        let mut mem = SyncMem::<u32, 4, 1, 2>::new_init_val(0);
+        mem.drive_wp(0, true, 0, 1);
+        mem.drive_wp(1, true, 0, 2);
+        mem.update();
+    }
+}
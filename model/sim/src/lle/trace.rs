@@ -0,0 +1,342 @@
+//! Signal tracing and VCD waveform dumping.
+//!
+//! Components implementing [Trace] report the value of their signals to a
+//! [Tracer]. Each signal has a hierarchical path (ie. `top.core.r_pc`)
+//! which is built from the names given to components when they are tracked
+//! with [ClockedState::track_named].
+//!
+//! [VcdWriter] is a [Tracer] which writes a VCD file that can be viewed in
+//! GTKWave (ie. next to a waveform from the Chisel simulation). When a
+//! [VcdWriter] is attached to a [ClockedState] (see
+//! [ClockedState::trace_vcd]), all traced values are sampled at the start
+//! of every call to [ClockedState::update] (before the clock edge).
+//!
+//! See [crate::lle::vcd] for reading a VCD file, and comparing the model
+//! against a waveform from the RTL.
+
+use std::io::{self, Write};
+
+use crate::lle::*;
+
+/// A value that can be represented with a fixed number of bits.
+pub trait TraceValue {
+    /// Width of the value (in bits, at most 64).
+    const WIDTH: u32;
+    /// Returns the bits of this value, or [None] if the value is undefined.
+    fn trace_bits(&self) -> Option<u64>;
+}
+/// NOTE: Signed values are masked to their width (instead of being
+/// sign-extended to 64 bits).
+macro_rules! impl_trace_value {
+    ($($ty:ty),*) => { $(
+        impl TraceValue for $ty {
+            const WIDTH: u32 = <$ty>::BITS;
+            fn trace_bits(&self) -> Option<u64> {
+                Some(*self as u64 & (u64::MAX >> (64 - Self::WIDTH)))
+            }
+        }
+    )* }
+}
+impl_trace_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl TraceValue for bool {
+    const WIDTH: u32 = 1;
+    fn trace_bits(&self) -> Option<u64> { Some(*self as u64) }
+}
+
+/// [None] is traced as an undefined value.
+impl <T: TraceValue> TraceValue for Option<T> {
+    const WIDTH: u32 = T::WIDTH;
+    fn trace_bits(&self) -> Option<u64> {
+        self.as_ref().and_then(|x| x.trace_bits())
+    }
+}
+
+/// A consumer of traced values.
+pub trait Tracer {
+    /// Record the value of the signal at `path`.
+    fn signal(&mut self, path: &str, width: u32, value: Option<u64>);
+}
+
+/// A component with signals that can be traced.
+pub trait Trace {
+    /// Report the value of all signals in this component, where `path` is
+    /// the hierarchical name of this component.
+    fn trace(&self, path: &str, t: &mut dyn Tracer);
+}
+
+/// Match a path against a pattern, where `*` matches any sequence of
+/// characters (including `.`).
+fn glob_match(pattern: &str, path: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == path,
+        Some((head, tail)) => {
+            let Some(rest) = path.strip_prefix(head) else { return false };
+            (0..=rest.len()).filter(|i| rest.is_char_boundary(*i))
+                .any(|i| glob_match(tail, &rest[i..]))
+        },
+    }
+}
+
+struct VcdVar {
+    path: String,
+    width: u32,
+    code: String,
+    last: Option<Option<u64>>,
+}
+
+/// Writes traced values to a VCD file.
+///
+/// The set of signals is fixed by the first call to [VcdWriter::sample],
+/// which also writes the header.
+pub struct VcdWriter<W: Write> {
+    out: W,
+    timescale: &'static str,
+    /// Only signals matching one of these patterns are written (or all
+    /// signals, when empty).
+    filters: Vec<String>,
+    vars: Vec<VcdVar>,
+    started: bool,
+    /// Values collected during the current call to [VcdWriter::sample].
+    cur: Vec<(String, u32, Option<u64>)>,
+}
+impl <W: Write> VcdWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            timescale: "1ns",
+            filters: Vec::new(),
+            vars: Vec::new(),
+            started: false,
+            cur: Vec::new(),
+        }
+    }
+    /// Set the timescale written in the header (the default is `1ns`).
+    pub fn with_timescale(mut self, timescale: &'static str) -> Self {
+        self.timescale = timescale;
+        self
+    }
+    /// Only write signals with a path matching `pattern`, ie. `top.core.*`.
+    /// Multiple filters may be added.
+    pub fn with_filter(mut self, pattern: &str) -> Self {
+        self.filters.push(pattern.to_string());
+        self
+    }
+
+    pub fn get_ref(&self) -> &W { &self.out }
+    pub fn into_inner(self) -> W { self.out }
+
+    fn selected(&self, path: &str) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|f| glob_match(f, path))
+    }
+
+    /// Returns a short identifier for the variable at `idx`.
+    fn code(mut idx: usize) -> String {
+        let mut res = String::new();
+        loop {
+            res.push((b'!' + (idx % 94) as u8) as char);
+            idx /= 94;
+            if idx == 0 {
+                return res;
+            }
+            idx -= 1;
+        }
+    }
+
+    fn write_value(out: &mut W, var: &VcdVar, value: Option<u64>) -> io::Result<()> {
+        match (var.width, value) {
+            (1, Some(v)) => writeln!(out, "{}{}", v & 1, var.code),
+            (1, None) => writeln!(out, "x{}", var.code),
+            (_, Some(v)) => writeln!(out, "b{:b} {}", v, var.code),
+            (_, None) => writeln!(out, "bx {}", var.code),
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        writeln!(self.out, "$timescale {} $end", self.timescale)?;
+        let mut scope: Vec<&str> = Vec::new();
+        for var in self.vars.iter() {
+            let mut parts: Vec<&str> = var.path.split('.').collect();
+            let name = parts.pop().unwrap();
+            let common = scope.iter().zip(parts.iter())
+                .take_while(|(a, b)| a == b).count();
+            while scope.len() > common {
+                scope.pop();
+                writeln!(self.out, "$upscope $end")?;
+            }
+            for part in &parts[common..] {
+                writeln!(self.out, "$scope module {} $end", part)?;
+                scope.push(part);
+            }
+            writeln!(self.out, "$var wire {} {} {} $end", var.width, var.code, name)?;
+        }
+        for _ in scope.iter() {
+            writeln!(self.out, "$upscope $end")?;
+        }
+        writeln!(self.out, "$enddefinitions $end")
+    }
+
+    /// Sample all signals in `root` at time `time`.
+    pub fn sample(&mut self, time: usize, path: &str, root: &dyn Trace)
+        -> io::Result<()>
+    {
+        self.cur.clear();
+        root.trace(path, self);
+        let cur = std::mem::take(&mut self.cur);
+
+        if !self.started {
+            for (path, width, _) in cur.iter() {
+                let code = Self::code(self.vars.len());
+                self.vars.push(VcdVar {
+                    path: path.clone(), width: *width, code, last: None
+                });
+            }
+            self.write_header()?;
+            self.started = true;
+        }
+
+        writeln!(self.out, "#{}", time)?;
+        for (idx, (path, _, value)) in cur.iter().enumerate() {
+            // Signals are usually reported in the same order every time.
+            // NOTE: Signals that didn't exist in the first sample are ignored
+            let idx = match self.vars.get(idx) {
+                Some(var) if &var.path == path => idx,
+                _ => match self.vars.iter().position(|v| &v.path == path) {
+                    Some(idx) => idx,
+                    None => continue,
+                },
+            };
+            let var = &mut self.vars[idx];
+            if var.last != Some(*value) {
+                Self::write_value(&mut self.out, var, *value)?;
+                var.last = Some(*value);
+            }
+        }
+        self.cur = cur;
+        Ok(())
+    }
+}
+impl <W: Write> Tracer for VcdWriter<W> {
+    fn signal(&mut self, path: &str, width: u32, value: Option<u64>) {
+        if self.selected(path) {
+            self.cur.push((path.to_string(), width, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::register::*;
+    use crate::lle::wire::*;
+    use crate::lle::queue::*;
+
+    /// A [Write] which can be inspected while owned by a [ClockedState].
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn trace_glob() {
+        assert!(glob_match("top.core.*", "top.core.r_pc"));
+        assert!(glob_match("*.r_pc", "top.core.r_pc"));
+        assert!(glob_match("top.*.r_*", "top.core.r_pc"));
+        assert!(!glob_match("top.core.*", "top.mem.r_pc"));
+        assert!(!glob_match("top.core", "top.core.r_pc"));
+    }
+
+    #[test]
+    fn trace_vcd() {
+        let buf = SharedBuf::default();
+        let mut core = ClockedState::new();
+        let r_pc = Rc::new(RefCell::new(Reg::new(0u32)));
+        let w_valid = Rc::new(RefCell::new(Wire::<bool>::new()));
+        core.track_named("r_pc", &r_pc);
+        core.track_named("w_valid", &w_valid);
+        let core = Rc::new(RefCell::new(core));
+
+        let mut top = ClockedState::new()
+            .trace_vcd("top", VcdWriter::new(Box::new(buf.clone())));
+        let q = Rc::new(RefCell::new(Queue::<u32>::new()));
+        top.track_named("core", &core);
+        top.track_named("q", &q);
+
+        for cyc in 0..3 {
+            let pc = r_pc.borrow().sample();
+            r_pc.borrow_mut().drive(pc + 4);
+            w_valid.borrow_mut().drive(cyc != 1);
+            q.borrow_mut().enq(pc);
+            top.update();
+        }
+
+        let vcd = String::from_utf8(buf.0.borrow().clone()).unwrap();
+        let expected = "\
+$timescale 1ns $end
+$scope module top $end
+$scope module core $end
+$var wire 32 ! r_pc $end
+$var wire 1 \" w_valid $end
+$upscope $end
+$scope module q $end
+$var wire 32 # len $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+b0 !
+1\"
+b0 #
+#1
+b100 !
+0\"
+b1 #
+#2
+b1000 !
+1\"
+b10 #
+";
+        assert_eq!(vcd, expected);
+    }
+
+    #[test]
+    fn trace_vcd_filter() {
+        let mut state = ClockedState::new();
+        let r_a = Rc::new(RefCell::new(Reg::new(1u8)));
+        let r_b = Rc::new(RefCell::new(Reg::new(Some(2u8))));
+        let r_c = Rc::new(RefCell::new(Reg::new(None::<u8>)));
+        state.track_named("r_a", &r_a);
+        state.track_named("r_b", &r_b);
+        state.track_named("r_c", &r_c);
+
+        let mut vcd = VcdWriter::new(Vec::new())
+            .with_filter("*.r_c")
+            .with_filter("top.r_b");
+        vcd.sample(0, "top", &state).unwrap();
+        let vcd = String::from_utf8(vcd.into_inner()).unwrap();
+        assert!(!vcd.contains("r_a"));
+        assert!(vcd.contains("$var wire 8 ! r_b $end"));
+        assert!(vcd.contains("$var wire 8 \" r_c $end"));
+        assert!(vcd.ends_with("#0\nb10 !\nbx \"\n"));
+    }
+
+    #[test]
+    fn trace_vcd_signed() {
+        let mut state = ClockedState::new();
+        let r_a = Rc::new(RefCell::new(Reg::new(-2i8)));
+        let r_b = Rc::new(RefCell::new(Reg::new(-1i32)));
+        state.track_named("r_a", &r_a);
+        state.track_named("r_b", &r_b);
+
+        let mut vcd = VcdWriter::new(Vec::new());
+        vcd.sample(0, "top", &state).unwrap();
+        let vcd = String::from_utf8(vcd.into_inner()).unwrap();
+        assert!(vcd.contains("$var wire 8 ! r_a $end"));
+        assert!(vcd.ends_with(&format!("#0\nb11111110 !\nb{} \"\n", "1".repeat(32))));
+    }
+}
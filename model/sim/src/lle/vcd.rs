@@ -0,0 +1,622 @@
+//! Reading VCD waveforms, and comparing the model against RTL.
+//!
+//! A [Vcd] holds every value change in a VCD file, ie. a waveform written
+//! by a [VcdWriter](crate::lle::trace::VcdWriter) for the model, or by a
+//! Chisel test for the RTL.
+//!
+//! A [VcdComparator] maps signals in the model to signals in the RTL, and
+//! compares their values on each cycle. The first difference is reported
+//! as a [Divergence], along with the values of every mapped signal on the
+//! surrounding cycles.
+//!
+//! Values are sampled once per cycle:
+//!
+//! - In a waveform from the model, each timestamp is a cycle (the values
+//!   are sampled before each clock edge)
+//! - In a waveform from the RTL, each rising edge of a clock signal is a
+//!   cycle, and values are sampled just before the edge (see
+//!   [VcdComparator::with_clock])
+//!
+//! NOTE: Only the low 64 bits of wider signals are kept.
+
+use std::collections::*;
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// A problem reading a VCD file.
+#[derive(Debug)]
+pub enum VcdError {
+    Io(io::Error),
+    /// Malformed input at some line (starting from 1).
+    Parse { line: usize, msg: String },
+}
+impl fmt::Display for VcdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+        }
+    }
+}
+impl std::error::Error for VcdError {}
+impl From<io::Error> for VcdError {
+    fn from(e: io::Error) -> Self { Self::Io(e) }
+}
+
+/// A variable declared in a VCD file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdSignal {
+    /// Hierarchical path (scopes and the variable name, joined with `.`)
+    pub path: String,
+    /// Width (in bits)
+    pub width: u32,
+    /// Index of the value changes for this signal (signals with the same
+    /// identifier code share their value changes).
+    id: usize,
+}
+
+/// The value changes in a VCD file.
+#[derive(Clone, Debug, Default)]
+pub struct Vcd {
+    timescale: Option<String>,
+    signals: Vec<VcdSignal>,
+    /// Value changes (in time order) for each identifier code, where
+    /// [None] is an undefined value
+    changes: Vec<Vec<(u64, Option<u64>)>>,
+    /// Every timestamp in the file
+    times: Vec<u64>,
+}
+impl Vcd {
+    /// Read a VCD file.
+    pub fn read(mut r: impl Read) -> Result<Self, VcdError> {
+        let mut s = String::new();
+        r.read_to_string(&mut s)?;
+        Self::parse(&s)
+    }
+
+    /// Parse the contents of a VCD file.
+    pub fn parse<'a>(input: &'a str) -> Result<Self, VcdError> {
+        let mut res = Self::default();
+        let mut codes: HashMap<&str, usize> = HashMap::new();
+        let mut scope: Vec<&str> = Vec::new();
+        let mut time = 0;
+        let mut in_defs = true;
+
+        let mut tokens = input.lines().enumerate()
+            .flat_map(|(idx, l)| l.split_whitespace().map(move |t| (idx + 1, t)));
+        let err = |line: usize, msg: String| VcdError::Parse { line, msg };
+
+        while let Some((line, tok)) = tokens.next() {
+            // Collect the tokens of a command (up to '$end')
+            let args = |tokens: &mut dyn Iterator<Item=(usize, &'a str)>| {
+                let mut args: Vec<&str> = Vec::new();
+                loop {
+                    match tokens.next() {
+                        Some((_, "$end")) => return Ok(args),
+                        Some((_, t)) => args.push(t),
+                        None => return Err(err(line, format!("missing $end for {}", tok))),
+                    }
+                }
+            };
+            match tok {
+                "$timescale" => res.timescale = Some(args(&mut tokens)?.join(" ")),
+                "$scope" => {
+                    let a = args(&mut tokens)?;
+                    let name = a.get(1)
+                        .ok_or_else(|| err(line, "missing scope name".to_string()))?;
+                    scope.push(name);
+                },
+                "$upscope" => {
+                    args(&mut tokens)?;
+                    scope.pop()
+                        .ok_or_else(|| err(line, "$upscope without $scope".to_string()))?;
+                },
+                "$var" => {
+                    // '$var <type> <width> <code> <name> [<range>] $end'
+                    let a = args(&mut tokens)?;
+                    if a.len() < 4 {
+                        return Err(err(line, "malformed $var".to_string()));
+                    }
+                    let width = a[1].parse()
+                        .map_err(|_| err(line, format!("bad width '{}'", a[1])))?;
+                    let next = res.changes.len();
+                    let id = *codes.entry(a[2]).or_insert(next);
+                    if id == next {
+                        res.changes.push(Vec::new());
+                    }
+                    let mut path = scope.join(".");
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(a[3]);
+                    res.signals.push(VcdSignal { path, width, id });
+                },
+                "$enddefinitions" => {
+                    args(&mut tokens)?;
+                    in_defs = false;
+                },
+                // Value changes in '$dumpvars' (and friends) are handled
+                // like any others
+                "$dumpvars" | "$dumpall" | "$dumpon" | "$dumpoff" | "$end" => {},
+                _ if tok.starts_with('$') => { args(&mut tokens)?; },
+                _ if in_defs => {
+                    return Err(err(line, format!("unexpected '{}' in header", tok)));
+                },
+                _ if tok.starts_with('#') => {
+                    time = tok[1..].parse()
+                        .map_err(|_| err(line, format!("bad timestamp '{}'", tok)))?;
+                    if res.times.last().is_some_and(|t| *t >= time) {
+                        return Err(err(line, format!("timestamp {} out of order", time)));
+                    }
+                    res.times.push(time);
+                },
+                _ => {
+                    let (value, code) = match tok.as_bytes()[0] {
+                        b'b' | b'B' | b'r' | b'R' => {
+                            let (_, code) = tokens.next()
+                                .ok_or_else(|| err(line, format!("missing code for '{}'", tok)))?;
+                            // NOTE: Real values are treated as undefined
+                            let value = match tok.as_bytes()[0] {
+                                b'b' | b'B' => Self::parse_bits(&tok[1..])
+                                    .map_err(|_| err(line, format!("bad value '{}'", tok)))?,
+                                _ => None,
+                            };
+                            (value, code)
+                        },
+                        _ => {
+                            let (value, code) = tok.split_at(
+                                tok.chars().next().unwrap().len_utf8());
+                            let value = Self::parse_bits(value)
+                                .map_err(|_| err(line, format!("bad value '{}'", tok)))?;
+                            (value, code)
+                        },
+                    };
+                    let id = *codes.get(code)
+                        .ok_or_else(|| err(line, format!("unknown code '{}'", code)))?;
+                    let changes = &mut res.changes[id];
+                    // Only the last change at each timestamp matters
+                    if changes.last().is_some_and(|(t, _)| *t == time) {
+                        changes.pop();
+                    }
+                    changes.push((time, value));
+                },
+            }
+        }
+        Ok(res)
+    }
+
+    /// Parse a binary value, where any 'x' or 'z' makes the value
+    /// undefined.
+    fn parse_bits(bits: &str) -> Result<Option<u64>, ()> {
+        let mut res = 0u64;
+        let mut defined = true;
+        for c in bits.chars() {
+            match c {
+                '0' | '1' => res = (res << 1) | (c == '1') as u64,
+                'x' | 'X' | 'z' | 'Z' => defined = false,
+                _ => return Err(()),
+            }
+        }
+        Ok(defined.then_some(res))
+    }
+
+    pub fn timescale(&self) -> Option<&str> { self.timescale.as_deref() }
+    pub fn signals(&self) -> &[VcdSignal] { &self.signals }
+    /// Returns every timestamp in the file.
+    pub fn times(&self) -> &[u64] { &self.times }
+
+    /// Find the signal at `path`.
+    pub fn find(&self, path: &str) -> Option<&VcdSignal> {
+        self.signals.iter().find(|s| s.path == path)
+    }
+
+    /// Returns the value of a signal at `time` (after any changes at that
+    /// time), or [None] if it's undefined.
+    pub fn value_at(&self, sig: &VcdSignal, time: u64) -> Option<u64> {
+        let changes = &self.changes[sig.id];
+        let n = changes.partition_point(|(t, _)| *t <= time);
+        changes[..n].last().and_then(|(_, v)| *v)
+    }
+
+    /// Returns the value of a signal just before `time` (ignoring any
+    /// changes at that time).
+    pub fn value_before(&self, sig: &VcdSignal, time: u64) -> Option<u64> {
+        let changes = &self.changes[sig.id];
+        let n = changes.partition_point(|(t, _)| *t < time);
+        changes[..n].last().and_then(|(_, v)| *v)
+    }
+
+    /// Returns the times where a (1-bit) signal changes from 0 to 1.
+    pub fn rising_edges(&self, sig: &VcdSignal) -> Vec<u64> {
+        let mut res = Vec::new();
+        let mut last = None;
+        for (t, v) in self.changes[sig.id].iter() {
+            let bit = v.map(|v| v & 1);
+            if last == Some(0) && bit == Some(1) {
+                res.push(*t);
+            }
+            last = bit;
+        }
+        res
+    }
+}
+
+/// A problem comparing two waveforms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompareError {
+    /// A mapped signal doesn't exist in the model (or RTL) waveform.
+    MissingSignal { path: String, rtl: bool },
+    /// A mapped signal has a different value in the model and the RTL.
+    Diverged(Box<Divergence>),
+}
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingSignal { path, rtl } => {
+                write!(f, "no signal '{}' in the {} waveform", path,
+                    if *rtl { "RTL" } else { "model" })
+            },
+            Self::Diverged(d) => write!(f, "{}", d),
+        }
+    }
+}
+impl std::error::Error for CompareError {}
+
+/// The model and RTL values of every mapped signal on one cycle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleValues {
+    pub cycle: usize,
+    /// Model and RTL values (in the order the signals were mapped)
+    pub values: Vec<(Option<u64>, Option<u64>)>,
+}
+
+/// The first cycle where the model and RTL disagree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Model cycle
+    pub cycle: usize,
+    /// Model path of the first mismatched signal
+    pub signal: String,
+    /// RTL path of the first mismatched signal
+    pub rtl_signal: String,
+    pub model: Option<u64>,
+    pub rtl: Option<u64>,
+    /// Model paths of every mapped signal
+    pub signals: Vec<String>,
+    /// Values of every mapped signal on the surrounding cycles
+    pub context: Vec<CycleValues>,
+}
+impl Divergence {
+    fn value(x: Option<u64>) -> String {
+        x.map_or("x".to_string(), |x| format!("{:x}", x))
+    }
+}
+/// Prints the first mismatch, then a table with the context (where
+/// mismatched values are given as `<model>!=<rtl>`, and values which the
+/// model doesn't care about as `-`).
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "'{}' (RTL '{}') diverged on cycle {}: model {}, RTL {}",
+            self.signal, self.rtl_signal, self.cycle,
+            Self::value(self.model), Self::value(self.rtl))?;
+        let rows: Vec<Vec<String>> = self.context.iter().map(|row| {
+            row.values.iter().map(|(m, r)| match (m, r) {
+                (None, _) => "-".to_string(),
+                (m, r) if m == r => Self::value(*m),
+                (m, r) => format!("{}!={}", Self::value(*m), Self::value(*r)),
+            }).collect()
+        }).collect();
+        let widths: Vec<usize> = self.signals.iter().enumerate().map(|(idx, s)| {
+            rows.iter().map(|r| r[idx].len()).chain([s.len()]).max().unwrap()
+        }).collect();
+
+        let line = |head: String, cells: &[&str]| {
+            let mut res = head;
+            for (c, w) in cells.iter().zip(widths.iter()) {
+                res.push_str(&format!("  {:<1$}", c, w));
+            }
+            res.trim_end().to_string()
+        };
+        let names: Vec<&str> = self.signals.iter().map(|s| s.as_str()).collect();
+        writeln!(f, "{}", line(format!("{:>7}", "cycle"), &names))?;
+        for (row, cells) in self.context.iter().zip(rows.iter()) {
+            let mark = if row.cycle == self.cycle { '>' } else { ' ' };
+            let cells: Vec<&str> = cells.iter().map(|c| c.as_str()).collect();
+            writeln!(f, "{}", line(format!("{}{:>6}", mark, row.cycle), &cells))?;
+        }
+        Ok(())
+    }
+}
+
+/// The values of a mapped signal on every cycle.
+struct Column {
+    model: Vec<Option<u64>>,
+    rtl: Vec<Option<u64>>,
+    /// Mask for the width of the model signal
+    mask: u64,
+}
+
+/// Compares signals in a model waveform against an RTL waveform.
+pub struct VcdComparator {
+    /// Pairs of model and RTL paths
+    signals: Vec<(String, String)>,
+    clock: Option<String>,
+    offset: usize,
+    window: Option<Range<usize>>,
+    context: usize,
+}
+impl VcdComparator {
+    pub fn new() -> Self {
+        Self {
+            signals: Vec::new(),
+            clock: None,
+            offset: 0,
+            window: None,
+            context: 2,
+        }
+    }
+    /// Compare the model signal at `model` with the RTL signal at `rtl`.
+    pub fn with_signal(mut self, model: &str, rtl: &str) -> Self {
+        self.signals.push((model.to_string(), rtl.to_string()));
+        self
+    }
+    /// Sample the RTL on each rising edge of the clock at `path` (ie.
+    /// `TOP.clock`). Without a clock, each timestamp in the RTL waveform
+    /// is a cycle.
+    pub fn with_clock(mut self, path: &str) -> Self {
+        self.clock = Some(path.to_string());
+        self
+    }
+    /// Skip the first `n` RTL cycles (ie. while reset is asserted), so
+    /// that model cycle 0 is RTL cycle `n`.
+    pub fn with_offset(mut self, n: usize) -> Self {
+        self.offset = n;
+        self
+    }
+    /// Only compare the model cycles in `window`.
+    pub fn with_window(mut self, window: Range<usize>) -> Self {
+        self.window = Some(window);
+        self
+    }
+    /// Report `n` cycles before and after a divergence (the default is 2).
+    pub fn with_context(mut self, n: usize) -> Self {
+        self.context = n;
+        self
+    }
+
+    /// Returns the values of `sig` on each cycle.
+    fn samples(vcd: &Vcd, sig: &VcdSignal, edges: Option<&[u64]>) -> Vec<Option<u64>> {
+        match edges {
+            Some(edges) => edges.iter().map(|t| vcd.value_before(sig, *t)).collect(),
+            None => vcd.times().iter().map(|t| vcd.value_at(sig, *t)).collect(),
+        }
+    }
+
+    /// Compare the waveforms, returning the number of cycles compared.
+    ///
+    /// Values are truncated to the width of the model signal. When the
+    /// model value is undefined (ie. the contents of an invalid pipeline
+    /// register), the RTL value is ignored.
+    pub fn compare(&self, model: &Vcd, rtl: &Vcd) -> Result<usize, CompareError> {
+        let edges = match &self.clock {
+            Some(path) => {
+                let clk = rtl.find(path).ok_or_else(|| CompareError::MissingSignal {
+                    path: path.clone(), rtl: true
+                })?;
+                Some(rtl.rising_edges(clk))
+            },
+            None => None,
+        };
+
+        // Values of each signal on every cycle
+        let mut cols: Vec<Column> = Vec::new();
+        for (m, r) in self.signals.iter() {
+            let msig = model.find(m).ok_or_else(|| CompareError::MissingSignal {
+                path: m.clone(), rtl: false
+            })?;
+            let rsig = rtl.find(r).ok_or_else(|| CompareError::MissingSignal {
+                path: r.clone(), rtl: true
+            })?;
+            let mask = if msig.width >= 64 { u64::MAX } else { (1 << msig.width) - 1 };
+            cols.push(Column {
+                model: Self::samples(model, msig, None),
+                rtl: Self::samples(rtl, rsig, edges.as_deref())
+                    .into_iter().skip(self.offset).collect(),
+                mask,
+            });
+        }
+
+        let num_cycles = cols.iter().map(|c| c.model.len().min(c.rtl.len()))
+            .min().unwrap_or(0);
+        let window = self.window.clone().unwrap_or(0..num_cycles);
+        let cycles = window.start..window.end.min(num_cycles);
+        let value = |cycle: usize, idx: usize| {
+            let c = &cols[idx];
+            (c.model[cycle].map(|x| x & c.mask), c.rtl[cycle].map(|x| x & c.mask))
+        };
+
+        for cycle in cycles.clone() {
+            for idx in 0..cols.len() {
+                let (m, r) = value(cycle, idx);
+                if m.is_none() || m == r {
+                    continue;
+                }
+                let context = cycle.saturating_sub(self.context)
+                    ..(cycle + self.context + 1).min(num_cycles);
+                return Err(CompareError::Diverged(Box::new(Divergence {
+                    cycle,
+                    signal: self.signals[idx].0.clone(),
+                    rtl_signal: self.signals[idx].1.clone(),
+                    model: m,
+                    rtl: r,
+                    signals: self.signals.iter().map(|(m, _)| m.clone()).collect(),
+                    context: context.map(|cycle| CycleValues {
+                        cycle,
+                        values: (0..cols.len()).map(|idx| value(cycle, idx)).collect(),
+                    }).collect(),
+                })));
+            }
+        }
+        Ok(cycles.len())
+    }
+}
+impl Default for VcdComparator {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lle::*;
+    use crate::lle::register::*;
+    use crate::lle::trace::*;
+
+    /// A waveform like one from a Chisel test, with a clock period of 2
+    /// and reset asserted for the first cycle.
+    const RTL: &str = "\
+$date today $end
+$timescale 1ps $end
+$scope module TOP $end
+$var wire 1 ! clock $end
+$var wire 1 \" reset $end
+$scope module dut $end
+$var wire 1 ! clock $end
+$var wire 32 # pc [31:0] $end
+$var wire 1 $ valid $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+1\"
+bx #
+0$
+$end
+#1
+1!
+#2
+0!
+0\"
+b0 #
+1$
+#3
+1!
+b100 #
+#4
+0!
+#5
+1!
+b1000 #
+#6
+0!
+0$
+#7
+1!
+b1100 #
+#8
+0!
+1$
+#9
+1!
+b10000 #
+#10
+0!
+";
+
+    #[test]
+    fn vcd_parse() {
+        let vcd = Vcd::parse(RTL).unwrap();
+        assert_eq!(vcd.timescale(), Some("1ps"));
+        let paths: Vec<&str> = vcd.signals().iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, vec!["TOP.clock", "TOP.reset", "TOP.dut.clock",
+            "TOP.dut.pc", "TOP.dut.valid"]);
+
+        let pc = vcd.find("TOP.dut.pc").unwrap();
+        assert_eq!(pc.width, 32);
+        assert_eq!(vcd.value_at(pc, 0), None);
+        assert_eq!(vcd.value_at(pc, 3), Some(4));
+        assert_eq!(vcd.value_before(pc, 3), Some(0));
+
+        // Aliases share their value changes
+        let clk = vcd.find("TOP.dut.clock").unwrap();
+        assert_eq!(vcd.rising_edges(clk), vec![1, 3, 5, 7, 9]);
+    }
+
+    #[test]
+    fn vcd_parse_errors() {
+        let err = Vcd::parse("$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1?\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 4: unknown code '?'");
+        let err = Vcd::parse("$scope module top\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: missing $end for $scope");
+        let err = Vcd::parse("$enddefinitions $end\n#2\n#1\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 3: timestamp 1 out of order");
+    }
+
+    /// Simulate a model of the RTL above, where the PC skips an address
+    /// on cycle `bug`.
+    fn model(bug: usize) -> Vcd {
+        let mut state = ClockedState::new();
+        let r_pc = Rc::new(RefCell::new(Reg::new(0u32)));
+        let r_valid = Rc::new(RefCell::new(Reg::new(true)));
+        state.track_named("r_pc", &r_pc);
+        state.track_named("r_valid", &r_valid);
+        let mut vcd = VcdWriter::new(Vec::new());
+        for cyc in 0..4 {
+            vcd.sample(cyc, "top", &state).unwrap();
+            let pc = r_pc.borrow().sample();
+            r_pc.borrow_mut().drive(pc + if cyc == bug { 8 } else { 4 });
+            r_valid.borrow_mut().drive(cyc != 1);
+            state.update();
+        }
+        Vcd::read(vcd.into_inner().as_slice()).unwrap()
+    }
+
+    fn comparator() -> VcdComparator {
+        VcdComparator::new()
+            .with_clock("TOP.clock")
+            .with_offset(1)
+            .with_signal("top.r_pc", "TOP.dut.pc")
+            .with_signal("top.r_valid", "TOP.dut.valid")
+    }
+
+    #[test]
+    fn vcd_compare_match() {
+        let rtl = Vcd::parse(RTL).unwrap();
+        assert_eq!(comparator().compare(&model(usize::MAX), &rtl), Ok(4));
+        // Without the offset, the first cycle is still in reset
+        let err = comparator().with_offset(0).compare(&model(usize::MAX), &rtl);
+        assert!(matches!(err, Err(CompareError::Diverged(d)) if d.cycle == 0));
+    }
+
+    #[test]
+    fn vcd_compare_diverged() {
+        let rtl = Vcd::parse(RTL).unwrap();
+        let Err(CompareError::Diverged(d)) = comparator().compare(&model(1), &rtl) else {
+            panic!("expected a divergence");
+        };
+        assert_eq!((d.cycle, d.signal.as_str()), (2, "top.r_pc"));
+        assert_eq!((d.model, d.rtl), (Some(0xc), Some(0x8)));
+        assert_eq!(d.context.iter().map(|c| c.cycle).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(d.to_string(), "\
+'top.r_pc' (RTL 'TOP.dut.pc') diverged on cycle 2: model c, RTL 8
+  cycle  top.r_pc  top.r_valid
+      0  0         1
+      1  4         1
+>     2  c!=8      0
+      3  10!=c     1
+");
+        // Only compare cycles before the divergence
+        assert_eq!(comparator().with_window(0..2).compare(&model(1), &rtl), Ok(2));
+    }
+
+    #[test]
+    fn vcd_compare_missing() {
+        let rtl = Vcd::parse(RTL).unwrap();
+        let err = comparator().with_signal("top.r_x", "TOP.dut.x")
+            .compare(&model(0), &rtl).unwrap_err();
+        assert_eq!(err.to_string(), "no signal 'top.r_x' in the model waveform");
+    }
+}
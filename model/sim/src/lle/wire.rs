@@ -1,6 +1,11 @@
 
+use std::cell::RefCell;
 use std::fmt::Debug;
+use std::panic::Location;
+
 use crate::lle::*;
+use crate::lle::drc::*;
+use crate::lle::trace::*;
 
 
 /// Representing the state of a simulated wire.
@@ -10,28 +15,59 @@ use crate::lle::*;
 ///
 /// Changes to the value of a wire are instantaneous.
 ///
+/// When design rule checking is enabled (see [crate::lle::drc]), sampling 
+/// a wire before it has been driven is reported (and returns the value from
+/// the previous cycle), and an undriven wire is reported at the clock edge
+/// instead of panicking. Inside a block run by an 
+/// [Evaluator](crate::lle::eval::Evaluator), a named wire must also be
+/// declared by the block (see [drc::BlockScope]).
+///
 pub struct Wire<D: Copy> {
     value: Option<D>,
+    /// The value from the previous cycle (for design rule checking).
+    last: Option<D>,
+    /// Name and creation site (for design rule checking).
+    info: SignalInfo,
+    /// Drivers and readers on this cycle (for design rule checking).
+    sites: RefCell<DriveSites>,
 }
 impl <D: Copy> Wire<D> {
+    #[track_caller]
     pub fn new() -> Self {
         Self { 
             value: None,
+            last: None,
+            info: SignalInfo::new(),
+            sites: RefCell::new(DriveSites::default()),
         }
     }
+    /// Name this wire (for design rule checking).
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.info.name = Some(name);
+        self
+    }
+    pub fn info(&self) -> &SignalInfo { &self.info }
+
+    #[track_caller]
     pub fn drive(&mut self, value: D) {
+        self.sites.get_mut().record_drive(Location::caller());
+        drc::check_drive(&self.info, Location::caller());
         self.value = Some(value);
     }
+    #[track_caller]
     pub fn sample(&self) -> D {
+        drc::check_read(&self.info, Location::caller());
         if let Some(value) = self.value {
-            value
-        } else {
-            panic!("Wire has no value");
+            return value;
         }
-    }
-
-    pub fn reset(&mut self) {
-        self.value = None;
+        if drc::enabled() {
+            self.sites.borrow_mut().record_read(Location::caller());
+            if let Some(value) = self.last {
+                return value;
+            }
+        }
+        panic!("Wire {} has no value (sampled at {})", self.info, 
+            Location::caller());
     }
 }
 
@@ -43,8 +79,27 @@ impl <D: Copy> Wire<D> {
 impl <D: Copy> Clocked for Wire<D> {
     fn update(&mut self) {
         if self.value.is_none() {
-            panic!("Unassigned wire");
+            if drc::enabled() {
+                drc::report(ViolationKind::Undriven, &self.info, Vec::new());
+            } else {
+                panic!("Unassigned wire {}", self.info);
+            }
         }
+        self.sites.get_mut().check(&self.info);
+        self.last = self.value.take();
+    }
+}
+impl <D: Copy> Resettable for Wire<D> {
+    fn reset(&mut self) {
         self.value = None;
+        self.last = None;
+        *self.sites.get_mut() = DriveSites::default();
+    }
+}
+
+/// An undriven wire is traced as an undefined value.
+impl <D: Copy + TraceValue> Trace for Wire<D> {
+    fn trace(&self, path: &str, t: &mut dyn Tracer) {
+        t.signal(path, D::WIDTH, self.value.and_then(|v| v.trace_bits()));
     }
 }
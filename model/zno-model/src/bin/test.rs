@@ -13,6 +13,8 @@ use std::rc::Rc;
 use std::cell::*;
 use std::any::*;
 
+use sim_derive::Clocked;
+
 use zno_model::sim::*;
 use zno_model::common::*;
 use zno_model::soc::mem::*;
@@ -49,42 +51,64 @@ pub struct Block {
     exit: ExitKind,
 }
 
+/// State elements in the pipeline.
+#[derive(Clocked)]
+#[clocked(path = "zno_model::sim::Clocked")]
+pub struct Pipeline {
+    cfe_s0: Reg<Option<ControlFlowEvent>>,
+
+    /// Fetch target queue
+    ftq: Decoupled<usize>,
+    /// Fetch block queue
+    fbq: Decoupled<FetchBlock>,
+    /// Predecode block queue
+    pdq: Decoupled<PredecodeBlock>,
+    /// Decode block queue
+    dbq: Decoupled<DecodeBlock>,
+    /// Renamed block queue
+    rbq: Decoupled<DecodeBlock>,
+
+    frl: Freelist<256>,
+    prf: PhysicalRegisterFile<256>,
+    map: RegisterMap,
+
+    srob: SimpleReorderBuffer<64>,
+    sch: IntScheduler<24>,
+
+    /// Control-flow map
+    cfm: BoundedCam<usize, CfmEntry, 64, 1>,
+
+    /// Control-flow map stage registers
+    cfm_pdblk_s1: PipeReg<PredecodeBlock>,
+    cfm_rp0_s1: PipeReg<(usize, Option<CfmEntry>)>,
+
+    cfeq: CircularQueue<Block, 8>,
+}
+
 fn main() {
     const RAM_SIZE: usize = 0x0200_0000;
     let mut ram = Ram::new(RAM_SIZE);
     let entry = read_prog(&mut ram, "programs/test.elf");
 
-    let mut cfe_s0 = Reg::<Option<ControlFlowEvent>>::new(Some(
-        ControlFlowEvent { spec: false, redirect: true, npc: entry }
-    ));
-
-    // Fetch target queue
-    let mut ftq: Queue<usize>          = Queue::new();
-    // Fetch block queue
-    let mut fbq: Queue<FetchBlock>     = Queue::new();
-    // Predecode block queue
-    let mut pdq: Queue<PredecodeBlock> = Queue::new();
-    // Decode block queue
-    let mut dbq: Queue<DecodeBlock>    = Queue::new();
-    // Renamed block queue
-    let mut rbq: Queue<DecodeBlock>    = Queue::new();
-
-    let mut frl: Freelist<256> = Freelist::new();
-    let mut prf: PhysicalRegisterFile<256> = PhysicalRegisterFile::new();
-    let mut map = RegisterMap::new();
-
-    let mut srob: SimpleReorderBuffer<64> = SimpleReorderBuffer::new();
-    let mut sch: IntScheduler<24> = IntScheduler::new();
-
-    // Control-flow map
-    let mut cfm: AsyncReadCam<usize, CfmEntry> = AsyncReadCam::new();
-
-    // Control-flow map stage registers
-    let mut cfm_pdblk_s1 = Reg::<Option<PredecodeBlock>>::new(None);
-    let mut cfm_rp0_s1   = Reg::<Option<(usize, Option<CfmEntry>)>>::new(None);
-
-
-    let mut cfeq = CircularQueue::<Block, 8>::new();
+    let mut p = Pipeline {
+        cfe_s0: Reg::new(Some(
+            ControlFlowEvent { spec: false, redirect: true, npc: entry }
+        )),
+        ftq: Decoupled::new(4),
+        fbq: Decoupled::new(2),
+        pdq: Decoupled::new(2),
+        dbq: Decoupled::new(2),
+        rbq: Decoupled::new(2),
+        frl: Freelist::new().with_allocated(0),
+        prf: PhysicalRegisterFile::new(),
+        map: RegisterMap::new(),
+        srob: SimpleReorderBuffer::new(),
+        sch: IntScheduler::new(),
+        cfm: BoundedCam::new(),
+        cfm_pdblk_s1: PipeReg::new(),
+        cfm_rp0_s1: PipeReg::new(),
+        cfeq: CircularQueue::new(),
+    };
 
     for cyc in 0..8 {
         println!("============== cycle {} ================", cyc);
@@ -93,20 +117,24 @@ fn main() {
         // Control-flow events.
         // This controls the program counter sent to the fetch unit. 
 
-        cfe_s0.drive(None);
-
         // Handle a control-flow event.
-        if let Some(cfe) = cfe_s0.sample() {
-
-            // Queue up this address for fetch
-            println!("[CFE] Sending pc={:08x} to FTQ", cfe.npc);
-            ftq.enq(cfe.npc);
+        if let Some(cfe) = p.cfe_s0.sample() {
+
+            // Queue up this address for fetch.
+            // When the FTQ is full, the event is held until the next cycle.
+            if !p.ftq.enq_ready() {
+                println!("[CFE] FTQ is full, holding pc={:08x}", cfe.npc);
+            } else {
+                println!("[CFE] Sending pc={:08x} to FTQ", cfe.npc);
+                p.ftq.drive_enq(cfe.npc);
+                p.cfe_s0.drive(None);
+            }
 
 
             // The "control-flow map" has asynchronous read ports. 
             // We use this to determine if we can *predict* a control-flow 
             // event. 
-            if let Some(cfm_entry) = cfm.sample_rp(cfe.npc) {
+            if let Some(cfm_entry) = p.cfm.sample_rp(cfe.npc) {
                 println!("[CFM] CFM hit unimplemented");
             } 
             else {
@@ -121,7 +149,10 @@ fn main() {
         // Take the pending fetch address and fetch the appropriate block.
         // Pop the fetch address and push a new fetch block.
         // FIXME: Fetch is instantaneous, there are no caches.
-        if let Some(npc) = ftq.front() {
+        if !p.fbq.enq_ready() {
+            println!("[IFU] FBQ is full");
+        }
+        else if let Some(npc) = p.ftq.deq() {
             let fetch_addr = npc & !(0x1f);
             let idx = (npc & 0x1f) >> 2;
             let mut fblk = FetchBlock { 
@@ -131,8 +162,8 @@ fn main() {
             };
             ram.read_bytes(fetch_addr, &mut fblk.data);
             println!("[IFU] Fetched {:08x}", fblk.addr);
-            fbq.enq(fblk);
-            ftq.set_deq();
+            p.fbq.drive_enq(fblk);
+            p.ftq.drive_deq_ready(true);
         } else {
             println!("[IFU] FTQ is empty");
         }
@@ -143,14 +174,17 @@ fn main() {
 
         // Take the pending fetch block and pre-decode it.
         // Pop the fetch block and push a new predecoded block.
-        if let Some(fblk) = fbq.front() {
+        if !p.pdq.enq_ready() {
+            println!("[PDU] PDQ is full");
+        }
+        else if let Some(fblk) = p.fbq.deq() {
             let words = fblk.as_words();
             let mut pdblk = fblk.predecode();
             println!("[PDU] Predecoded {:08x}", pdblk.addr);
 
             println!("[PDU] Found {:08x?}", pdblk.get_exit());
-            pdq.enq(pdblk);
-            fbq.set_deq();
+            p.pdq.drive_enq(pdblk);
+            p.fbq.drive_deq_ready(true);
         } 
         else {
             println!("[PDU] FBQ is empty");
@@ -162,22 +196,25 @@ fn main() {
 
         // Take the pending pre-decoded block and decode it. 
         // Pop the pre-decoded block and push a new decode block.
-        if let Some(pdblk) = pdq.front() {
+        if !p.dbq.enq_ready() {
+            println!("[IDU] DBQ is full");
+        }
+        else if let Some(pdblk) = p.pdq.deq() {
 
             let enc_arr = pdblk.as_words();
             let info_arr = pdblk.get_imm_info();
 
             println!("[IDU] Decoding {:08x}", pdblk.addr);
             let mut dblk = DecodeBlock {
-                start: pdblk.start,
-                addr: pdblk.addr,
+                start: UInt::new(pdblk.start as u64),
+                addr: UInt::new(pdblk.addr as u64),
                 exit: pdblk.get_exit(),
                 data: MacroOp::decode_arr(&enc_arr, &info_arr),
             };
 
             dblk.print();
-            dbq.enq(dblk);
-            pdq.set_deq();
+            p.dbq.drive_enq(dblk);
+            p.pdq.drive_deq_ready(true);
         }
 
         // ====================================================================
@@ -185,18 +222,18 @@ fn main() {
         //
         // 1. Register Rename
 
-        map.print();
-        rename_stage(&mut dbq, &mut map, &mut frl, &mut rbq);
+        p.map.print();
+        rename_stage(&mut p.dbq, &mut p.map, &mut p.frl, &mut p.rbq);
 
         // ====================================================================
         // Stage 4
         //
         // 1. Dispatch
 
-        if let Some(rblk) = rbq.front() {
+        if let Some(rblk) = p.rbq.deq() {
             println!("[DIS] Dispatching {:08x}", rblk.addr);
 
-            let rob_idx = srob.drive_alloc(&rblk).unwrap();
+            let rob_idx = p.srob.drive_alloc(rblk).unwrap();
             println!("[DIS] Allocated ROB index {}", rob_idx);
             for (idx, mop) in rblk.iter_seq() {
 
@@ -210,7 +247,7 @@ fn main() {
                 println!("[DIS] {}: {:?} {}", idx, mop.kind, mop);
             }
 
-            rbq.set_deq();
+            p.rbq.drive_deq_ready(true);
         } else {
             println!("[DIS] RBQ is empty");
         }
@@ -223,25 +260,10 @@ fn main() {
         // (a) some representation of combinational logic, or (b) staging
         // changes to stateful elements. 
         //
-        // FIXME: You have to remember to update whatever state we declare.
-        // This is not ideal - how can we make this easier to write? 
-
-        ftq.update();
-        fbq.update();
-        dbq.update();
-        rbq.update();
-        pdq.update();
-        cfm.update();
-        cfm_rp0_s1.update();
-        cfm_pdblk_s1.update();
-        cfe_s0.update();
-        cfeq.update();
-        sch.update();
-        srob.update();
-
-        frl.update();
-        map.update();
+        // All of the state is declared in [Pipeline], so everything is
+        // updated here together.
 
+        p.update();
     }
 }
 
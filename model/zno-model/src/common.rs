@@ -6,6 +6,11 @@ pub mod cam;
 pub use crate::sim::state::*;
 pub use cam::*;
 pub use queue::*;
+pub use ::sim::lle::bits::*;
+pub use ::sim::lle::alloc::*;
+pub use ::sim::lle::repl::*;
+pub use ::sim::lle::cost::*;
+pub use ::sim::lle::pipereg::*;
 
 use std::collections::*;
 
@@ -70,7 +75,9 @@ impl <D: Copy + Default, const SZ: usize> Mem<D, SZ> {
     pub fn drive(&mut self, idx: usize, val: D) { 
         self.data[idx].drive(val);
     }
-    pub fn update(&mut self) {
+}
+impl <D: Copy + Default, const SZ: usize> Clocked for Mem<D, SZ> {
+    fn update(&mut self) {
         for r in self.data.iter_mut() {
             r.update();
         }
@@ -115,8 +122,11 @@ SyncRegisterFile<D, SZ, NUM_RP, NUM_WP>
     pub fn sample_rp(&self, port: usize) -> Option<D> {
         self.rp[port].sample()
     }
-
-    pub fn update(&mut self) {
+}
+impl <D: Copy, const SZ: usize, const NUM_RP: usize, const NUM_WP: usize>
+Clocked for SyncRegisterFile<D, SZ, NUM_RP, NUM_WP> 
+{
+    fn update(&mut self) {
         for rp in self.rp.iter_mut() {
             if let Some(idx) = rp.idx.take() {
                 rp.update(self.data[idx]);
@@ -1,6 +1,14 @@
 
 use std::collections::*;
 
+use crate::sim::Clocked;
+
+/// Bounded CAMs (see [::sim::lle::cam]).
+pub use ::sim::lle::cam::{
+    BoundedCam, CamWriteCmd, CamStats, CamReplacement,
+    FifoReplacement,
+};
+
 pub struct AsyncReadCam<K: Ord + Copy, V: Copy> {
     pub wp_pending: Vec<(K, V)>,
     pub data: BTreeMap<K, V>,
@@ -21,8 +29,9 @@ impl <K: Ord + Copy, V: Copy> AsyncReadCam<K, V> {
     pub fn drive_wp(&mut self, key: K, value: V) {
         self.wp_pending.push((key, value));
     }
-
-    pub fn update(&mut self) {
+}
+impl <K: Ord + Copy, V: Copy> Clocked for AsyncReadCam<K, V> {
+    fn update(&mut self) {
         while let Some((k,v)) = self.wp_pending.pop() {
             self.data.insert(k, v);
         }
@@ -81,14 +90,6 @@ SyncReadCam<K, V, NUM_RP, NUM_WP>
         self.rp_val[port]
     }
 
-    pub fn update(&mut self) {
-        if let Some(update_fn) = self.update_fn {
-            (update_fn)(self);
-        } else {
-            self.default_update();
-        }
-    }
-
     // Default update strategy.
     fn default_update(&mut self) {
         for idx in 0..NUM_RP {
@@ -113,5 +114,16 @@ SyncReadCam<K, V, NUM_RP, NUM_WP>
         }
     }
 }
+impl <K: Ord + Copy, V: Copy, const NUM_RP: usize, const NUM_WP: usize> 
+Clocked for SyncReadCam<K, V, NUM_RP, NUM_WP> 
+{
+    fn update(&mut self) {
+        if let Some(update_fn) = self.update_fn {
+            (update_fn)(self);
+        } else {
+            self.default_update();
+        }
+    }
+}
 
 
@@ -5,166 +5,275 @@ use std::ops::{ Index, IndexMut };
 
 use crate::sim::*;
 
+/// A group of up to `SIZE` entries moving through the pipeline together.
+///
+/// Valid entries are always packed at the start of the packet.
+#[derive(Clone, Copy, Debug)]
+pub struct Packet<T, const SIZE: usize>
+    where T: Copy + Default + Debug
+{
+    data: [Option<T>; SIZE]
+}
+impl <T, const SIZE: usize> Packet<T, SIZE>
+    where T: Copy + Default + Debug
+{
+    /// Create a new empty [Packet].
+    pub fn new() -> Self {
+        Self { data: [None; SIZE] }
+    }
 
+    /// Create a new [Packet] from a slice (with at most `SIZE` entries).
+    pub fn from_slice(src: &[T]) -> Self {
+        assert!(src.len() <= SIZE);
+        let mut res = Self::new();
+        for (dst, x) in res.data.iter_mut().zip(src.iter()) {
+            *dst = Some(*x);
+        }
+        res
+    }
 
-//#[derive(Debug)]
-//pub struct Packet<T, const SIZE: usize> 
-//    where T: Copy + Default + Debug
-//{
-//    data: [Option<T>; SIZE]
-//}
-//impl <T, const SIZE: usize> Packet<T, SIZE> 
-//    where T: Copy + Default + Debug
-//{
-//    /// Create a new empty [Packet].
-//    pub fn new() -> Self { 
-//        Self { data: [None; SIZE] }
-//    }
-//
-//    /// Return the maximum width (number of entries) for this packet.
-//    pub fn capacity(&self) -> usize { 
-//        SIZE
-//    }
-//
-//    /// Returns true if this packet has no valid entries.
-//    pub fn is_empty(&self) -> bool { 
-//        self.data.iter().all(|x| x.is_none())
-//    }
-//
-//    /// Returns true if this packet is filled with valid entries.
-//    pub fn is_full(&self) -> bool { 
-//        self.data.iter().all(|x| x.is_some())
-//    }
-//
-//    /// Return the number of *valid* entries in this packet.
-//    pub fn valid_len(&self) -> usize { 
-//        self.data.iter().filter(|x| x.is_some()).count()
-//    }
-//
-//    /// Return an iterator over all *valid* entries in this packet.
-//    pub fn valid_iter(&self) -> impl Iterator<Item=&T> + '_ {
-//        self.data.iter().flat_map(|x| x)
-//    }
-//
-//    pub fn dump(&self, s: &'static str) {
-//        println!("| {}", s);
-//        for idx in 0..SIZE {
-//            println!("| [{:02}] {:x?}", idx, self.data[idx]);
-//        }
-//    }
-//}
-//
-//impl <T, const SIZE: usize> Index<usize> for Packet<T, SIZE> 
-//    where T: Copy + Default + Debug
-//{
-//    type Output = T;
-//    fn index(&self, idx: usize) -> &Self::Output {
-//        assert!(idx < SIZE);
-//        self.data[idx].as_ref().unwrap()
-//    }
-//}
-//
-//impl <T, const SIZE: usize> IndexMut<usize> for Packet<T, SIZE> 
-//    where T: Copy + Default + Debug
-//{
-//    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
-//        if self.data[idx].is_none() {
-//            self.data[idx].insert(T::default())
-//        } else { 
-//            self.data[idx].as_mut().unwrap()
-//        }
-//    }
-//}
-//
-//impl <T, const SIZE: usize> Default for Packet<T, SIZE> 
-//    where T: Copy + Default + Debug
-//{
-//    fn default() -> Self { 
-//        Self { data: [None; SIZE] }
-//    }
-//}
-//
-//
-//pub struct PacketQueue<T, const CAP: usize, const PSIZE: usize>
-//    where T: Copy + Default + Debug
-//{
-//    name: String,
-//    data: VecDeque<T>,
-//    input: Option<Packet<T, PSIZE>>,
-//    take: Option<usize>,
-//}
-//
-//impl <T, const CAP: usize, const PSIZE: usize> PacketQueue<T, CAP, PSIZE> 
-//    where T: Copy + Default + Debug
-//{
-//    pub fn new(name: impl ToString) -> Self {
-//        Self { 
-//            name: name.to_string(),
-//            data: VecDeque::with_capacity(CAP),
-//            input: None,
-//            take: None,
-//        }
-//    }
-//
-//    /// Return a [Packet] with the oldest entries in the queue.
-//    ///
-//    /// The maximum number of entries in the output is given by `PSIZE`.
-//    pub fn output(&self) -> Packet<T, PSIZE> {
-//        let mut res = Packet::new();
-//        if self.data.len() >= PSIZE { 
-//            for idx in 0..PSIZE {
-//                res[idx] = *self.data.get(idx).unwrap();
-//            }
-//        } else {
-//            for idx in 0..self.data.len() {
-//                res[idx] = *self.data.get(idx).unwrap();
-//            }
-//        }
-//        res
-//    }
-//
-//    /// Producer interface: submit a packet to the queue, to-be-latched on 
-//    /// the next clock edge.
-//    pub fn push(&mut self, input: Packet<T, PSIZE>) {
-//        self.input = Some(input);
-//    }
-//
-//    /// Consumer interface: indicate how many entries have been consumed
-//    /// from the output for this cycle. Consumed entries are dequeued on
-//    /// the next clock edge. 
-//    pub fn consume(&mut self, take: usize) {
-//        assert!(take < PSIZE);
-//        assert!(take < self.data.len());
-//        self.take = Some(take);
-//    }
-//}
-//
-//impl <T, const CAP: usize, const PSIZE: usize> 
-//    Clocked for PacketQueue<T, CAP, PSIZE> 
-//    where T: Copy + Default + Debug
-//{
-//    fn name(&self) -> &str { &self.name }
-//    fn update(&mut self) {
-//        // Consume entries from the queue
-//        if let Some(take) = self.take {
-//            for _ in 0..take {
-//                self.data.pop_front().unwrap();
-//            }
-//            self.take = None;
-//        }
-//        // Push new entries onto the queue
-//        if let Some(input) = &self.input {  
-//            assert!(input.valid_len() + self.data.len() <= CAP);
-//            for idx in 0..input.valid_len() { 
-//                self.data.push_back(input[idx]);
-//            }
-//            self.input = None;
-//        } else { 
-//            unreachable!("PacketQueue {} had no input driven this cycle?",
-//                self.name);
-//        }
-//        self.data.make_contiguous();
-//    }
-//}
+    /// Return the maximum width (number of entries) for this packet.
+    pub fn capacity(&self) -> usize {
+        SIZE
+    }
 
+    /// Returns true if this packet has no valid entries.
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|x| x.is_none())
+    }
 
+    /// Returns true if this packet is filled with valid entries.
+    pub fn is_full(&self) -> bool {
+        self.data.iter().all(|x| x.is_some())
+    }
+
+    /// Return the number of *valid* entries in this packet.
+    pub fn valid_len(&self) -> usize {
+        self.data.iter().filter(|x| x.is_some()).count()
+    }
+
+    /// Return an iterator over all *valid* entries in this packet.
+    pub fn valid_iter(&self) -> impl Iterator<Item=&T> + '_ {
+        self.data.iter().flatten()
+    }
+
+    /// Add an entry to the end of this packet.
+    pub fn push(&mut self, x: T) {
+        let len = self.valid_len();
+        assert!(len < SIZE, "Packet is full");
+        self.data[len] = Some(x);
+    }
+
+    pub fn dump(&self, s: &'static str) {
+        println!("| {}", s);
+        for idx in 0..SIZE {
+            println!("| [{:02}] {:x?}", idx, self.data[idx]);
+        }
+    }
+}
+
+impl <T, const SIZE: usize> Index<usize> for Packet<T, SIZE>
+    where T: Copy + Default + Debug
+{
+    type Output = T;
+    fn index(&self, idx: usize) -> &Self::Output {
+        assert!(idx < SIZE);
+        self.data[idx].as_ref().unwrap()
+    }
+}
+
+/// NOTE: Writing to an invalid entry makes it valid, so entries must be
+/// written in order (see [Packet::push]).
+impl <T, const SIZE: usize> IndexMut<usize> for Packet<T, SIZE>
+    where T: Copy + Default + Debug
+{
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        assert!(idx <= self.valid_len(), "Packet entries must be contiguous");
+        self.data[idx].get_or_insert(T::default())
+    }
+}
+
+impl <T, const SIZE: usize> Default for Packet<T, SIZE>
+    where T: Copy + Default + Debug
+{
+    fn default() -> Self {
+        Self { data: [None; SIZE] }
+    }
+}
+
+
+/// A first-in first-out queue with `CAP` entries and multiple "lanes",
+/// where up to `PSIZE` entries can be enqueued/dequeued on each cycle.
+///
+/// This mirrors `DecouplingFIFO` in `src/main/scala/common/packet.scala`:
+///
+/// - The producer samples [PacketQueue::lim] (the number of entries that
+///   can be enqueued this cycle) and drives a [Packet] with [PacketQueue::push]
+/// - The consumer samples a [Packet] with the oldest entries from
+///   [PacketQueue::output], and drives the number of entries it consumed
+///   with [PacketQueue::consume]
+///
+/// Both take effect at the next clock edge.
+///
+/// NOTE: The hardware ignores a packet that doesn't fit, but we assume this
+/// is a bug in the producer (and panic).
+pub struct PacketQueue<T, const CAP: usize, const PSIZE: usize>
+    where T: Copy + Default + Debug
+{
+    data: VecDeque<T>,
+    input: Option<Packet<T, PSIZE>>,
+    take: Option<usize>,
+}
+
+impl <T, const CAP: usize, const PSIZE: usize> PacketQueue<T, CAP, PSIZE>
+    where T: Copy + Default + Debug
+{
+    pub fn new() -> Self {
+        assert!(PSIZE <= CAP);
+        Self {
+            data: VecDeque::with_capacity(CAP),
+            input: None,
+            take: None,
+        }
+    }
+
+    /// Producer interface: the maximum number of entries that can be
+    /// enqueued on this cycle.
+    pub fn lim(&self) -> usize {
+        self.num_in()
+    }
+
+    /// Producer interface: submit a packet to the queue, to-be-latched on
+    /// the next clock edge.
+    pub fn push(&mut self, input: Packet<T, PSIZE>) {
+        assert!(input.valid_len() <= self.lim(),
+            "PacketQueue overflow ({} entries, limit {})",
+            input.valid_len(), self.lim());
+        self.input = Some(input);
+    }
+
+    /// Consumer interface: the number of valid entries in the output.
+    pub fn deq_len(&self) -> usize {
+        self.data.len().min(PSIZE)
+    }
+
+    /// Return a [Packet] with the oldest entries in the queue.
+    ///
+    /// The maximum number of entries in the output is given by `PSIZE`.
+    pub fn output(&self) -> Packet<T, PSIZE> {
+        let mut res = Packet::new();
+        for x in self.data.iter().take(PSIZE) {
+            res.push(*x);
+        }
+        res
+    }
+
+    /// Consumer interface: indicate how many entries have been consumed
+    /// from the output for this cycle. Consumed entries are dequeued on
+    /// the next clock edge.
+    pub fn consume(&mut self, take: usize) {
+        assert!(take <= self.deq_len(),
+            "PacketQueue underflow ({} entries, {} available)",
+            take, self.deq_len());
+        self.take = Some(take);
+    }
+}
+impl <T, const CAP: usize, const PSIZE: usize> Default 
+    for PacketQueue<T, CAP, PSIZE>
+    where T: Copy + Default + Debug
+{
+    fn default() -> Self { Self::new() }
+}
+
+impl <T, const CAP: usize, const PSIZE: usize>
+    Clocked for PacketQueue<T, CAP, PSIZE>
+    where T: Copy + Default + Debug
+{
+    fn update(&mut self) {
+        // Consume entries from the queue
+        if let Some(take) = self.take.take() {
+            self.data.drain(..take);
+        }
+        // Push new entries onto the queue
+        if let Some(input) = self.input.take() {
+            self.data.extend(input.valid_iter());
+        }
+        assert!(self.data.len() <= CAP);
+    }
+}
+
+/// NOTE: Capacity and limits are computed from the number of entries at
+/// the start of the cycle (entries consumed on this cycle cannot be reused
+/// until the next cycle).
+impl <T, const CAP: usize, const PSIZE: usize>
+    Storage<CAP, PSIZE> for PacketQueue<T, CAP, PSIZE>
+    where T: Copy + Default + Debug
+{
+    fn num_used(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packet_push() {
+        let mut p = Packet::<u32, 4>::new();
+        assert!(p.is_empty());
+        p.push(1);
+        p[1] = 2;
+        assert_eq!(p.valid_len(), 2);
+        assert_eq!(p.valid_iter().copied().collect::<Vec<_>>(), vec![1, 2]);
+        assert!(!p.is_full());
+        assert_eq!(Packet::<u32, 2>::from_slice(&[1, 2]).valid_len(), 2);
+    }
+
+    #[test]
+    fn packet_queue_partial() {
+        let mut q = PacketQueue::<u32, 6, 4>::new();
+        assert_eq!(q.lim(), 4);
+        q.push(Packet::from_slice(&[0, 1, 2, 3]));
+        q.update();
+        assert_eq!(q.num_used(), 4);
+        assert_eq!(q.lim(), 2);
+
+        // Consume part of the output while refilling
+        assert_eq!(q.deq_len(), 4);
+        q.consume(3);
+        q.push(Packet::from_slice(&[4, 5]));
+        q.update();
+        assert!(!q.is_full());
+        let out = q.output();
+        assert_eq!(out.valid_iter().copied().collect::<Vec<_>>(), vec![3, 4, 5]);
+
+        q.consume(3);
+        q.update();
+        assert!(q.is_empty());
+        assert!(q.output().is_empty());
+    }
+
+    #[test]
+    fn packet_queue_storage() {
+        let mut q = PacketQueue::<u32, 4, 3>::new();
+        assert_eq!(q.capacity(), 4);
+        q.push(Packet::from_slice(&[0, 1, 2]));
+        q.update();
+        assert_eq!(q.num_free(), 1);
+        assert_eq!(q.num_in(), 1);
+        q.push(Packet::from_slice(&[3]));
+        q.update();
+        assert!(q.is_full());
+        assert_eq!(q.lim(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow")]
+    fn packet_queue_overflow() {
+        let mut q = PacketQueue::<u32, 4, 4>::new();
+        q.push(Packet::from_slice(&[0, 1, 2]));
+        q.update();
+        q.push(Packet::from_slice(&[3, 4]));
+    }
+}
@@ -1,9 +1,17 @@
 
 use std::collections::*;
 
+use crate::sim::Clocked;
+
+/// Bounded channels (see [::sim::lle::decoupled]).
+pub use ::sim::lle::decoupled::*;
+/// Circular buffer pointers (see [::sim::lle::ring]).
+pub use ::sim::lle::ring::*;
+
 /// Simple queue implementation. 
 ///
 /// FIXME: This is a high-level version (and there's no bound on the size!).
+/// Use [Decoupled] for a bounded channel with backpressure.
 pub struct Queue<T> {
     pub next: Option<T>,
     pub deq_ok: bool,
@@ -34,9 +42,9 @@ impl <T> Queue<T> {
     pub fn front(&self) -> Option<&T> {
         self.data.front()
     }
-
-    /// Update the state of the queue. 
-    pub fn update(&mut self) {
+}
+impl <T> Clocked for Queue<T> {
+    fn update(&mut self) {
         // Add a new element being driven this cycle
         if let Some(next) = self.next.take() {
             self.data.push_back(next);
@@ -51,8 +59,8 @@ impl <T> Queue<T> {
 
 pub struct CircularQueue<T: Copy, const SZ: usize> {
     pub next: Option<T>,
-    pub enq_ptr: usize,
-    pub deq_ptr: usize,
+    pub enq_ptr: RingPtr<SZ>,
+    pub deq_ptr: RingPtr<SZ>,
     pub deq_ok: bool,
     pub data: [Option<T>; SZ],
 
@@ -64,8 +72,8 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
     pub fn new() -> Self {
         Self {
             next: None,
-            enq_ptr: 0,
-            deq_ptr: 0,
+            enq_ptr: RingPtr::new(),
+            deq_ptr: RingPtr::new(),
             deq_ok: false,
             data: [None; SZ],
 
@@ -94,10 +102,13 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
     }
 
     pub fn is_full(&self) -> bool {
-        self.enq_ptr == self.deq_ptr && self.data[self.enq_ptr].is_some()
+        RingPtr::is_full(self.enq_ptr, self.deq_ptr)
     }
     pub fn is_empty(&self) -> bool {
-        self.enq_ptr == self.deq_ptr && self.data[self.deq_ptr].is_none()
+        RingPtr::is_empty(self.enq_ptr, self.deq_ptr)
+    }
+    pub fn num_used(&self) -> usize {
+        RingPtr::num_used(self.enq_ptr, self.deq_ptr)
     }
 
     // Drive an entry to be enqueued on the following cycle. 
@@ -109,7 +120,7 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
             None
         } else {
             self.next = Some(value);
-            Some(self.enq_ptr)
+            Some(self.enq_ptr.idx())
         }
     }
 
@@ -118,26 +129,11 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
     }
 
     pub fn front(&self) -> Option<T> {
-        self.data[self.deq_ptr]
-    }
-
-    fn inc_deq_ptr(&mut self) {
-        if self.deq_ptr == SZ - 1 {
-            self.deq_ptr = 0;
-        } else {
-            self.deq_ptr += 1;
-        }
-    }
-    fn inc_enq_ptr(&mut self) {
-        if self.deq_ptr == SZ - 1 {
-            self.deq_ptr = 0;
-        } else {
-            self.deq_ptr += 1;
-        }
+        self.data[self.deq_ptr.idx()]
     }
-
-
-    pub fn update(&mut self) {
+}
+impl <T: Copy, const SZ: usize> Clocked for CircularQueue<T, SZ> {
+    fn update(&mut self) {
         // Each entry in 'wp_pending' corresponds to a write port being
         // driven on the current cycle. 
         while let Some((idx, value)) = self.wp_pending.pop() {
@@ -145,17 +141,20 @@ impl <T: Copy, const SZ: usize> CircularQueue<T, SZ> {
             self.data[idx] = Some(value);
         }
 
-        // FIXME: actually handle overflow conditions
-
+        // NOTE: [CircularQueue::enq] refuses to drive an entry when the 
+        // queue is full, even if the oldest entry is being dequeued.
         if self.deq_ok {
-            self.data[self.deq_ptr] = None;
+            assert!(!self.is_empty(), "CircularQueue underflow");
+            self.data[self.deq_ptr.idx()] = None;
             self.deq_ok = false;
-            self.inc_deq_ptr();
+            self.deq_ptr.inc();
         }
 
         if let Some(next) = self.next.take() {
-            self.data[self.enq_ptr] = Some(next);
-            self.inc_enq_ptr();
+            assert!(self.data[self.enq_ptr.idx()].is_none(), 
+                "CircularQueue overflow");
+            self.data[self.enq_ptr.idx()] = Some(next);
+            self.enq_ptr.inc();
         }
 
 
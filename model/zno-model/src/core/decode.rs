@@ -1,6 +1,7 @@
 
 use crate::riscv::rv32i::*;
 use crate::core::uarch::*;
+use crate::common::*;
 
 
 impl MacroOp {
@@ -57,11 +58,11 @@ impl MacroOp {
             Opcode::SYSTEM   => {
                 let f12 = (enc & Rv32::MASK_I_IMM12_20_31) >> 20;
                 match (f12, rs1, rd) { 
-                    (0b0000_0000_0000, ArchReg(0), ArchReg(0)) => {
-                        res.kind = MacroOpKind::Sys(SysOp::Ecall(f3));
+                    (0b0000_0000_0000, ArchReg::ZERO, ArchReg::ZERO) => {
+                        res.kind = MacroOpKind::Sys(SysOp::Ecall(UInt::new(f3 as u64)));
                     },
-                    (0b0000_0000_0001, ArchReg(0), ArchReg(0)) => {
-                        res.kind = MacroOpKind::Sys(SysOp::Ebreak(f3));
+                    (0b0000_0000_0001, ArchReg::ZERO, ArchReg::ZERO) => {
+                        res.kind = MacroOpKind::Sys(SysOp::Ebreak(UInt::new(f3 as u64)));
                     },
                     (_, _, _) => {
                         res.kind = MacroOpKind::Illegal;
@@ -75,8 +76,8 @@ impl MacroOp {
                 res.op2 = Operand::Imm;
             },
             Opcode::JALR     => {
-                let rd_lr = res.rd == ArchReg(1) || res.rd == ArchReg(5);
-                let rs1_lr = res.rs1 == ArchReg(1) || res.rs1 == ArchReg(5);
+                let rd_lr = res.rd == ArchReg::RA || res.rd == ArchReg::T0;
+                let rs1_lr = res.rs1 == ArchReg::RA || res.rs1 == ArchReg::T0;
 
                 res.kind = MacroOpKind::Jmp(JmpOp::JmpIndirect);
                 res.rr = true;
@@ -3,14 +3,19 @@ use crate::common::*;
 use crate::core::uarch::*;
 
 pub fn rename_stage(
-    dbq: &mut Queue<DecodeBlock>,
+    dbq: &mut Decoupled<DecodeBlock>,
     map: &mut RegisterMap,
     frl: &mut Freelist<256>,
-    rbq: &mut Queue<DecodeBlock>,
+    rbq: &mut Decoupled<DecodeBlock>,
 ) 
 {
     // Take the pending decode block and rename it. 
-    if let Some(dblk) = dbq.front() {
+    // Nothing moves until there's space in the RBQ.
+    if !rbq.enq_ready() {
+        println!("[RRN] RBQ is full");
+        return;
+    }
+    if let Some(dblk) = dbq.deq() {
         println!("[RRN] Renamed {:08x}", dblk.addr);
 
         let mut blk = dblk.clone();
@@ -48,17 +53,17 @@ pub fn rename_stage(
         let num_alcs = blk.num_preg_allocs();
         println!("[RRN] FRL has {} free entries, need {}", 
                  frl.num_free(), num_alcs);
-        let mut alcs = frl.sample_alcs(num_alcs).unwrap();
-        alcs.reverse();
+        let mut alcs = frl.sample_alcs().into_iter().take(num_alcs);
+        frl.drive_alc(num_alcs);
         for (idx, mut mop) in blk.iter_seq_mut() {
             if mop.has_rr_alc() {
-                let prn = alcs.pop().unwrap();
+                let prn = PhysReg::new(alcs.next().flatten().unwrap() as u64);
                 mop.pd = PhysRegDst::Allocated(prn);
                 map.drive_wp(mop.rd, prn);
             }
             //println!("  {} {}", idx, mop);
         }
-        assert!(alcs.is_empty());
+        assert!(alcs.next().is_none());
 
         // Rename with local dependences
         let ldeps = blk.calc_local_deps();
@@ -92,7 +97,7 @@ pub fn rename_stage(
             match mop.mov {
                 MovCtl::None => {},
                 MovCtl::Zero => {
-                    map.drive_wp(mop.rd, 0);
+                    map.drive_wp(mop.rd, PhysReg::ZERO);
                 },
                 MovCtl::Op1 => {
                     match mop.op1 {
@@ -121,8 +126,8 @@ pub fn rename_stage(
             println!("  {} {}", idx, mop);
         }
 
-        rbq.enq(blk);
-        dbq.set_deq();
+        rbq.drive_enq(blk);
+        dbq.drive_deq_ready(true);
     } else {
         println!("[RRN] DBQ is empty");
 
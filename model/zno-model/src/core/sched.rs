@@ -7,77 +7,31 @@ use std::collections::*;
 pub struct SimpleReorderBuffer<const SIZE: usize> {
     alloc_pending: Option<DecodeBlock>,
     data: [Option<DecodeBlock>; SIZE],
-    alloc_ptr: usize,
-    commit_ptr: usize,
+    alloc_ptr: RingPtr<SIZE>,
+    commit_ptr: RingPtr<SIZE>,
 }
 impl <const SIZE: usize> SimpleReorderBuffer<SIZE> {
     pub fn new() -> Self {
         Self {
             alloc_pending: None,
             data: [None; SIZE],
-            alloc_ptr: 0,
-            commit_ptr: 0,
-        }
-    }
-    pub fn alloc_ptr(&self) -> usize { self.alloc_ptr }
-    pub fn commit_ptr(&self) -> usize { self.commit_ptr }
-    fn inc_alloc(&mut self) {
-        if self.alloc_ptr == SIZE - 1 { 
-            self.alloc_ptr = 0; 
-        } else { 
-            self.alloc_ptr += 1; 
-        }
-    }
-    fn inc_commit(&mut self) {
-        if self.commit_ptr == SIZE - 1 { 
-            self.commit_ptr = 0; 
-        } else { 
-            self.commit_ptr += 1; 
+            alloc_ptr: RingPtr::new(),
+            commit_ptr: RingPtr::new(),
         }
     }
+    pub fn alloc_ptr(&self) -> usize { self.alloc_ptr.idx() }
+    pub fn commit_ptr(&self) -> usize { self.commit_ptr.idx() }
 
     fn num_free(&self) -> usize {
-        //    alc com
-        //     |   |
-        // X X F F X X X X
-        // 0 1 2 3 4 5 6 7
-        if self.commit_ptr > self.alloc_ptr {
-            return self.commit_ptr - self.alloc_ptr;
-        } 
-        //    com alc
-        //     |   |
-        // F F X X F F F F
-        // 0 1 2 3 4 5 6 7
-        else if self.commit_ptr < self.alloc_ptr {
-            return SIZE - (self.alloc_ptr - self.commit_ptr);
-        } 
-        else {
-            // X X X X X X X X
-            // 0 1 2 3 4 5 6 7
-            if self.full() {
-                return 0;
-            } 
-            // F F F F F F F F
-            // 0 1 2 3 4 5 6 7
-            else if self.empty() {
-                return SIZE;
-            } 
-            else {
-                unreachable!();
-            }
-        }
+        RingPtr::num_free(self.alloc_ptr, self.commit_ptr)
     }
 
     pub fn full(&self) -> bool {
-        self.alloc_ptr == self.commit_ptr &&
-        self.data[self.alloc_ptr].is_some() &&
-        self.data[self.commit_ptr].is_some()
+        RingPtr::is_full(self.alloc_ptr, self.commit_ptr)
     }
 
     pub fn empty(&self) -> bool {
-        self.alloc_ptr == self.commit_ptr &&
-        self.data[self.alloc_ptr].is_none() &&
-        self.data[self.commit_ptr].is_none()
+        RingPtr::is_empty(self.alloc_ptr, self.commit_ptr)
     }
 
 
@@ -88,7 +42,7 @@ impl <const SIZE: usize> SimpleReorderBuffer<SIZE> {
             Err("ROB full")
         } else {
             self.alloc_pending = Some(*dblk);
-            Ok(self.alloc_ptr)
+            Ok(self.alloc_ptr.idx())
         }
     }
 
@@ -96,14 +50,25 @@ impl <const SIZE: usize> SimpleReorderBuffer<SIZE> {
 impl <const SIZE: usize> Clocked for SimpleReorderBuffer<SIZE> {
     fn update(&mut self) {
         if let Some(dblk) = self.alloc_pending.take() {
-            assert!(self.data[self.alloc_ptr].is_none());
-            self.data[self.alloc_ptr] = Some(dblk);
-            self.inc_alloc();
+            assert!(self.data[self.alloc_ptr.idx()].is_none());
+            self.data[self.alloc_ptr.idx()] = Some(dblk);
+            self.alloc_ptr.inc();
         }
 
     }
 }
 
+/// NOTE: Blocks are allocated and committed in order, so there's a single
+/// read and write port.
+impl <const SIZE: usize> Cost for SimpleReorderBuffer<SIZE> {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        r.add(&format!("{}.data", path), StorageCost::new("ReorderBuffer",
+            SIZE, Option::<DecodeBlock>::BITS).with_ports(1, 1));
+        r.add(&format!("{}.ptrs", path),
+            StorageCost::new("Reg", 2, RingPtr::<SIZE>::BITS).with_ports(1, 1));
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum IntSchedulerStatus {
     None,
@@ -141,3 +106,30 @@ impl <const SIZE: usize> Clocked for IntScheduler<SIZE> {
     fn update(&mut self) {
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Rename state against the reorder buffer.
+    #[test]
+    fn rob_cost() {
+        #[derive(Cost)]
+        struct Rename {
+            frl: Freelist<256>,
+            map: RegisterMap,
+        }
+        let rename = Rename { frl: Freelist::new(), map: RegisterMap::new() };
+        let rob = SimpleReorderBuffer::<64>::new();
+
+        let mut r = CostReport::new();
+        rename.cost("rename", &mut r);
+        rob.cost("rob", &mut r);
+        assert_eq!(r.bits("rename.frl"), 256);
+        assert_eq!(r.bits("rename"), 256 + 32 * 9);
+        assert_eq!(MacroOp::BITS, 118);
+        assert_eq!(DecodeBlock::BITS, 3 + 6 + 32 + 8 * 118);
+        assert_eq!(r.bits("rob"), 64 * (DecodeBlock::BITS + 1) + 2 * 7);
+        assert!(r.bits("rob") > r.bits("rename"));
+    }
+}
@@ -3,7 +3,7 @@ use crate::riscv::rv32i::*;
 use crate::common::*;
 
 /// Immediate storage strategy. 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum ImmStorage { 
     /// Indicates the lack of an immediate value (nothing to store).
     None, 
@@ -19,8 +19,8 @@ impl Default for ImmStorage {
     fn default() -> Self { Self::None }
 }
 impl ImmStorage {
-    pub fn from_imm19(imm19: u32) -> Self {
-        if imm19 == 0 {
+    pub fn from_imm19(imm19: UInt<19>) -> Self {
+        if imm19 == UInt::ZERO {
             Self::Zero
         } else {
             Self::Alloc
@@ -29,7 +29,7 @@ impl ImmStorage {
 }
 
 /// Immediate control bits.
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, HwBits)]
 pub struct ImmCtl {
     /// Indicates how immediate data is stored in the pipeline.
     pub storage: ImmStorage,
@@ -84,7 +84,7 @@ impl FetchBlock {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, HwBits)]
 pub struct ImmediateInfo {
     pub ctl: ImmCtl,
     pub data: ImmData,
@@ -113,7 +113,7 @@ impl Default for PredecodeInfo {
             },
             imm_data: ImmData { 
                 sign: false, 
-                imm19: 0, 
+                imm19: UInt::ZERO, 
             },
             brn_kind: None,
             rs1: None,
@@ -147,18 +147,19 @@ pub enum CfmEntryKind {
     Invalid,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// The exit of a decode block (and the index of the exit instruction).
+#[derive(Clone, Copy, Debug, HwBits)]
 pub enum DecodeBlockExit {
     /// This block has no control-flow instructions
     Sequential,
     /// Expected fault/exception/trap at this index
-    Fault(usize),
+    Fault(UInt<3>),
     /// Expected unconditional jump at this index
-    Jmp(usize),
+    Jmp(UInt<3>),
     /// Expected procedure call at this index
-    Call(usize),
+    Call(UInt<3>),
     /// Expected procedure return at this index
-    Ret(usize),
+    Ret(UInt<3>),
     /// The end of this block must be resolved dynamically
     Dynamic,
 }
@@ -169,7 +170,7 @@ impl DecodeBlockExit {
             Self::Fault(idx) |
             Self::Jmp(idx) |
             Self::Call(idx) |
-            Self::Ret(idx) => *idx,
+            Self::Ret(idx) => idx.as_usize(),
         }
     }
 }
@@ -230,7 +231,7 @@ impl PredecodeBlock {
     /// Return the index of the terminal instruction in this block. 
     pub fn get_exit(&self) -> DecodeBlockExit {
         if let Some(idx) = self.first_illegal_inst() {
-            return DecodeBlockExit::Fault(idx);
+            return DecodeBlockExit::Fault(UInt::new(idx as u64));
         } 
 
         if self.is_sequential() {
@@ -238,6 +239,7 @@ impl PredecodeBlock {
         }
 
         if let Some((idx, info)) = self.first_cfi() {
+            let idx = UInt::new(idx as u64);
             match info.brn_kind.unwrap() {
                 BranchKind::Return => return DecodeBlockExit::Ret(idx),
                 BranchKind::CallIndirect => return DecodeBlockExit::Call(idx),
@@ -341,7 +343,7 @@ impl PredecodeBlock {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum MacroOpKind {
     None,
     Alu(AluOp),
@@ -353,7 +355,7 @@ pub enum MacroOpKind {
     Illegal,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum AluOp { None, Add, Sub, Sll, Slt, Sltu, Xor, Srl, Sra, Or, And }
 impl std::fmt::Display for AluOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -405,7 +407,7 @@ impl AluOp {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum BrnOp { None, Eq, Ne, Lt, Ge, Ltu, Geu }
 impl std::fmt::Display for BrnOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -438,7 +440,7 @@ impl BrnOp {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum JmpOp {
     JmpRelative,
     JmpIndirect,
@@ -447,8 +449,13 @@ pub enum JmpOp {
     Return,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum SysOp { None, Ecall(u32), Ebreak(u32) }
+/// NOTE: In hardware, only 'funct3' from the encoding is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
+pub enum SysOp { 
+    None, 
+    Ecall(UInt<3>), 
+    Ebreak(UInt<3>),
+}
 impl std::fmt::Display for SysOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         let name = match self {
@@ -462,27 +469,27 @@ impl std::fmt::Display for SysOp {
 
 
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum Operand { None, Zero, Reg, Imm, Pc, }
 
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum MovCtl { None, Op1, Op2, Zero }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum PhysRegSrc {
     None,
-    Local(usize),
-    Global(usize),
+    Local(PhysReg),
+    Global(PhysReg),
 }
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, HwBits)]
 pub enum PhysRegDst {
     None,
-    Allocated(usize),
+    Allocated(PhysReg),
 }
 
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, HwBits)]
 pub struct MacroOp {
     pub enc: u32,
     pub kind: MacroOpKind,
@@ -584,12 +591,12 @@ impl Default for MacroOp {
             mov: MovCtl::None,
             rr: false,
             kind: MacroOpKind::None,
-            rd: ArchReg(0),
+            rd: ArchReg::ZERO,
             pd: PhysRegDst::None,
             ps1: PhysRegSrc::None,
             ps2: PhysRegSrc::None,
-            rs1: ArchReg(0),
-            rs2: ArchReg(0),
+            rs1: ArchReg::ZERO,
+            rs2: ArchReg::ZERO,
             op1: Operand::None,
             op2: Operand::None,
             imm: ImmediateInfo::default(),
@@ -611,26 +618,26 @@ impl MacroOp {
 
     /// This op has a valid register result 
     pub fn has_rr(&self) -> bool { 
-        self.rr && self.rd != ArchReg(0)
+        self.rr && self.rd != ArchReg::ZERO
     }
     /// This op allocates a new physical register
     pub fn has_rr_alc(&self) -> bool {
         self.has_rr() && self.mov == MovCtl::None
     }
 
-    pub fn get_pd(&self) -> Option<usize> {
+    pub fn get_pd(&self) -> Option<PhysReg> {
         match self.pd {
             PhysRegDst::None => None,
             PhysRegDst::Allocated(prn) => Some(prn),
         }
     }
-    pub fn get_ps1(&self) -> Option<usize> {
+    pub fn get_ps1(&self) -> Option<PhysReg> {
         match self.ps1 {
             PhysRegSrc::None => None,
             PhysRegSrc::Local(prn) | PhysRegSrc::Global(prn) => Some(prn),
         }
     }
-    pub fn get_ps2(&self) -> Option<usize> {
+    pub fn get_ps2(&self) -> Option<PhysReg> {
         match self.ps2 {
             PhysRegSrc::None => None,
             PhysRegSrc::Local(prn) | PhysRegSrc::Global(prn) => Some(prn),
@@ -640,18 +647,18 @@ impl MacroOp {
 }
 
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, HwBits)]
 pub struct DecodeBlock {
-    pub start: usize,
+    pub start: UInt<3>,
     pub exit: DecodeBlockExit,
-    pub addr: usize,
+    pub addr: UInt<32>,
     pub data: [MacroOp; 8],
 }
 impl DecodeBlock {
     pub fn print(&self) {
         for idx in 0..8 {
-            let pc = self.start.wrapping_add(idx << 2);
-            if idx < self.start || idx > self.exit.to_idx() {
+            let pc = self.start_idx().wrapping_add(idx << 2);
+            if idx < self.start_idx() || idx > self.exit.to_idx() {
                 println!("{:08x}: X {}", pc, Rv32::disas(self.data[idx].enc));
             } else {
                 println!("{:08x}:   {}", pc, Rv32::disas(self.data[idx].enc));
@@ -660,24 +667,25 @@ impl DecodeBlock {
     }
 
     pub fn num_preg_allocs(&self) -> usize {
-        self.data.iter().skip(self.start).take(self.exit_idx()+1)
+        self.data.iter().skip(self.start_idx()).take(self.exit_idx()+1)
             .filter(|mop| mop.has_rr_alc())
             .count()
     }
 
+    pub fn start_idx(&self) -> usize { self.start.as_usize() }
     pub fn exit_idx(&self) -> usize { self.exit.to_idx() }
 
     pub fn iter_seq(&self) 
         -> impl Iterator<Item=(usize, &MacroOp)> 
     {
-        self.data.iter().enumerate().skip(self.start)
+        self.data.iter().enumerate().skip(self.start_idx())
             .take_while(|(idx, _)| *idx <= self.exit_idx())
     }
 
     pub fn iter_seq_mut(&mut self) 
         -> impl Iterator<Item=(usize, &mut MacroOp)> 
     {
-        self.data.iter_mut().enumerate().skip(self.start)
+        self.data.iter_mut().enumerate().skip(self.start.as_usize())
             .take_while(|(idx, mop)| idx <= &mut self.exit.to_idx())
     }
 
@@ -692,10 +700,10 @@ impl DecodeBlock {
     /// Given the index of a mop, return the index of *most-recent* previous
     /// provider for a particular architectural register. 
     pub fn find_provider(&self, sink_idx: usize, arn: ArchReg) -> Option<usize> {
-        assert!(sink_idx >= self.start);
+        assert!(sink_idx >= self.start_idx());
         assert!(sink_idx <= self.exit_idx());
 
-        for pidx in (self.start..sink_idx).rev() {
+        for pidx in (self.start_idx()..sink_idx).rev() {
             let provider = self.data[pidx];
             if provider.has_rr() && provider.rd == arn {
                 return Some(pidx);
@@ -709,7 +717,7 @@ impl DecodeBlock {
     pub fn calc_local_deps(&self) -> Vec<(usize, Option<usize>, Option<usize>)> {
         let mut res = Vec::new();
 
-        for sidx in self.start+1..=self.exit_idx() {
+        for sidx in self.start_idx()+1..=self.exit_idx() {
             let sink = self.data[sidx];
             // Skip ops without any source register operands
             if sink.op1 != Operand::Reg && sink.op2 != Operand::Reg {
@@ -741,7 +749,7 @@ impl DecodeBlock {
     pub fn rewrite_dyn_zero_operands(&mut self, zeroes: [bool; 32]) -> usize {
         let mut num_rewritten = 0;
 
-        for idx in self.start..=self.exit_idx() {
+        for idx in self.start_idx()..=self.exit_idx() {
             let op = self.data[idx];
 
             if op.op1 == Operand::Reg {
@@ -811,7 +819,7 @@ impl DecodeBlock {
 
     pub fn rewrite_mov_ops(&mut self) -> usize {
         let mut num_rewritten = 0;
-        for idx in self.start..=self.exit_idx() {
+        for idx in self.start_idx()..=self.exit_idx() {
             let mop = self.data[idx];
             if mop.mov != MovCtl::None {
                 continue;
@@ -898,72 +906,37 @@ pub enum MicroOpKind {
 }
 
 
-pub struct Freelist<const SZ: usize> {
-    wp_allocated: Option<Vec<usize>>,
-    arr: [bool; SZ],
-}
-impl <const SZ: usize> Freelist<SZ> {
-    pub fn new() -> Self {
-        let mut res = Self {
-            wp_allocated: None,
-            arr: [true; SZ],
-        };
-        res.arr[0] = false;
-        res
-    }
-    pub fn num_free(&self) -> usize {
-        self.arr.iter().filter(|s| **s == true).count()
-    }
-    pub fn sample_alcs(&self, n: usize) -> Option<Vec<usize>> {
-        if n > self.num_free() {
-            return None;
-        }
-        let res: Vec<usize> = self.arr.iter()
-            .enumerate()
-            .filter(|(prn,s)| **s == true)
-            .map(|(idx, s)| idx)
-            .take(n).collect();
-        Some(res)
-    }
+/// Width of a physical register index (for 256 physical registers).
+pub const PREG_BITS: usize = index_bits(256) as usize;
 
-    pub fn drive_allocated(&mut self, alcs: Vec<usize>) {
-        self.wp_allocated = Some(alcs);
-    }
-}
-impl <const SZ: usize> Clocked for Freelist<SZ> {
-    fn update(&mut self) {
-        if let Some(alcs) = self.wp_allocated.take() {
-            for prn in alcs {
-                assert!(self.arr[prn] == true);
-                self.arr[prn] = false;
-            }
-        }
-    }
-}
+/// A physical register index.
+pub type PhysReg = UInt<PREG_BITS>;
+
+/// Physical register freelist, allocating up to one register for each
+/// micro-op in a [DecodeBlock] on each cycle.
+///
+/// NOTE: Physical register 0 must be marked as allocated with 
+/// [BitAllocator::with_allocated]. 
+pub type Freelist<const SZ: usize> = BitAllocator<SZ, 8, 8>;
 
 
 pub struct RegisterMap {
-    wp_pending: Vec<(ArchReg, usize)>,
-    data: [usize; 32],
+    wp_pending: Vec<(ArchReg, PhysReg)>,
+    data: [PhysReg; 32],
     zero: [bool; 32],
 }
 impl RegisterMap {
     pub fn new() -> Self { 
         let mut zero = [false; 32];
         zero[0] = true;
-        let mut data = [ 
-             0,  1,  2,  3,  4,  5,  6,  7,
-             8,  9, 10, 11, 12, 13, 14, 15, 
-            16, 17, 18, 19, 20, 21, 22, 23, 
-            24, 25, 26, 27, 28, 29, 30, 31,
-        ];
+        let mut data = std::array::from_fn(|idx| PhysReg::new(idx as u64));
         Self { 
             zero, 
             data,
             wp_pending: Vec::new(),
         }
     }
-    pub fn sample_rp(&self, arn: ArchReg) -> (usize, bool) {
+    pub fn sample_rp(&self, arn: ArchReg) -> (PhysReg, bool) {
         let idx = arn.as_usize();
         (self.data[idx], self.zero[idx])
     }
@@ -971,8 +944,8 @@ impl RegisterMap {
         self.zero
     }
 
-    pub fn drive_wp(&mut self, arn: ArchReg, prn: usize) {
-        assert!(arn != ArchReg(0));
+    pub fn drive_wp(&mut self, arn: ArchReg, prn: PhysReg) {
+        assert!(arn != ArchReg::ZERO);
         self.wp_pending.push((arn, prn));
     }
     pub fn print(&self) {
@@ -990,11 +963,18 @@ impl RegisterMap {
 
 }
 
+/// NOTE: The number of ports depends on the width of rename.
+impl Cost for RegisterMap {
+    fn cost(&self, path: &str, r: &mut CostReport) {
+        // A physical register, and a bit for mappings to zero
+        r.add(path, StorageCost::new("RegisterMap", 32, PhysReg::BITS + 1));
+    }
+}
 impl Clocked for RegisterMap {
     fn update(&mut self) {
         while let Some((arn, prn)) = self.wp_pending.pop() {
             self.data[arn.as_usize()] = prn;
-            self.zero[arn.as_usize()] = prn == 0;
+            self.zero[arn.as_usize()] = prn == PhysReg::ZERO;
         }
     }
 }
@@ -1,4 +1,6 @@
 pub mod state;
+pub mod prim;
 pub use state::*;
+pub use prim::*;
 
 
@@ -1,6 +1,6 @@
 
 
-use crate::state::Clocked;
+use crate::sim::state::Clocked;
 
 /// Common interface for microarchitectural storage elements which have
 /// some kind of underlying notion of "a capacity," and where storage
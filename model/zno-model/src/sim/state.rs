@@ -44,10 +44,10 @@ use std::cell::*;
 use std::rc::*;
 
 /// Interface to clocked components. 
-pub trait Clocked { 
-    /// Simulate a clock edge, mutating some internal state. 
-    fn update(&mut self);
-}
+///
+/// NOTE: This is the same trait used in the `sim` crate, so that components
+/// from `sim::lle` can be used alongside the ones defined here.
+pub use ::sim::lle::Clocked;
 
 /// A shared mutable reference to some clocked component.
 pub type StateRef<T> = Rc<RefCell<T>>;
@@ -11,9 +11,14 @@ use sim::hle::mem::*;
 use sim::hle::riscv::*;
 
 use sim::lle::register::*;
+use sim::lle::pipereg::*;
 use sim::lle::mem::*;
+use sim::lle::bits::*;
 use sim::lle::*;
 
+/// Physical register indices (the same as in zno-model).
+pub use zno_model::core::uarch::{PhysReg, PREG_BITS};
+
 pub mod rename;
 use rename::*;
 
@@ -254,9 +259,6 @@ impl DecodeBlock {
 }
 
 
-#[derive(Clone, Copy, Debug, Default)]
-pub struct PhysReg(pub usize);
-
 #[derive(Clone, Copy, Debug)]
 pub enum UopStorage {
     Imm(usize),
@@ -288,11 +290,28 @@ impl Uop {
 }
 
 
-const MAP_INIT: &[usize; 32] = &[
-    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
-    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31
-];
-type RegisterMap = Mem<usize, 32>;
+type RegisterMap = Mem<PhysReg, 32>;
+
+/// State elements in the frontend pipeline.
+#[derive(Clocked)]
+struct Frontend {
+    /// Control-flow event
+    r_cfe: Reg<Option<ControlFlowEvent>>,
+    /// Fetch target address
+    r_fpc: PipeReg<ProgramCounter>,
+    /// Fetch block
+    r_fblk: PipeReg<FetchBlock>,
+    /// Predecode block
+    r_pdblk: PipeReg<PredecodeBlock>,
+    /// Decode block
+    r_dblk: PipeReg<DecodeBlock>,
+    /// Rename block
+    r_rblk: PipeReg<RenameBlock>,
+    /// Register map
+    r_map: RegisterMap,
+    /// Freelist
+    r_frl: Freelist<256>,
+}
 
 fn main() {
     const RAM_SIZE: usize = 0x0200_0000;
@@ -300,26 +319,20 @@ fn main() {
     let entry   = read_prog(&mut ram, "programs/test.elf");
 
 
-    // Control-flow event
-    let mut r_cfe = Reg::<Option<ControlFlowEvent>>::new(
-        Some(ControlFlowEvent::ResetVector(ProgramCounter::new(entry)))
-    );
-
-    // Fetch target address
-    let mut r_fpc = Reg::<Option<ProgramCounter>>::new(None);
-    // Fetch block
-    let mut r_fblk = Reg::<Option<FetchBlock>>::new(None);
-    // Predecode block
-    let mut r_pdblk = Reg::<Option<PredecodeBlock>>::new(None);
-    // Decode block
-    let mut r_dblk = Reg::<Option<DecodeBlock>>::new(None);
-    // Rename block
-    let mut r_rblk = Reg::<Option<RenameBlock>>::new(None);
-    // Register map
-    let mut r_map = Mem::<usize, 32>::new_init_array(&MAP_INIT);
-    // Freelist
-    let mut r_frl = Reg::<Freelist<256>>::new(Freelist::default());
-
+    let mut fe = Frontend {
+        r_cfe: Reg::new(Some(
+            ControlFlowEvent::ResetVector(ProgramCounter::new(entry))
+        )),
+        r_fpc: PipeReg::new(),
+        r_fblk: PipeReg::new(),
+        r_pdblk: PipeReg::new(),
+        r_dblk: PipeReg::new(),
+        r_rblk: PipeReg::new(),
+        r_map: Mem::new_init_array(
+            &std::array::from_fn(|idx| PhysReg::new(idx as u64))
+        ),
+        r_frl: Freelist::new().with_allocated(0),
+    };
 
     for cyc in 0..24 {
         println!("================ cycle {} ==================", cyc);
@@ -330,34 +343,35 @@ fn main() {
 
 
         // Control-flow control
-        if let Some(cfe) = r_cfe.sample() {
+        if let Some(cfe) = fe.r_cfe.sample() {
             let pc = cfe.get_pc();
             println!("Control flow event @ {:08x}, {:08x?}", pc.value(), cfe);
-            r_fpc.drive(Some(pc));
+            fe.r_fpc.drive(pc);
 
             // Generate the next event. 
             // NOTE: This is only relevant if later stages do not drive 
             // 'r_cfe' (in which case, those values will take precedence). 
             let mut npc = ProgramCounter::new(pc.fetch_addr() + 0x20);
-            r_cfe.drive(Some(ControlFlowEvent::Sequential(npc)));
+            fe.r_cfe.drive(Some(ControlFlowEvent::Sequential(npc)));
         } else {
+            // NOTE: 'r_fpc' becomes a bubble (the previous fetch pc isn't
+            // fetched again).
             println!("No valid CFE for this cycle");
         }
 
         // Fetch Unit
-        if let Some(fpc) = r_fpc.sample() {
+        if let Some(fpc) = fe.r_fpc.sample() {
             println!("Fetching block @ {:08x}", fpc.fetch_addr());
             let mut tmp = [0u8; 32];
             ram.read_bytes(fpc.fetch_addr(), &mut tmp);
             let mut fblk = FetchBlock::from_bytes(fpc, tmp);
-            r_fblk.drive(Some(fblk));
+            fe.r_fblk.drive(fblk);
         } else {
             println!("No valid fetch pc to fetch this cycle");
-            r_fblk.drive(None);
         }
 
         // Predecode Unit
-        if let Some(fblk) = r_fblk.sample() {
+        if let Some(fblk) = fe.r_fblk.sample() {
             println!("Predecoding block @ {:08x}", &fblk.pc.value());
             let mut pdblk = PredecodeBlock::from_fetch_block(&fblk);
 
@@ -384,12 +398,12 @@ fn main() {
                 if let Some(tgt) = static_tgt { 
                     println!("Discovered branch {:08x}: {:?}, idx={}, tgt={:08x?}", pc, brn, idx, tgt);
                     let npc = ProgramCounter::new(tgt as usize);
-                    r_cfe.drive(Some(ControlFlowEvent::Static(brn, npc)));
+                    fe.r_cfe.drive(Some(ControlFlowEvent::Static(brn, npc)));
 
                     // Flush incorrect spec. from the pipeline
                     redirect_from_predecode = true;
-                    r_fblk.drive(None);
-                    r_fpc.drive(None);
+                    fe.r_fblk.flush();
+                    fe.r_fpc.flush();
                 }
 
                 // Do not decode speculatively past the branch
@@ -399,14 +413,17 @@ fn main() {
 
             }
 
-            r_pdblk.drive(Some(pdblk));
+            fe.r_pdblk.drive(pdblk);
         } else {
             println!("No valid fetch block to predecode this cycle");
-            r_pdblk.drive(None);
         }
 
         // Decode Unit
-        if let Some(pdblk) = r_pdblk.sample() {
+        //
+        // NOTE: 'r_dblk' is a pipeline register, so an ignored (or missing)
+        // predecode block leaves a bubble. The same decode block is *not*
+        // renamed again on the next cycle.
+        if let Some(pdblk) = fe.r_pdblk.sample() {
             if redirect_from_predecode {
                 println!("Decoder ignoring block @ {:08x}", &pdblk.pc.value());
             } 
@@ -416,7 +433,7 @@ fn main() {
                 let mut dblk = DecodeBlock::from_predecode_block(&pdblk);
                 dblk.print();
 
-                r_dblk.drive(Some(dblk));
+                fe.r_dblk.drive(dblk);
             }
         } else {
             println!("No valid predecode block to decode this cycle");
@@ -424,33 +441,29 @@ fn main() {
 
 
         // Rename Unit
-        if let Some(dblk) = r_dblk.sample() {
+        if let Some(dblk) = fe.r_dblk.sample() {
             println!("Renaming block @ {:08x}", &dblk.pc.value());
 
-            let mut frl = r_frl.sample();
             let mut window = RenameWindowInfo::from_decode_block(&dblk);
-            window.resolve_dependencies(&r_map);
-            window.allocate(&mut frl, &mut r_map).unwrap();
+            window.resolve_dependencies(&fe.r_map);
+            window.allocate(&mut fe.r_frl, &mut fe.r_map).unwrap();
             window.forward_allocs();
 
 
             window.print();
 
-            r_frl.drive(frl);
-            //r_rblk.drive(Some(rblk));
+            //fe.r_rblk.drive(rblk);
         } else {
             println!("No valid edecode block to rename this cycle");
         }
 
 
-        r_fpc.update();
-        r_cfe.update();
-        r_fblk.update();
-        r_pdblk.update();
-        r_dblk.update();
-        r_frl.update();
-        r_map.update();
+        fe.update();
     }
 
+    println!("r_fpc:   {}", fe.r_fpc.stats());
+    println!("r_fblk:  {}", fe.r_fblk.stats());
+    println!("r_pdblk: {}", fe.r_pdblk.stats());
+    println!("r_dblk:  {}", fe.r_dblk.stats());
 
 }
@@ -1,38 +1,12 @@
 
 use sim::hle::riscv::*;
+use sim::lle::alloc::*;
 
 use crate::*;
 
-#[derive(Copy, Clone)]
-pub struct Freelist<const SZ: usize> { 
-    data: [bool; SZ],
-}
-impl <const SZ: usize> Default for Freelist<SZ> {
-    fn default() -> Self { 
-        let mut res = Self { data: [true; SZ] };
-        res.data[0] = false;
-        res
-    }
-}
-impl <const SZ: usize> Freelist<SZ> {
-    pub fn num_free(&self) -> usize {
-        self.data.iter().filter(|s| **s == true).count()
-    }
-    pub fn peek_next_alc(&self) -> Option<usize> { 
-        self.data.iter().enumerate()
-            .find(|(idx, val)| **val)
-            .map(|(idx, val)| idx)
-    }
-    pub fn take_next_alc(&mut self) -> Option<usize> { 
-        if let Some(idx) = self.peek_next_alc() {
-            assert!(idx != 0);
-            self.data[idx] = false;
-            return Some(idx);
-        }
-        None
-    }
-
-}
+/// Physical register freelist (with one allocation for each instruction 
+/// in a rename window on each cycle).
+pub type Freelist<const SZ: usize> = BitAllocator<SZ, 8, 8>;
 
 
 #[derive(Copy, Clone)]
@@ -44,9 +18,9 @@ pub struct DepInfo {
     rs1_dep: Option<usize>,
     rs2_dep: Option<usize>,
 
-    pd: Option<usize>,
-    ps1: Option<usize>,
-    ps2: Option<usize>,
+    pd: Option<PhysReg>,
+    ps1: Option<PhysReg>,
+    ps2: Option<PhysReg>,
 }
 
 pub struct RenameWindowInfo {
@@ -59,9 +33,9 @@ pub struct RenameWindowInfo {
     rs1_dep: [Option<usize>; 8],
     rs2_dep: [Option<usize>; 8],
 
-    pd_arr: [Option<usize>; 8],
-    ps1_arr: [Option<usize>; 8],
-    ps2_arr: [Option<usize>; 8],
+    pd_arr: [Option<PhysReg>; 8],
+    ps1_arr: [Option<PhysReg>; 8],
+    ps2_arr: [Option<PhysReg>; 8],
 
 }
 impl RenameWindowInfo {
@@ -119,13 +93,13 @@ impl RenameWindowInfo {
 
 
         let pd = self.pd_arr.map(|v| 
-            if let Some(pd) = v { format!("{:>3}", pd) } else { "XXX".to_string() }
+            if let Some(pd) = v { format!("{:>3}", pd.as_usize()) } else { "XXX".to_string() }
         ).join("|");
         let ps1 = self.ps1_arr.map(|v| 
-            if let Some(ps1) = v { format!("{:>3}", ps1) } else { "XXX".to_string() }
+            if let Some(ps1) = v { format!("{:>3}", ps1.as_usize()) } else { "XXX".to_string() }
         ).join("|");
         let ps2 = self.ps2_arr.map(|v| 
-            if let Some(ps2) = v { format!("{:>3}", ps2) } else { "XXX".to_string() }
+            if let Some(ps2) = v { format!("{:>3}", ps2.as_usize()) } else { "XXX".to_string() }
         ).join("|");
 
 
@@ -210,9 +184,11 @@ impl RenameWindowInfo {
             return Err(());
         }
 
+        let mut alcs = frl.sample_alcs().into_iter().flatten();
+        frl.drive_alc(req_alcs);
         for idx in 0..8 { 
             if let Some(rd) = self.rd_arr[idx] {
-                let pd = frl.take_next_alc().unwrap();
+                let pd = PhysReg::new(alcs.next().unwrap() as u64);
                 self.pd_arr[idx] = Some(pd);
 
                 map.drive(rd.as_usize(), pd);